@@ -1,12 +1,13 @@
 /// Cache operation commands: manage render cache and temporary files
-use crate::log_debug;
+use crate::{log_debug, log_warn};
 use crate::utils;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
 use std::time::{Duration, SystemTime};
-use tauri::AppHandle;
+use tauri::{AppHandle, Emitter};
 
 #[derive(Debug, Serialize)]
 pub struct CacheStats {
@@ -16,24 +17,91 @@ pub struct CacheStats {
     pub cache_misses: usize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 pub struct CleanupResponse {
     pub files_removed: usize,
     pub total_space_freed: u64,
 }
 
+impl CleanupResponse {
+    /// Fold another cleanup report into this one.
+    fn absorb(&mut self, other: CleanupResponse) {
+        self.files_removed += other.files_removed;
+        self.total_space_freed += other.total_space_freed;
+    }
+}
+
+/// A cached render the size-capped LRU policy would evict.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CacheEvictionCandidate {
+    pub name: String,
+    pub size_bytes: u64,
+    pub age_secs: u64,
+}
+
+/// Criteria for each stage of `compact_build_dir`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CompactOptions {
+    /// Number of most recently used `cached_*` renders to keep
+    pub keep_cached_renders: usize,
+    /// Number of newest `temp_*.pdf` files to keep
+    pub keep_temp_pdfs: usize,
+    /// Temp PDFs older than this are removed regardless of count
+    pub temp_pdf_max_age_secs: u64,
+    /// Typst query dumps (`.typst_query_*`) older than this are removed
+    pub query_dump_max_age_secs: u64,
+    /// Also remove assets that no markdown file references
+    pub remove_unused_assets: bool,
+}
+
+impl Default for CompactOptions {
+    fn default() -> Self {
+        Self {
+            keep_cached_renders: 20,
+            keep_temp_pdfs: 10,
+            temp_pdf_max_age_secs: 30 * 60,
+            query_dump_max_age_secs: 24 * 60 * 60,
+            remove_unused_assets: false,
+        }
+    }
+}
+
+/// Maximum directory depth walked when scanning markdown for asset references.
+/// Deeper directories make the scan incomplete, so nothing is deleted.
+const MAX_ASSET_SCAN_DEPTH: usize = 8;
+
+/// Run filesystem-heavy work on the blocking thread pool.
+///
+/// Stat calls on network shares (SMB/NFS) can take seconds; running them inline
+/// would tie up the async runtime and freeze the UI.
+pub(crate) async fn run_blocking<T, F>(work: F) -> Result<T, String>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(work)
+        .await
+        .map_err(|e| format!("Background task failed: {}", e))
+}
+
 /// Get render cache statistics
 #[tauri::command]
 pub async fn get_cache_stats(app_handle: AppHandle) -> Result<CacheStats, String> {
     let content_dir = utils::get_content_dir(&app_handle)
         .map_err(|e| format!("Failed to get content directory: {}", e))?;
     let build_dir = content_dir.join(".build");
-    
+
+    run_blocking(move || cache_stats_in(&build_dir)).await
+}
+
+/// Count and size the `cached_*` renders in `build_dir`.
+fn cache_stats_in(build_dir: &Path) -> CacheStats {
     let mut cached_documents = 0;
     let mut cache_size_mb = 0.0;
     
     if build_dir.exists() {
-        if let Ok(entries) = fs::read_dir(&build_dir) {
+        if let Ok(entries) = fs::read_dir(build_dir) {
             for entry in entries.flatten() {
                 let path = entry.path();
                 if path.file_name()
@@ -49,12 +117,12 @@ pub async fn get_cache_stats(app_handle: AppHandle) -> Result<CacheStats, String
         }
     }
     
-    Ok(CacheStats {
+    CacheStats {
         cached_documents,
         cache_size_mb,
         cache_hits: 0, // Basic cache - no hit/miss tracking for now
         cache_misses: 0,
-    })
+    }
 }
 
 /// Clear render cache
@@ -82,6 +150,76 @@ pub async fn clear_render_cache(app_handle: AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+/// List the `cached_*` files that evicting down to `max_mb` would remove, without deleting anything.
+#[tauri::command]
+pub async fn preview_cache_eviction(app_handle: AppHandle, max_mb: f64) -> Result<Vec<CacheEvictionCandidate>, String> {
+    let content_dir = utils::get_content_dir(&app_handle).map_err(|e| e.to_string())?;
+    let build_dir = content_dir.join(".build");
+    run_blocking(move || cache_eviction_candidates(&build_dir, mb_to_bytes(max_mb))).await
+}
+
+/// Evict least recently used `cached_*` files until the render cache fits in `max_mb`.
+#[tauri::command]
+pub async fn evict_render_cache(app_handle: AppHandle, max_mb: f64) -> Result<CleanupResponse, String> {
+    let content_dir = utils::get_content_dir(&app_handle).map_err(|e| e.to_string())?;
+    let build_dir = content_dir.join(".build");
+    run_blocking(move || evict_render_cache_in(&build_dir, mb_to_bytes(max_mb))).await
+}
+
+fn mb_to_bytes(mb: f64) -> u64 {
+    (mb.max(0.0) * 1024.0 * 1024.0) as u64
+}
+
+/// Oldest-first list of `cached_*` files to drop so the remaining total is at most `max_bytes`.
+fn cache_eviction_candidates(build_dir: &Path, max_bytes: u64) -> Vec<CacheEvictionCandidate> {
+    let now = SystemTime::now();
+    let mut cached = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(build_dir) {
+        for entry in entries.flatten() {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue };
+            if !name.starts_with("cached_") {
+                continue;
+            }
+            if let Ok(metadata) = entry.metadata() {
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                cached.push((name, metadata.len(), modified));
+            }
+        }
+    }
+
+    // Least recently used first; ties broken by name so the order is stable
+    cached.sort_by(|a, b| a.2.cmp(&b.2).then_with(|| a.0.cmp(&b.0)));
+
+    let mut total: u64 = cached.iter().map(|(_, size, _)| size).sum();
+    let mut candidates = Vec::new();
+    for (name, size_bytes, modified) in cached {
+        if total <= max_bytes {
+            break;
+        }
+        total -= size_bytes;
+        candidates.push(CacheEvictionCandidate {
+            name,
+            size_bytes,
+            age_secs: now.duration_since(modified).unwrap_or_default().as_secs(),
+        });
+    }
+    candidates
+}
+
+/// Delete the files [`cache_eviction_candidates`] selects.
+fn evict_render_cache_in(build_dir: &Path, max_bytes: u64) -> CleanupResponse {
+    let mut report = CleanupResponse::default();
+    for candidate in cache_eviction_candidates(build_dir, max_bytes) {
+        if fs::remove_file(build_dir.join(&candidate.name)).is_ok() {
+            report.files_removed += 1;
+            report.total_space_freed += candidate.size_bytes;
+        }
+    }
+    log_debug!("cache", "Evicted {} cached renders ({} bytes)", report.files_removed, report.total_space_freed);
+    report
+}
+
 /// Cleanup temporary PDF files based on age and count
 #[tauri::command]
 pub async fn cleanup_temp_pdfs(app_handle: AppHandle, keep_last_n: Option<usize>) -> Result<CleanupResponse, String> {
@@ -90,18 +228,20 @@ pub async fn cleanup_temp_pdfs(app_handle: AppHandle, keep_last_n: Option<usize>
     
     let content_dir = utils::get_content_dir(&app_handle).map_err(|e| e.to_string())?;
     let build_dir = content_dir.join(".build");
-    
+
+    run_blocking(move || cleanup_temp_pdfs_in(&build_dir, keep_count, max_age)).await
+}
+
+/// Remove `temp_*.pdf` files from `build_dir` beyond the newest `keep_count` or older than `max_age`.
+fn cleanup_temp_pdfs_in(build_dir: &Path, keep_count: usize, max_age: Duration) -> CleanupResponse {
     if !build_dir.exists() {
-        return Ok(CleanupResponse {
-            files_removed: 0,
-            total_space_freed: 0,
-        });
+        return CleanupResponse::default();
     }
     
     let mut temp_pdfs = Vec::new();
     
     // Find all temp_*.pdf files
-    if let Ok(entries) = fs::read_dir(&build_dir) {
+    if let Ok(entries) = fs::read_dir(build_dir) {
         for entry in entries.flatten() {
             let path = entry.path();
             if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
@@ -142,30 +282,73 @@ pub async fn cleanup_temp_pdfs(app_handle: AppHandle, keep_last_n: Option<usize>
         }
     }
     
-    Ok(CleanupResponse {
+    CleanupResponse {
         files_removed,
         total_space_freed,
-    })
+    }
 }
 
+/// Progress of a long-running cleanup, emitted as `cleanup-progress` events.
+#[derive(Debug, Clone, Serialize)]
+pub struct CleanupProgress {
+    /// `"scan"` while reading markdown files, `"delete"` while checking assets
+    pub phase: &'static str,
+    pub files_scanned: usize,
+    pub files_total: usize,
+}
+
+/// Emit progress every this many files (the last file of a phase always reports).
+const PROGRESS_INTERVAL: usize = 25;
+
 /// Cleanup unused assets (images) that are not referenced in any markdown file.
 /// Scans all .md files in the content directory and removes orphaned assets.
+/// Emits `cleanup-progress` events while scanning and deleting.
 #[tauri::command]
 pub async fn cleanup_unused_assets(app_handle: AppHandle) -> Result<CleanupResponse, String> {
     let content_dir = utils::get_content_dir(&app_handle).map_err(|e| e.to_string())?;
     let assets_dir = utils::get_assets_dir(&app_handle).map_err(|e| e.to_string())?;
 
+    run_blocking(move || {
+        cleanup_unused_assets_with_progress(&content_dir, &assets_dir, |progress| {
+            app_handle.emit("cleanup-progress", progress).ok();
+        })
+    })
+    .await
+}
+
+/// Remove files in `assets_dir` that no markdown file under `content_dir` references.
+fn cleanup_unused_assets_in(content_dir: &Path, assets_dir: &Path) -> CleanupResponse {
+    cleanup_unused_assets_with_progress(content_dir, assets_dir, |_| {})
+}
+
+/// [`cleanup_unused_assets_in`], reporting progress through `on_progress`.
+///
+/// Files are counted up front so every report carries the phase total. If the
+/// markdown scan cannot cover the whole content directory (unreadable or too deeply
+/// nested directories), an asset could look unused while still referenced, so the
+/// delete phase is skipped.
+fn cleanup_unused_assets_with_progress(
+    content_dir: &Path,
+    assets_dir: &Path,
+    mut on_progress: impl FnMut(CleanupProgress),
+) -> CleanupResponse {
     if !assets_dir.exists() {
-        return Ok(CleanupResponse {
-            files_removed: 0,
-            total_space_freed: 0,
-        });
+        return CleanupResponse::default();
     }
 
-    // Step 1: Find all referenced assets by scanning markdown files
-    let mut referenced_assets = HashSet::new();
+    let mut report_progress = |phase: &'static str, files_scanned: usize, files_total: usize| {
+        if files_scanned % PROGRESS_INTERVAL == 0 || files_scanned == files_total {
+            on_progress(CleanupProgress {
+                phase,
+                files_scanned,
+                files_total,
+            });
+        }
+    };
 
-    fn scan_directory_for_markdown(dir: &PathBuf, referenced: &mut HashSet<String>) -> std::io::Result<()> {
+    /// Returns `false` when a directory was left out for exceeding the depth limit.
+    fn collect_markdown_files(dir: &Path, depth: usize, files: &mut Vec<std::path::PathBuf>) -> std::io::Result<bool> {
+        let mut complete = true;
         for entry in fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
@@ -178,69 +361,155 @@ pub async fn cleanup_unused_assets(app_handle: AppHandle) -> Result<CleanupRespo
             }
 
             if path.is_dir() {
-                scan_directory_for_markdown(&path, referenced)?;
-            } else if path.extension().and_then(|e| e.to_str()) == Some("md") {
-                // Read markdown file and find asset references
-                if let Ok(content) = fs::read_to_string(&path) {
-                    // Look for assets/ references in markdown (both img tags and markdown syntax)
-                    // Pattern: assets/filename.ext or "assets/filename.ext"
-                    for line in content.lines() {
-                        if line.contains("assets/") {
-                            // Extract all "assets/..." patterns
-                            for word in line.split_whitespace() {
-                                if let Some(start) = word.find("assets/") {
-                                    let asset_path = &word[start..];
-                                    // Clean up quotes, parentheses, etc.
-                                    let cleaned = asset_path
-                                        .trim_end_matches(|c: char| !c.is_alphanumeric() && c != '.' && c != '-' && c != '_' && c != '/')
-                                        .trim_start_matches(|c: char| !c.is_alphanumeric() && c != '/')
-                                        .replace("assets/", "");
-                                    if !cleaned.is_empty() {
-                                        referenced.insert(cleaned);
-                                    }
-                                }
-                            }
-                        }
-                    }
+                if depth < MAX_ASSET_SCAN_DEPTH {
+                    complete &= collect_markdown_files(&path, depth + 1, files)?;
+                } else {
+                    complete = false;
                 }
+            } else if path.extension().and_then(|e| e.to_str()) == Some("md") {
+                files.push(path);
             }
         }
-        Ok(())
+        Ok(complete)
     }
 
-    let _ = scan_directory_for_markdown(&content_dir, &mut referenced_assets);
+    let prefix = asset_reference_prefix(assets_dir);
+
+    // Step 1: Find all referenced assets by scanning markdown files
+    let mut markdown_files = Vec::new();
+    let scan = collect_markdown_files(content_dir, 0, &mut markdown_files);
+    let mut referenced_assets = HashSet::new();
+    for (index, path) in markdown_files.iter().enumerate() {
+        if let Ok(content) = fs::read_to_string(path) {
+            scan_markdown_for_assets(&content, &prefix, &mut referenced_assets);
+        }
+        report_progress("scan", index + 1, markdown_files.len());
+    }
+    match scan {
+        Ok(true) => {}
+        Ok(false) => {
+            log_warn!(
+                "assets",
+                "Skipped asset cleanup: folders nested deeper than {} levels were not scanned",
+                MAX_ASSET_SCAN_DEPTH
+            );
+            return CleanupResponse::default();
+        }
+        Err(e) => {
+            log_warn!("assets", "Skipped asset cleanup: could not scan the content directory: {}", e);
+            return CleanupResponse::default();
+        }
+    }
 
     // Step 2: Find all actual assets in the assets directory
     let mut files_removed = 0;
     let mut total_space_freed = 0;
 
-    if let Ok(entries) = fs::read_dir(&assets_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-
-            if path.is_file() {
-                if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-                    // If this asset is not referenced in any markdown file, remove it
-                    if !referenced_assets.contains(filename) {
-                        if let Ok(metadata) = fs::metadata(&path) {
-                            total_space_freed += metadata.len();
-                        }
-                        if fs::remove_file(&path).is_ok() {
-                            files_removed += 1;
-                            log_debug!("assets", "Removed unused asset: {}", filename);
-                        }
-                    }
+    let assets: Vec<std::path::PathBuf> = fs::read_dir(assets_dir)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect())
+        .unwrap_or_default();
+    for (index, path) in assets.iter().enumerate() {
+        if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
+            // If this asset is not referenced in any markdown file, remove it
+            if !referenced_assets.contains(filename) {
+                if let Ok(metadata) = fs::metadata(path) {
+                    total_space_freed += metadata.len();
+                }
+                if fs::remove_file(path).is_ok() {
+                    files_removed += 1;
+                    log_debug!("assets", "Removed unused asset: {}", filename);
                 }
             }
         }
+        report_progress("delete", index + 1, assets.len());
     }
 
     log_debug!("assets", "Cleanup complete: {} files removed, {} bytes freed", files_removed, total_space_freed);
 
-    Ok(CleanupResponse {
+    CleanupResponse {
         files_removed,
         total_space_freed,
-    })
+    }
+}
+
+/// Collect asset paths (relative to the assets directory) referenced in `content`.
+fn scan_markdown_for_assets(content: &str, prefix: &str, referenced: &mut HashSet<String>) {
+    // Look for assets/ references in markdown (both img tags and markdown syntax)
+    // Pattern: assets/filename.ext or "assets/filename.ext"
+    for line in content.lines() {
+        // Windows users may write `assets\fig.png`
+        let line = line.replace('\\', "/");
+        if line.contains(prefix) {
+            // Extract all "assets/..." patterns
+            for word in line.split_whitespace() {
+                if let Some(start) = word.find(prefix) {
+                    let asset_path = &word[start..];
+                    // Clean up quotes, parentheses, etc.
+                    let cleaned = asset_path
+                        .trim_end_matches(|c: char| !c.is_alphanumeric() && c != '.' && c != '-' && c != '_' && c != '/')
+                        .trim_start_matches(|c: char| !c.is_alphanumeric() && c != '/')
+                        .replace(prefix, "");
+                    if !cleaned.is_empty() {
+                        referenced.insert(cleaned);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Prefix of asset references in markdown, e.g. `assets/`.
+///
+/// References use the assets directory's own name, which may be renamed (e.g. `media/`).
+fn asset_reference_prefix(assets_dir: &Path) -> String {
+    assets_dir
+        .file_name()
+        .map(|name| format!("{}/", name.to_string_lossy()))
+        .unwrap_or_else(|| format!("{}/", utils::DEFAULT_ASSETS_DIR_NAME))
+}
+
+/// One asset a document depends on, for verifying archived builds.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct AssetManifestEntry {
+    /// Content-relative path, e.g. `assets/figure.png`
+    pub path: String,
+    pub size: u64,
+    /// Lowercase hex SHA-256 of the file contents
+    pub sha256: String,
+}
+
+/// List every asset referenced by `markdown` with its size and SHA-256 hash.
+#[tauri::command]
+pub async fn build_asset_manifest(app_handle: AppHandle, markdown: String) -> Result<Vec<AssetManifestEntry>, String> {
+    let assets_dir = utils::get_assets_dir(&app_handle).map_err(|e| e.to_string())?;
+
+    run_blocking(move || asset_manifest_in(&markdown, &assets_dir)).await?
+}
+
+/// Build the asset manifest for `markdown` against an explicit assets directory.
+///
+/// Entries are sorted by path. References to missing files are skipped.
+fn asset_manifest_in(markdown: &str, assets_dir: &Path) -> Result<Vec<AssetManifestEntry>, String> {
+    let prefix = asset_reference_prefix(assets_dir);
+    let mut referenced = HashSet::new();
+    scan_markdown_for_assets(markdown, &prefix, &mut referenced);
+    let mut referenced: Vec<String> = referenced.into_iter().collect();
+    referenced.sort();
+
+    let mut manifest = Vec::new();
+    for relative in referenced {
+        let path = assets_dir.join(&relative);
+        if !path.is_file() {
+            continue;
+        }
+        let bytes = fs::read(&path).map_err(|e| format!("Failed to read asset {}: {}", relative, e))?;
+        manifest.push(AssetManifestEntry {
+            path: format!("{}{}", prefix, relative),
+            size: bytes.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&bytes)),
+        });
+    }
+    Ok(manifest)
 }
 
 /// Clear bibliography files from .build directory.
@@ -268,3 +537,311 @@ pub async fn clear_bibliography(app_handle: AppHandle) -> Result<(), String> {
 
     Ok(())
 }
+
+/// Run every build directory cleanup in one pass and return the combined report.
+#[tauri::command]
+pub async fn compact_build_dir(
+    app_handle: AppHandle,
+    options: Option<CompactOptions>,
+) -> Result<CleanupResponse, String> {
+    let content_dir = utils::get_content_dir(&app_handle).map_err(|e| e.to_string())?;
+    let assets_dir = utils::get_assets_dir(&app_handle).map_err(|e| e.to_string())?;
+    let options = options.unwrap_or_default();
+
+    run_blocking(move || compact_build_dir_in(&content_dir, &assets_dir, &options)).await
+}
+
+/// Compact `content_dir/.build` (and optionally `assets_dir`) according to `options`.
+fn compact_build_dir_in(content_dir: &Path, assets_dir: &Path, options: &CompactOptions) -> CleanupResponse {
+    let build_dir = content_dir.join(".build");
+    let mut report = CleanupResponse::default();
+
+    report.absorb(prune_render_cache_in(&build_dir, options.keep_cached_renders));
+    report.absorb(cleanup_temp_pdfs_in(
+        &build_dir,
+        options.keep_temp_pdfs,
+        Duration::from_secs(options.temp_pdf_max_age_secs),
+    ));
+    report.absorb(cleanup_query_dumps_in(
+        &build_dir,
+        Duration::from_secs(options.query_dump_max_age_secs),
+    ));
+    if options.remove_unused_assets {
+        report.absorb(cleanup_unused_assets_in(content_dir, assets_dir));
+    }
+
+    log_debug!(
+        "cache",
+        "Compacted build dir: {} files removed, {} bytes freed",
+        report.files_removed,
+        report.total_space_freed
+    );
+    report
+}
+
+/// Keep only the `keep` most recently used `cached_*` renders in `build_dir`.
+fn prune_render_cache_in(build_dir: &Path, keep: usize) -> CleanupResponse {
+    let mut cached = Vec::new();
+    if let Ok(entries) = fs::read_dir(build_dir) {
+        for entry in entries.flatten() {
+            let is_cached = entry.file_name().to_str().map(|name| name.starts_with("cached_")).unwrap_or(false);
+            if is_cached {
+                if let Ok(metadata) = entry.metadata() {
+                    cached.push((entry.path(), metadata));
+                }
+            }
+        }
+    }
+
+    // Newest first: access time is unreliable across platforms, so use modification time
+    cached.sort_by_key(|(_, metadata)| std::cmp::Reverse(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)));
+
+    let mut report = CleanupResponse::default();
+    for (path, metadata) in cached.iter().skip(keep) {
+        if fs::remove_file(path).is_ok() {
+            report.files_removed += 1;
+            report.total_space_freed += metadata.len();
+        }
+    }
+    report
+}
+
+/// Remove stale `.typst_query_*` source-map dumps left behind by the renderer.
+fn cleanup_query_dumps_in(build_dir: &Path, max_age: Duration) -> CleanupResponse {
+    let now = SystemTime::now();
+    let mut report = CleanupResponse::default();
+
+    if let Ok(entries) = fs::read_dir(build_dir) {
+        for entry in entries.flatten() {
+            let is_dump = entry.file_name().to_str().map(|name| name.starts_with(".typst_query_")).unwrap_or(false);
+            let Ok(metadata) = entry.metadata() else { continue };
+            if !is_dump || !metadata.is_file() {
+                continue;
+            }
+            let age = now
+                .duration_since(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH))
+                .unwrap_or_default();
+            if age >= max_age && fs::remove_file(entry.path()).is_ok() {
+                report.files_removed += 1;
+                report.total_space_freed += metadata.len();
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed_messy_content_dir() -> std::path::PathBuf {
+        let content_dir = std::env::temp_dir().join(format!("tideflow-compact-{}", uuid::Uuid::new_v4()));
+        let build_dir = content_dir.join(".build");
+        let assets_dir = content_dir.join("assets");
+        fs::create_dir_all(&build_dir).unwrap();
+        fs::create_dir_all(&assets_dir).unwrap();
+
+        for i in 0..4 {
+            fs::write(build_dir.join(format!("cached_{}.pdf", i)), vec![0u8; 100]).unwrap();
+            fs::write(build_dir.join(format!("temp_{}.pdf", i)), vec![0u8; 10]).unwrap();
+        }
+        fs::write(build_dir.join(".typst_query_output_heading.json"), "[]").unwrap();
+        fs::write(build_dir.join("content.md"), "# Keep me").unwrap();
+        fs::write(assets_dir.join("used.png"), [1u8; 7]).unwrap();
+        fs::write(assets_dir.join("orphan.png"), [1u8; 9]).unwrap();
+        fs::write(content_dir.join("doc.md"), "![x](assets/used.png)").unwrap();
+        content_dir
+    }
+
+    #[test]
+    fn test_compact_build_dir_totals_match_sub_cleanups() {
+        let options = CompactOptions {
+            keep_cached_renders: 1,
+            keep_temp_pdfs: 2,
+            query_dump_max_age_secs: 0,
+            remove_unused_assets: true,
+            ..Default::default()
+        };
+
+        let compacted = seed_messy_content_dir();
+        let report = compact_build_dir_in(&compacted, &compacted.join("assets"), &options);
+
+        let manual = seed_messy_content_dir();
+        let build_dir = manual.join(".build");
+        let parts = [
+            prune_render_cache_in(&build_dir, 1),
+            cleanup_temp_pdfs_in(&build_dir, 2, Duration::from_secs(options.temp_pdf_max_age_secs)),
+            cleanup_query_dumps_in(&build_dir, Duration::ZERO),
+            cleanup_unused_assets_in(&manual, &manual.join("assets")),
+        ];
+
+        assert_eq!(report.files_removed, parts.iter().map(|p| p.files_removed).sum::<usize>());
+        assert_eq!(report.total_space_freed, parts.iter().map(|p| p.total_space_freed).sum::<u64>());
+        assert_eq!(report.files_removed, 3 + 2 + 1 + 1);
+        assert!(compacted.join(".build").join("content.md").exists());
+        assert!(compacted.join("assets").join("used.png").exists());
+        assert!(!compacted.join("assets").join("orphan.png").exists());
+
+        let _ = fs::remove_dir_all(&compacted);
+        let _ = fs::remove_dir_all(&manual);
+    }
+
+    #[test]
+    fn test_preview_cache_eviction_matches_eviction() {
+        let build_dir = std::env::temp_dir().join(format!("tideflow-evict-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&build_dir).unwrap();
+        for (name, size) in [("cached_a.pdf", 400), ("cached_b.pdf", 300), ("cached_c.pdf", 200), ("cached_d.pdf", 100)] {
+            fs::write(build_dir.join(name), vec![0u8; size]).unwrap();
+        }
+        fs::write(build_dir.join("content.md"), vec![0u8; 5000]).unwrap();
+
+        let candidates = cache_eviction_candidates(&build_dir, 450);
+        let freed: u64 = candidates.iter().map(|c| c.size_bytes).sum();
+        assert!(1000 - freed <= 450);
+        assert!(candidates.len() >= 2);
+        // Dry run leaves everything in place
+        assert!(candidates.iter().all(|c| build_dir.join(&c.name).exists()));
+
+        let report = evict_render_cache_in(&build_dir, 450);
+        assert_eq!(report.files_removed, candidates.len());
+        assert_eq!(report.total_space_freed, freed);
+        for candidate in &candidates {
+            assert!(!build_dir.join(&candidate.name).exists());
+        }
+        assert!(cache_eviction_candidates(&build_dir, 450).is_empty());
+        assert!(build_dir.join("content.md").exists());
+
+        let _ = fs::remove_dir_all(&build_dir);
+    }
+
+    #[test]
+    fn test_cleanup_scans_renamed_assets_dir() {
+        let content_dir = std::env::temp_dir().join(format!("tideflow-media-{}", uuid::Uuid::new_v4()));
+        let media_dir = content_dir.join("media");
+        fs::create_dir_all(&media_dir).unwrap();
+        fs::write(media_dir.join("used.png"), [1u8; 4]).unwrap();
+        fs::write(media_dir.join("orphan.png"), [1u8; 6]).unwrap();
+        fs::write(content_dir.join("doc.md"), "![x](media/used.png)").unwrap();
+
+        let report = cleanup_unused_assets_in(&content_dir, &media_dir);
+        assert_eq!((report.files_removed, report.total_space_freed), (1, 6));
+        assert!(media_dir.join("used.png").exists());
+
+        let _ = fs::remove_dir_all(&content_dir);
+    }
+
+    #[tokio::test]
+    async fn test_blocking_wrapper_preserves_results() {
+        let direct_dir = seed_messy_content_dir();
+        let wrapped_dir = seed_messy_content_dir();
+
+        let direct_stats = cache_stats_in(&direct_dir.join(".build"));
+        let build_dir = wrapped_dir.join(".build");
+        let wrapped_stats = run_blocking(move || cache_stats_in(&build_dir)).await.unwrap();
+        assert_eq!(direct_stats.cached_documents, wrapped_stats.cached_documents);
+        assert_eq!(direct_stats.cache_size_mb, wrapped_stats.cache_size_mb);
+
+        let direct = cleanup_temp_pdfs_in(&direct_dir.join(".build"), 1, Duration::from_secs(1800));
+        let build_dir = wrapped_dir.join(".build");
+        let wrapped = run_blocking(move || cleanup_temp_pdfs_in(&build_dir, 1, Duration::from_secs(1800)))
+            .await
+            .unwrap();
+        assert_eq!((direct.files_removed, direct.total_space_freed), (wrapped.files_removed, wrapped.total_space_freed));
+
+        let direct = cleanup_unused_assets_in(&direct_dir, &direct_dir.join("assets"));
+        let (content_dir, assets_dir) = (wrapped_dir.clone(), wrapped_dir.join("assets"));
+        let wrapped = run_blocking(move || cleanup_unused_assets_in(&content_dir, &assets_dir))
+            .await
+            .unwrap();
+        assert_eq!((direct.files_removed, direct.total_space_freed), (wrapped.files_removed, wrapped.total_space_freed));
+        assert_eq!(wrapped.files_removed, 1);
+
+        let _ = fs::remove_dir_all(&direct_dir);
+        let _ = fs::remove_dir_all(&wrapped_dir);
+    }
+
+    #[test]
+    fn test_cleanup_skipped_when_scan_hits_depth_limit() {
+        let content_dir = seed_messy_content_dir();
+        let deep_dir = (0..=MAX_ASSET_SCAN_DEPTH).fold(content_dir.join("notes"), |dir, level| dir.join(format!("l{}", level)));
+        fs::create_dir_all(&deep_dir).unwrap();
+        fs::write(deep_dir.join("deep.md"), "![Kept](assets/orphan.png)").unwrap();
+
+        let report = cleanup_unused_assets_in(&content_dir, &content_dir.join("assets"));
+
+        assert_eq!(report.files_removed, 0);
+        assert!(content_dir.join("assets").join("orphan.png").exists());
+
+        let _ = fs::remove_dir_all(&content_dir);
+    }
+
+    #[test]
+    fn test_backslash_asset_paths_count_as_referenced() {
+        let content_dir = seed_messy_content_dir();
+        fs::write(content_dir.join("windows.md"), "![Orphan?](assets\\orphan.png)").unwrap();
+
+        let report = cleanup_unused_assets_in(&content_dir, &content_dir.join("assets"));
+
+        assert_eq!(report.files_removed, 0);
+        assert!(content_dir.join("assets").join("orphan.png").exists());
+
+        let _ = fs::remove_dir_all(&content_dir);
+    }
+
+    #[test]
+    fn test_cleanup_progress_reports() {
+        let content_dir = seed_messy_content_dir();
+        for i in 0..PROGRESS_INTERVAL + 5 {
+            fs::write(content_dir.join(format!("note_{}.md", i)), "No images here.").unwrap();
+        }
+
+        let mut events = Vec::new();
+        let report = cleanup_unused_assets_with_progress(&content_dir, &content_dir.join("assets"), |progress| {
+            events.push(progress)
+        });
+
+        let scan: Vec<_> = events.iter().filter(|e| e.phase == "scan").collect();
+        let delete: Vec<_> = events.iter().filter(|e| e.phase == "delete").collect();
+        let markdown_total = PROGRESS_INTERVAL + 6; // notes plus doc.md
+        assert_eq!(scan.len(), 2);
+        assert_eq!(scan[0].files_scanned, PROGRESS_INTERVAL);
+        assert_eq!(scan.last().map(|e| (e.files_scanned, e.files_total)), Some((markdown_total, markdown_total)));
+        assert_eq!(delete.last().map(|e| (e.files_scanned, e.files_total)), Some((2, 2)));
+
+        assert_eq!(report.files_removed, 1);
+        assert!(!content_dir.join("assets").join("orphan.png").exists());
+
+        let _ = fs::remove_dir_all(&content_dir);
+    }
+
+    #[test]
+    fn test_asset_manifest_hashes_referenced_assets() {
+        let root = std::env::temp_dir().join(format!("tideflow-manifest-{}", uuid::Uuid::new_v4()));
+        let assets_dir = root.join("assets");
+        fs::create_dir_all(assets_dir.join("charts")).unwrap();
+        fs::write(assets_dir.join("logo.png"), "abc").unwrap();
+        fs::write(assets_dir.join("charts").join("q3.png"), "hello").unwrap();
+        fs::write(assets_dir.join("unused.png"), "unused").unwrap();
+
+        let md = "![Logo](assets/logo.png)\n\n![Chart](assets\\charts\\q3.png)\n\n![Gone](assets/missing.png)";
+        let manifest = asset_manifest_in(md, &assets_dir).unwrap();
+
+        assert_eq!(
+            manifest,
+            vec![
+                AssetManifestEntry {
+                    path: "assets/charts/q3.png".to_string(),
+                    size: 5,
+                    sha256: "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".to_string(),
+                },
+                AssetManifestEntry {
+                    path: "assets/logo.png".to_string(),
+                    size: 3,
+                    sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+                },
+            ]
+        );
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,898 @@
+/// Document preparation commands: run the preprocessing pipeline in a single call
+use crate::preprocessor::{
+    anchor_category, citation_keys_by_line, decode_html_entities, diff_document_anchors, frontmatter_str,
+    frontmatter_u64, malformed_table_rows, offset_to_line_column, parse_frontmatter,
+    preprocess_markdown, preprocess_markdown_with_options, slugify, split_frontmatter, AnchorDiff, AnchorMeta,
+    CodeBlockInfo, ExportMode, PreprocessOptions,
+};
+use super::image_ops::store_bibliography;
+use crate::renderer;
+use crate::utils;
+use anyhow::Result;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tauri::AppHandle;
+
+#[derive(Debug, Serialize)]
+pub struct PreparedDocument {
+    pub markdown: String,
+    pub anchors: Vec<AnchorMeta>,
+    pub bibliography: Option<String>,
+    pub has_bibliography: bool,
+    pub citation_counts: HashMap<String, usize>,
+    /// Code anchor id -> fence language and attributes (e.g. `{linenos}`)
+    pub code_blocks: HashMap<String, CodeBlockInfo>,
+    pub is_empty: bool,
+    pub warnings: Vec<String>,
+}
+
+/// Slug the preprocessor would generate for a heading with this text, so the UI can
+/// predict internal link targets. Duplicate suffixes (`-1`, `-2`) are not applied.
+#[tauri::command]
+pub fn slugify_heading(text: String) -> String {
+    slugify(&decode_html_entities(&text))
+}
+
+/// Anchors added, removed or moved between two versions of a document, with their
+/// line numbers, so the UI can highlight sections another author changed.
+#[tauri::command]
+pub async fn diff_documents(old_md: String, new_md: String) -> Result<AnchorDiff, String> {
+    let old = preprocess_markdown(&old_md, false).map_err(|e| e.to_string())?;
+    let new = preprocess_markdown(&new_md, false).map_err(|e| e.to_string())?;
+    Ok(diff_document_anchors(&old_md, &old.anchors, &new_md, &new.anchors))
+}
+
+/// Number of sync anchors per element type (`heading`, `paragraph`, `code`, `image`,
+/// `hr`, ...), for diagnostics and UI summaries.
+#[tauri::command]
+pub async fn anchor_type_breakdown(markdown: String) -> Result<HashMap<String, usize>, String> {
+    anchor_type_counts(&markdown).map_err(|e| e.to_string())
+}
+
+/// Count the anchors [`preprocess_markdown`] emits by [`anchor_category`].
+pub fn anchor_type_counts(markdown: &str) -> Result<HashMap<String, usize>> {
+    let output = preprocess_markdown(markdown, false)?;
+    let mut counts = HashMap::new();
+    for anchor in &output.anchors {
+        *counts.entry(anchor_category(&anchor.id).to_string()).or_insert(0) += 1;
+    }
+    Ok(counts)
+}
+
+/// Reading speed used when the document does not declare `reading_speed`.
+const DEFAULT_WORDS_PER_MINUTE: u64 = 200;
+
+#[derive(Debug, Serialize)]
+pub struct DocumentStats {
+    pub words: usize,
+    pub characters: usize,
+    pub headings: usize,
+    pub images: usize,
+    pub words_per_minute: u64,
+    pub reading_time_minutes: u64,
+}
+
+/// Word, heading and image counts plus an estimated reading time.
+///
+/// The reading speed can be overridden per document with a `reading_speed`
+/// frontmatter field (words per minute).
+#[tauri::command]
+pub async fn get_document_stats(markdown: String) -> Result<DocumentStats, String> {
+    Ok(document_stats(&markdown))
+}
+
+/// Compute [`DocumentStats`] for a markdown document.
+pub fn document_stats(markdown: &str) -> DocumentStats {
+    let (frontmatter, body) = split_frontmatter(markdown);
+    let words_per_minute = parse_frontmatter(frontmatter)
+        .as_ref()
+        .and_then(|fields| frontmatter_u64(fields, "reading_speed"))
+        .filter(|&wpm| wpm > 0)
+        .unwrap_or(DEFAULT_WORDS_PER_MINUTE);
+
+    let mut words = 0;
+    let mut characters = 0;
+    let mut headings = 0;
+    let mut images = 0;
+
+    for event in Parser::new_ext(body, Options::all()) {
+        match event {
+            Event::Text(text) | Event::Code(text) => {
+                words += text.split_whitespace().count();
+                characters += text.chars().count();
+            }
+            Event::Start(Tag::Heading(..)) => headings += 1,
+            Event::Start(Tag::Image(..)) => images += 1,
+            _ => {}
+        }
+    }
+
+    // Round up so any non-empty document reads as at least a minute
+    let reading_time_minutes = (words as u64).div_ceil(words_per_minute);
+
+    DocumentStats {
+        words,
+        characters,
+        headings,
+        images,
+        words_per_minute,
+        reading_time_minutes,
+    }
+}
+
+/// Page geometry for [`estimate_pages`]. Defaults approximate an A4 page at 11pt.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EstimateOptions {
+    pub lines_per_page: usize,
+    pub words_per_line: usize,
+    /// Lines of page height an image takes up
+    pub lines_per_image: usize,
+    /// Lines a heading takes up, including the spacing around it
+    pub lines_per_heading: usize,
+}
+
+impl Default for EstimateOptions {
+    fn default() -> Self {
+        Self {
+            lines_per_page: 45,
+            words_per_line: 12,
+            lines_per_image: 15,
+            lines_per_heading: 3,
+        }
+    }
+}
+
+/// Rough page count of the rendered PDF, without compiling.
+///
+/// This is only an approximation from word, heading and image counts; tables, code,
+/// page breaks and the template's layout are not taken into account.
+#[tauri::command]
+pub async fn estimate_page_count(markdown: String, options: Option<EstimateOptions>) -> Result<usize, String> {
+    Ok(estimate_pages(&markdown, &options.unwrap_or_default()))
+}
+
+/// Approximate page count of `markdown` under the given page geometry. Always at least 1.
+pub fn estimate_pages(markdown: &str, options: &EstimateOptions) -> usize {
+    let stats = document_stats(markdown);
+    let text_lines = stats.words.div_ceil(options.words_per_line.max(1));
+    let lines = text_lines + stats.images * options.lines_per_image + stats.headings * options.lines_per_heading;
+    lines.div_ceil(options.lines_per_page.max(1)).max(1)
+}
+
+/// Warning for documents that cite sources without a bibliography to resolve them.
+const NO_BIBLIOGRAPHY_WARNING: &str =
+    "Document contains citations but no bibliography is loaded; they will be shown as plain text";
+
+/// Preprocess a markdown document exactly as the renderer would and return the
+/// Typst-ready markdown together with anchors, bibliography and warnings.
+#[tauri::command]
+pub async fn prepare_document(
+    app_handle: AppHandle,
+    markdown: String,
+) -> Result<PreparedDocument, String> {
+    let content_dir = utils::get_content_dir(&app_handle).map_err(|e| e.to_string())?;
+    let assets_dir = utils::get_assets_dir(&app_handle).map_err(|e| e.to_string())?;
+    let options = renderer::preprocess_options(&app_handle, ExportMode::Final);
+
+    prepare_document_in(&markdown, &options, &content_dir, &assets_dir)
+        .map_err(|e| e.to_string())
+}
+
+/// Prepare a document against an explicit content/assets directory.
+///
+/// A `bibliography:` frontmatter key enables citation conversion when the referenced
+/// file has been imported into `.build`. Without one, an inline `references:` list is
+/// written into `.build` as a YAML bibliography. Otherwise `options.has_bibliography`
+/// (the preferences setting) decides. Citations missing from the bibliography are
+/// reported as warnings.
+pub fn prepare_document_in(
+    markdown: &str,
+    options: &PreprocessOptions,
+    content_dir: &Path,
+    assets_dir: &Path,
+) -> Result<PreparedDocument> {
+    let mut warnings = Vec::new();
+    let (frontmatter, body) = split_frontmatter(markdown);
+    let fields = parse_frontmatter(frontmatter);
+
+    // Bibliographies are imported into .build under their sanitized file name
+    let declared_bibliography = fields
+        .as_ref()
+        .and_then(|fields| frontmatter_str(fields, "bibliography"))
+        .and_then(|path| Path::new(path).file_name())
+        .map(|name| utils::sanitize_filename(&name.to_string_lossy()));
+
+    let bibliography = match declared_bibliography {
+        Some(name) if content_dir.join(".build").join(&name).exists() => Some(name),
+        Some(name) => {
+            warnings.push(format!(
+                "Bibliography '{}' is declared in frontmatter but has not been imported",
+                name
+            ));
+            None
+        }
+        None => match fields.as_ref().and_then(inline_references) {
+            Some(references) => {
+                let yaml = serde_yaml::to_string(&references)?;
+                let build_dir = content_dir.join(".build");
+                Some(
+                    store_bibliography(&build_dir, INLINE_REFERENCES_FILE, yaml.as_bytes())
+                        .map_err(anyhow::Error::msg)?,
+                )
+            }
+            None => None,
+        },
+    };
+    let has_bibliography = bibliography.is_some() || options.has_bibliography;
+
+    if !has_bibliography && !citation_keys_by_line(body).is_empty() {
+        warnings.push(NO_BIBLIOGRAPHY_WARNING.to_string());
+    }
+
+    let known_keys = if has_bibliography {
+        bibliography_keys(&content_dir.join(".build"), bibliography.as_deref())
+    } else {
+        None
+    };
+    let options = PreprocessOptions {
+        has_bibliography,
+        known_keys,
+        ..options.clone()
+    };
+    let preprocess = preprocess_markdown_with_options(markdown, &options)?;
+    warnings.extend(preprocess.warnings.iter().cloned());
+    for key in &preprocess.unknown_citations {
+        warnings.push(format!("Citation '{}' is not in the bibliography", key));
+    }
+    let prepared =
+        utils::rewrite_image_paths_in_markdown(&preprocess.markdown, content_dir, Some(assets_dir));
+
+    // Missing images are replaced with a visible placeholder by the rewriter
+    let re_missing_image = Regex::new(r"\[⚠ Image not found: ([^\]]*)\]")
+        .expect("BUG: Invalid regex pattern for missing image placeholders");
+    for caps in re_missing_image.captures_iter(&prepared) {
+        warnings.push(format!("Image not found: {}", &caps[1]));
+    }
+
+    Ok(PreparedDocument {
+        markdown: prepared,
+        anchors: preprocess.anchors,
+        bibliography,
+        has_bibliography,
+        citation_counts: preprocess.citation_counts,
+        code_blocks: preprocess.code_blocks,
+        is_empty: preprocess.is_empty,
+        warnings,
+    })
+}
+
+/// Bibliography file written for references declared inline in frontmatter.
+const INLINE_REFERENCES_FILE: &str = "frontmatter-references.yml";
+
+/// Extract an inline `references:` bibliography from frontmatter.
+///
+/// Accepts a CSL-YAML style list (`- id: key`), which is re-keyed by `id` into the
+/// keyed mapping Typst's YAML bibliographies use, or an already keyed mapping.
+/// Entry fields are passed through unchanged.
+fn inline_references(fields: &Mapping) -> Option<Mapping> {
+    match fields.get("references")? {
+        Value::Mapping(entries) if !entries.is_empty() => Some(entries.clone()),
+        Value::Sequence(items) => {
+            let mut entries = Mapping::new();
+            for item in items {
+                let Value::Mapping(item) = item else { continue };
+                let mut entry = item.clone();
+                if let Some(id) = entry.remove("id").filter(|id| id.is_string()) {
+                    entries.insert(id, Value::Mapping(entry));
+                }
+            }
+            (!entries.is_empty()).then_some(entries)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum DiagnosticCategory {
+    UnknownCitation,
+    MissingAsset,
+    BrokenLink,
+    MalformedTable,
+    EmptyDocument,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: DiagnosticSeverity,
+    pub category: DiagnosticCategory,
+    /// 0-based editor line
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DocumentDiagnostics {
+    pub diagnostics: Vec<Diagnostic>,
+    pub error_count: usize,
+    pub warning_count: usize,
+}
+
+impl DocumentDiagnostics {
+    fn push(&mut self, severity: DiagnosticSeverity, category: DiagnosticCategory, line: usize, message: String) {
+        match severity {
+            DiagnosticSeverity::Error => self.error_count += 1,
+            DiagnosticSeverity::Warning => self.warning_count += 1,
+        }
+        self.diagnostics.push(Diagnostic {
+            severity,
+            category,
+            line,
+            message,
+        });
+    }
+}
+
+/// Check a document for problems before export: unknown citations, missing
+/// images, broken internal links, malformed tables and an empty body.
+#[tauri::command]
+pub async fn validate_document(
+    app_handle: AppHandle,
+    markdown: String,
+) -> Result<DocumentDiagnostics, String> {
+    let content_dir = utils::get_content_dir(&app_handle).map_err(|e| e.to_string())?;
+    let assets_dir = utils::get_assets_dir(&app_handle).map_err(|e| e.to_string())?;
+    let options = renderer::preprocess_options(&app_handle, ExportMode::Final);
+
+    validate_document_in(&markdown, &options, &content_dir, &assets_dir)
+        .map_err(|e| e.to_string())
+}
+
+/// Validate a document against an explicit content/assets directory.
+///
+/// Diagnostics are sorted by line.
+pub fn validate_document_in(
+    markdown: &str,
+    options: &PreprocessOptions,
+    content_dir: &Path,
+    assets_dir: &Path,
+) -> Result<DocumentDiagnostics> {
+    let mut report = DocumentDiagnostics::default();
+    let prepared = prepare_document_in(markdown, options, content_dir, assets_dir)?;
+    let (frontmatter, body) = split_frontmatter(markdown);
+    let body_line = frontmatter.matches('\n').count();
+
+    if prepared.is_empty {
+        report.push(
+            DiagnosticSeverity::Warning,
+            DiagnosticCategory::EmptyDocument,
+            body_line,
+            "Document has no content to render".to_string(),
+        );
+    }
+
+    // Citations can only be checked against a loaded bibliography
+    if prepared.has_bibliography {
+        let build_dir = content_dir.join(".build");
+        if let Some(known) = bibliography_keys(&build_dir, prepared.bibliography.as_deref()) {
+            for (line, key) in citation_keys_by_line(body) {
+                if !known.contains(&key) {
+                    report.push(
+                        DiagnosticSeverity::Error,
+                        DiagnosticCategory::UnknownCitation,
+                        body_line + line,
+                        format!("Citation '{}' is not in the bibliography", key),
+                    );
+                }
+            }
+        }
+    }
+
+    // Internal links may target any label the preprocessor emits
+    let re_label = Regex::new(r#"#label\("([^"]+)"\)"#).expect("BUG: Invalid regex pattern for labels");
+    let labels: HashSet<&str> = re_label
+        .captures_iter(&prepared.markdown)
+        .filter_map(|caps| caps.get(1).map(|m| m.as_str()))
+        .collect();
+
+    let parser = Parser::new_ext(body, Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES | Options::ENABLE_HEADING_ATTRIBUTES);
+    for (event, range) in parser.into_offset_iter() {
+        let line = body_line + offset_to_line_column(body, range.start).0;
+        match event {
+            Event::Start(Tag::Image(_, dest, _)) if !asset_exists(&dest, content_dir, assets_dir) => {
+                report.push(
+                    DiagnosticSeverity::Error,
+                    DiagnosticCategory::MissingAsset,
+                    line,
+                    format!("Image not found: {}", dest),
+                );
+            }
+            Event::Start(Tag::Link(_, dest, _)) => {
+                if let Some(target) = dest.strip_prefix('#').filter(|t| !labels.contains(t)) {
+                    report.push(
+                        DiagnosticSeverity::Warning,
+                        DiagnosticCategory::BrokenLink,
+                        line,
+                        format!("Link target '#{}' does not exist", target),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for line in malformed_table_rows(body) {
+        report.push(
+            DiagnosticSeverity::Warning,
+            DiagnosticCategory::MalformedTable,
+            body_line + line,
+            "Table row has a different number of cells than its header".to_string(),
+        );
+    }
+
+    report.diagnostics.sort_by_key(|d| d.line);
+    Ok(report)
+}
+
+/// Whether an image reference resolves to a file (remote images are assumed present).
+fn asset_exists(dest: &str, content_dir: &Path, assets_dir: &Path) -> bool {
+    if dest.contains("://") || dest.starts_with("data:") {
+        return true;
+    }
+    let path = Path::new(dest);
+    path.is_absolute() && path.exists()
+        || content_dir.join(dest.trim_start_matches('/')).exists()
+        || path.file_name().is_some_and(|name| assets_dir.join(name).exists())
+}
+
+/// Citation keys defined by the bibliography in `build_dir`.
+///
+/// Reads the named file, or the single imported bibliography when no name is given.
+/// Returns `None` when no bibliography can be read.
+fn bibliography_keys(build_dir: &Path, name: Option<&str>) -> Option<HashSet<String>> {
+    let path = match name {
+        Some(name) => build_dir.join(name),
+        None => fs::read_dir(build_dir).ok()?.flatten().map(|e| e.path()).find(|p| {
+            matches!(p.extension().and_then(|e| e.to_str()), Some("bib" | "yml" | "yaml"))
+        })?,
+    };
+    let file = fs::File::open(&path).ok()?;
+    Some(scan_bib_keys(BufReader::new(file)))
+}
+
+/// Citation keys of a bibliography, read line by line so huge files are never held
+/// in memory or parsed in full.
+///
+/// Finds BibTeX `@type{key,` entries (skipping `@string`, `@preamble` and `@comment`).
+/// A file without any is read as YAML: CSL-YAML `- id: key` items or unindented
+/// Hayagriva `key:` entries. Unreadable lines are skipped.
+pub fn scan_bib_keys<R: BufRead>(reader: R) -> HashSet<String> {
+    let re_entry = Regex::new(r"^\s*@(\w+)\s*[{(]\s*([^,\s{}()]+)\s*,")
+        .expect("BUG: Invalid regex pattern for BibTeX entries");
+    let re_csl_id = Regex::new(r#"^(?:-\s+|\s{1,2})id:\s*['"]?([^'"\s#]+)"#)
+        .expect("BUG: Invalid regex pattern for CSL-YAML ids");
+    let re_top_key = Regex::new(r##"^['"]?([^\s'"#:-][^'":]*)['"]?:\s*(?:#.*)?$"##)
+        .expect("BUG: Invalid regex pattern for YAML keys");
+
+    let mut bibtex_keys = HashSet::new();
+    let mut yaml_keys = HashSet::new();
+    for line in reader.split(b'\n').map_while(|line| line.ok()) {
+        let line = String::from_utf8_lossy(&line);
+        if let Some(caps) = re_entry.captures(&line) {
+            if !matches!(caps[1].to_ascii_lowercase().as_str(), "string" | "preamble" | "comment") {
+                bibtex_keys.insert(caps[2].to_string());
+            }
+        } else if bibtex_keys.is_empty() {
+            let caps = re_csl_id.captures(&line).or_else(|| re_top_key.captures(line.trim_end()));
+            if let Some(caps) = caps {
+                yaml_keys.insert(caps[1].to_string());
+            }
+        }
+    }
+
+    if bibtex_keys.is_empty() {
+        yaml_keys
+    } else {
+        bibtex_keys
+    }
+}
+
+/// Preprocessing result for one file of a multi-file project.
+#[derive(Debug, Serialize)]
+pub struct ProjectFileResult {
+    pub path: String,
+    /// Label prefix for this file, derived from its file name (e.g. `chapter-1`)
+    pub namespace: String,
+    pub anchors: Vec<AnchorMeta>,
+    /// Anchor ids qualified with the file's namespace (`chapter-1:introduction`)
+    pub labels: Vec<String>,
+    pub warnings: Vec<String>,
+    /// Set when the file could not be read or preprocessed
+    pub error: Option<String>,
+}
+
+/// Preprocess every markdown file of a project (e.g. a folder of chapters) at once.
+///
+/// Files are processed concurrently; results are returned in the order given.
+#[tauri::command]
+pub async fn preprocess_project(app_handle: AppHandle, files: Vec<String>) -> Vec<ProjectFileResult> {
+    let options = renderer::preprocess_options(&app_handle, ExportMode::Final);
+    preprocess_project_in(files, options).await
+}
+
+/// Preprocess `files` concurrently with explicit options.
+pub async fn preprocess_project_in(files: Vec<String>, options: PreprocessOptions) -> Vec<ProjectFileResult> {
+    let namespaces = project_namespaces(&files);
+    let tasks: Vec<_> = files
+        .into_iter()
+        .zip(namespaces)
+        .map(|(path, namespace)| {
+            let options = options.clone();
+            let (task_path, task_namespace) = (path.clone(), namespace.clone());
+            let task = tauri::async_runtime::spawn_blocking(move || {
+                preprocess_project_file(task_path, task_namespace, &options)
+            });
+            (path, namespace, task)
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(tasks.len());
+    for (path, namespace, task) in tasks {
+        match task.await {
+            Ok(result) => results.push(result),
+            // Keep the file in the results so the caller sees what failed
+            Err(e) => results.push(ProjectFileResult {
+                path,
+                namespace,
+                anchors: Vec::new(),
+                labels: Vec::new(),
+                warnings: Vec::new(),
+                error: Some(format!("Background task failed: {}", e)),
+            }),
+        }
+    }
+    results
+}
+
+/// Unique label namespace per file, from its slugified file stem. Files sharing a
+/// stem (`a/intro.md`, `b/intro.md`) get `-2`, `-3` suffixes in order.
+fn project_namespaces(files: &[String]) -> Vec<String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    files
+        .iter()
+        .map(|path| {
+            let stem = Path::new(path).file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+            let base = match slugify(&stem) {
+                slug if slug.is_empty() => "file".to_string(),
+                slug => slug,
+            };
+            let count = seen.entry(base.clone()).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                base
+            } else {
+                format!("{}-{}", base, count)
+            }
+        })
+        .collect()
+}
+
+fn preprocess_project_file(path: String, namespace: String, options: &PreprocessOptions) -> ProjectFileResult {
+    let mut result = ProjectFileResult {
+        path,
+        namespace,
+        anchors: Vec::new(),
+        labels: Vec::new(),
+        warnings: Vec::new(),
+        error: None,
+    };
+
+    let markdown = match fs::read_to_string(&result.path) {
+        Ok(markdown) => markdown,
+        Err(e) => {
+            result.error = Some(format!("Failed to read file: {}", e));
+            return result;
+        }
+    };
+    let output = match preprocess_markdown_with_options(&markdown, options) {
+        Ok(output) => output,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+
+    let (frontmatter, body) = split_frontmatter(&markdown);
+    let body_line = frontmatter.matches('\n').count();
+    if !options.has_bibliography && !citation_keys_by_line(body).is_empty() {
+        result.warnings.push(NO_BIBLIOGRAPHY_WARNING.to_string());
+    }
+    for line in malformed_table_rows(body) {
+        result.warnings.push(format!(
+            "Line {}: table row has a different number of cells than its header",
+            body_line + line + 1
+        ));
+    }
+    result.warnings.extend(output.warnings);
+
+    result.labels = output
+        .anchors
+        .iter()
+        .map(|anchor| format!("{}:{}", result.namespace, anchor.id))
+        .collect();
+    result.anchors = output.anchors;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_document_with_frontmatter_citations_and_images() {
+        let root = std::env::temp_dir().join(format!("tideflow-prepare-{}", uuid::Uuid::new_v4()));
+        let content_dir = root.join("content");
+        let assets_dir = content_dir.join("assets");
+        fs::create_dir_all(&assets_dir).unwrap();
+        fs::create_dir_all(content_dir.join(".build")).unwrap();
+        fs::write(content_dir.join(".build").join("refs.bib"), "@book{knuth1984,}").unwrap();
+        fs::write(assets_dir.join("figure.png"), [0x89, 0x50, 0x4E, 0x47]).unwrap();
+
+        let md = "---\ntitle: Paper\nbibliography: library/refs.bib\n---\n\n# Intro\n\nAs shown [@knuth1984].\n\n![Figure](assets/figure.png)\n\n![Lost](assets/missing.png)\n";
+        let prepared = prepare_document_in(md, &PreprocessOptions::default(), &content_dir, &assets_dir).unwrap();
+
+        assert_eq!(prepared.bibliography.as_deref(), Some("refs.bib"));
+        assert!(prepared.has_bibliography);
+        assert!(prepared.markdown.starts_with("---\ntitle: Paper"));
+        assert!(prepared.markdown.contains("#cite(<knuth1984>)"));
+        assert!(prepared.markdown.contains("![Figure](/assets/figure.png)"));
+        assert!(prepared.anchors.iter().any(|a| a.id == "intro"));
+        assert_eq!(prepared.citation_counts.get("knuth1984"), Some(&1));
+        assert_eq!(prepared.warnings, vec!["Image not found: Lost".to_string()]);
+
+        // Without an imported bibliography, citations stay literal and a warning is raised
+        fs::remove_file(content_dir.join(".build").join("refs.bib")).unwrap();
+        let prepared = prepare_document_in(md, &PreprocessOptions::default(), &content_dir, &assets_dir).unwrap();
+        assert!(!prepared.has_bibliography);
+        assert!(prepared.markdown.contains("[@knuth1984]"));
+        assert_eq!(prepared.warnings.len(), 3);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_prepare_document_with_inline_references() {
+        let root = std::env::temp_dir().join(format!("tideflow-prepare-{}", uuid::Uuid::new_v4()));
+        let content_dir = root.join("content");
+        let assets_dir = content_dir.join("assets");
+        fs::create_dir_all(&assets_dir).unwrap();
+
+        let md = "---\ntitle: Paper\nreferences:\n  - id: knuth1984\n    type: book\n    title: Literate Programming\n---\n\nAs shown [@knuth1984].\n";
+        let prepared = prepare_document_in(md, &PreprocessOptions::default(), &content_dir, &assets_dir).unwrap();
+
+        assert_eq!(prepared.bibliography.as_deref(), Some(INLINE_REFERENCES_FILE));
+        assert!(prepared.has_bibliography);
+        assert!(prepared.markdown.contains("#cite(<knuth1984>)"));
+        assert!(prepared.warnings.is_empty());
+
+        let written = fs::read_to_string(content_dir.join(".build").join(INLINE_REFERENCES_FILE)).unwrap();
+        let entries: Mapping = serde_yaml::from_str(&written).unwrap();
+        let entry = entries.get("knuth1984").and_then(Value::as_mapping).unwrap();
+        assert_eq!(entry.get("title").and_then(Value::as_str), Some("Literate Programming"));
+        assert!(entry.get("id").is_none());
+
+        // Keys missing from the bibliography are reported
+        let unknown = format!("{}Also [@nobody2020].\n", md);
+        let prepared = prepare_document_in(&unknown, &PreprocessOptions::default(), &content_dir, &assets_dir).unwrap();
+        assert_eq!(prepared.warnings, vec!["Citation 'nobody2020' is not in the bibliography".to_string()]);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_validate_document_reports_each_problem() {
+        let root = std::env::temp_dir().join(format!("tideflow-validate-{}", uuid::Uuid::new_v4()));
+        let content_dir = root.join("content");
+        let assets_dir = content_dir.join("assets");
+        fs::create_dir_all(&assets_dir).unwrap();
+        fs::create_dir_all(content_dir.join(".build")).unwrap();
+        fs::write(content_dir.join(".build").join("refs.bib"), "@book{knuth1984,\n  title = {TAOCP}\n}").unwrap();
+        fs::write(assets_dir.join("figure.png"), [0x89, 0x50, 0x4E, 0x47]).unwrap();
+
+        let md = "---\nbibliography: refs.bib\n---\n# Intro\n\nKnown [@knuth1984], unknown [@nobody2020].\n\n![Fig](assets/figure.png)\n![Gone](assets/gone.png)\n\nSee [intro](#intro) and [missing](#nowhere).\n\n| A | B |\n|---|---|\n| 1 |\n";
+        let report = validate_document_in(md, &PreprocessOptions::default(), &content_dir, &assets_dir).unwrap();
+
+        let found: Vec<(DiagnosticCategory, usize)> = report.diagnostics.iter().map(|d| (d.category, d.line)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (DiagnosticCategory::UnknownCitation, 5),
+                (DiagnosticCategory::MissingAsset, 8),
+                (DiagnosticCategory::BrokenLink, 10),
+                (DiagnosticCategory::MalformedTable, 14),
+            ]
+        );
+        assert!(report.diagnostics[0].message.contains("nobody2020"));
+        assert_eq!((report.error_count, report.warning_count), (2, 2));
+
+        let report = validate_document_in("---\ntitle: Empty\n---\n", &PreprocessOptions::default(), &content_dir, &assets_dir).unwrap();
+        assert_eq!(report.diagnostics.len(), 1);
+        assert_eq!(report.diagnostics[0].category, DiagnosticCategory::EmptyDocument);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_document_stats_reading_speed_from_frontmatter() {
+        let body = "# Notes\n\n".to_string() + &"word ".repeat(399) + "\n";
+        let stats = document_stats(&body);
+        assert_eq!(stats.words, 400); // heading text counts too
+        assert_eq!(stats.headings, 1);
+        assert_eq!(stats.words_per_minute, DEFAULT_WORDS_PER_MINUTE);
+        assert_eq!(stats.reading_time_minutes, 2);
+
+        let slow = format!("---\ntitle: Dense\nreading_speed: 180\n---\n{}", body);
+        let stats = document_stats(&slow);
+        assert_eq!(stats.words, 400);
+        assert_eq!(stats.words_per_minute, 180);
+        assert_eq!(stats.reading_time_minutes, 3);
+    }
+
+    #[test]
+    fn test_estimate_pages() {
+        let options = EstimateOptions::default();
+        let short = "# Note\n\nA few words only.";
+        let long = "# Report\n\n".to_string() + &"word ".repeat(5000) + "\n\n![Chart](chart.png)\n";
+
+        assert_eq!(estimate_pages(short, &options), 1);
+        let long_pages = estimate_pages(&long, &options);
+        assert!(long_pages > estimate_pages(short, &options));
+        // 5000 words / 12 per line = 417 lines, + 15 for the image + 3 for the heading
+        assert_eq!(long_pages, 435usize.div_ceil(45));
+        assert_eq!(estimate_pages(&long, &options), long_pages);
+
+        let dense = EstimateOptions {
+            lines_per_page: 60,
+            words_per_line: 16,
+            ..EstimateOptions::default()
+        };
+        assert!(estimate_pages(&long, &dense) < long_pages);
+    }
+
+    #[test]
+    fn test_anchor_type_counts() {
+        let md = "---\ntitle: Mixed\n---\n# Title\n\nFirst paragraph.\n\nSecond paragraph.\n\n\
+                  ## Section\n\n```rust\nfn main() {}\n```\n\n![Chart](assets/chart.png)\n\n\
+                  ---\n\n| A | B |\n|---|---|\n| 1 | 2 |\n\n\\pagebreak\n\nLast paragraph.\n";
+        let counts = anchor_type_counts(md).unwrap();
+
+        // Tables carry no sync anchor of their own
+        assert!(!counts.contains_key("table"));
+
+        let expected: HashMap<String, usize> = [
+            ("document", 1),
+            ("heading", 2),
+            ("paragraph", 3),
+            ("code", 1),
+            ("image", 1),
+            ("hr", 1),
+            ("pagebreak", 1),
+        ]
+        .into_iter()
+        .map(|(category, count)| (category.to_string(), count))
+        .collect();
+        assert_eq!(counts, expected);
+    }
+
+    #[test]
+    fn test_slugify_heading_matches_injected_labels() {
+        let headings = [
+            "Getting Started",
+            "What's new in v2.0?",
+            "Ünïcode — Dashes – and/or Slashes",
+            "  Padded   Title  ",
+            "C++ & Rust: a comparison",
+            "Tips &amp; Tricks",
+            "Before&#8212;After",
+        ];
+
+        for text in headings {
+            let output = preprocess_markdown(&format!("# {}\n", text), false).unwrap();
+            let label = output
+                .anchors
+                .iter()
+                .find(|a| a.id != "tf-doc-start")
+                .map(|a| a.id.clone())
+                .unwrap();
+            assert_eq!(slugify_heading(text.to_string()), label, "heading {:?}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preprocess_project_namespaces_chapters() {
+        let root = std::env::temp_dir().join(format!("tideflow-project-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let chapter_one = root.join("Chapter 1.md");
+        let chapter_two = root.join("chapter-2.md");
+        fs::write(&chapter_one, "# Introduction\n\nText.\n").unwrap();
+        fs::write(&chapter_two, "# Introduction\n\nMore text [@knuth1984].\n").unwrap();
+        let files = vec![
+            chapter_one.to_string_lossy().to_string(),
+            chapter_two.to_string_lossy().to_string(),
+            root.join("missing.md").to_string_lossy().to_string(),
+        ];
+
+        let results = preprocess_project_in(files, PreprocessOptions::default()).await;
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].namespace, "chapter-1");
+        assert_eq!(results[1].namespace, "chapter-2");
+        assert!(results[0].labels.contains(&"chapter-1:introduction".to_string()));
+        assert!(results[1].labels.contains(&"chapter-2:introduction".to_string()));
+        assert!(results[0].warnings.is_empty());
+        assert_eq!(results[1].warnings.len(), 1);
+        assert!(results[2].error.is_some());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_project_namespaces_are_unique() {
+        let files = vec!["a/intro.md".to_string(), "b/intro.md".to_string(), "notes.md".to_string()];
+        assert_eq!(project_namespaces(&files), vec!["intro", "intro-2", "notes"]);
+    }
+
+    /// Generates BibTeX entries on demand, so the whole file never exists at once.
+    struct GeneratedBib {
+        next: usize,
+        total: usize,
+        pending: Vec<u8>,
+    }
+
+    impl std::io::Read for GeneratedBib {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pending.is_empty() && self.next < self.total {
+                self.pending = format!(
+                    "@article{{key{0},\n  title = {{Entry {{{0}}}}},\n  year = 2024,\n}}\n\n",
+                    self.next
+                )
+                .into_bytes();
+                self.next += 1;
+            }
+            let len = buf.len().min(self.pending.len());
+            buf[..len].copy_from_slice(&self.pending[..len]);
+            self.pending.drain(..len);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_scan_bib_keys_streams_entries() {
+        let source = GeneratedBib { next: 0, total: 20_000, pending: Vec::new() };
+        let keys = scan_bib_keys(BufReader::with_capacity(256, source));
+        assert_eq!(keys.len(), 20_000);
+        assert!(keys.contains("key0") && keys.contains("key19999"));
+
+        let bib = "@string{jr = \"Journal\"}\n@comment{note,}\n@Book( jones2021 ,\n  title = {B},\n)\n";
+        assert_eq!(scan_bib_keys(bib.as_bytes()), HashSet::from(["jones2021".to_string()]));
+
+        let csl = "- id: smith2020\n  title: A study\n- type: book\n  id: \"jones2021\"\n  note: \"id: not-a-key\"\n";
+        let expected: HashSet<String> = ["smith2020", "jones2021"].iter().map(|k| k.to_string()).collect();
+        assert_eq!(scan_bib_keys(csl.as_bytes()), expected);
+
+        let hayagriva = "smith2020:\n  type: article\n  title: A study\njones2021:\n  type: book\n";
+        assert_eq!(scan_bib_keys(hayagriva.as_bytes()), expected);
+    }
+}
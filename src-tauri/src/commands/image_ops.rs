@@ -1,9 +1,17 @@
 /// Image operation commands: importing and managing images
-use crate::utils;
+use super::cache_ops::run_blocking;
+use crate::preferences;
+use crate::utils::{self, AssetsDirName};
 use base64::Engine;
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat};
+use regex::{Captures, Regex};
+use serde::Serialize;
+use std::collections::HashSet;
 use std::fs;
+use std::io::{Cursor, Read};
 use std::path::Path;
-use tauri::AppHandle;
+use std::time::{Duration, SystemTime};
+use tauri::{AppHandle, State};
 use uuid::Uuid;
 
 #[tauri::command]
@@ -28,12 +36,18 @@ pub async fn import_image(
     let assets_dir = utils::get_assets_dir(&app_handle)
         .map_err(|e| e.to_string())?;
     
+    save_image_bytes(&assets_dir, file_name, &image_bytes)
+}
+
+/// Write decoded image bytes into `assets_dir` under `file_name` (or a generated name).
+/// Returns the relative path for Markdown insertion.
+fn save_image_bytes(assets_dir: &Path, file_name: Option<String>, image_bytes: &[u8]) -> Result<String, String> {
     // Ensure assets directory exists
-    fs::create_dir_all(&assets_dir).map_err(|e| e.to_string())?;
+    fs::create_dir_all(assets_dir).map_err(|e| e.to_string())?;
     
     // Generate unique filename if not provided
     let filename = match file_name {
-        Some(name) => utils::sanitize_filename(&name),
+        Some(name) => with_image_extension(utils::sanitize_filename(&name), image_bytes),
         None => {
             let uuid = Uuid::new_v4();
             format!("image-{}.png", uuid)
@@ -47,34 +61,250 @@ pub async fn import_image(
     fs::write(&image_path, image_bytes).map_err(|e| e.to_string())?;
     
     // Return relative path for Markdown insertion
-    Ok(format!("assets/{}", filename))
+    Ok(asset_link(assets_dir, &filename))
+}
+
+/// Append the detected format's extension when `name` has no recognized image extension,
+/// so Typst can identify the file.
+fn with_image_extension(name: String, image_bytes: &[u8]) -> String {
+    const IMAGE_EXTENSIONS: [&str; 9] = ["png", "jpg", "jpeg", "gif", "webp", "bmp", "svg", "tif", "tiff"];
+
+    let has_image_extension = Path::new(&name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+    if has_image_extension {
+        return name;
+    }
+
+    match utils::detect_image_extension(image_bytes) {
+        Some(ext) => format!("{}.{}", name.trim_end_matches('.'), ext),
+        None => name,
+    }
 }
 
 /// Import an image by copying it from a local filesystem path into the app's assets directory.
 /// Returns a relative path like "assets/<filename>" suitable for Markdown insertion.
+///
+/// For a multi-page TIFF, `page` (1-based) selects a single page, which is saved as PNG.
+/// Single-page images ignore it.
+///
+/// Identical content already in the assets tree is reused, unless `force_name` is set,
+/// in which case the file is written under its own name as an intentional alias.
+///
+/// With `preserve_mtime`, a newly written file keeps the source's modification time
+/// instead of the import time.
 #[tauri::command]
 pub async fn import_image_from_path(
     app_handle: AppHandle,
     source_path: &str,
-) -> Result<String, String> {
+    page: Option<usize>,
+    force_name: Option<bool>,
+    preserve_mtime: Option<bool>,
+) -> Result<ImportedImage, String> {
     let src = Path::new(source_path);
     if !src.exists() {
         return Err(format!("Source image does not exist: {}", source_path));
     }
 
+    // Determine destination directory
+    let assets_dir = utils::get_assets_dir(&app_handle).map_err(|e| e.to_string())?;
+
+    import_image_file_in(
+        src,
+        &assets_dir,
+        page,
+        force_name.unwrap_or(false),
+        preserve_mtime.unwrap_or(false),
+    )
+}
+
+/// Result of importing an image file.
+#[derive(Debug, Serialize)]
+pub struct ImportedImage {
+    /// Path relative to the content directory (e.g. "assets/figure.png")
+    pub path: String,
+    /// Problems to show the user, e.g. SVG image references that will render blank
+    pub warnings: Vec<String>,
+}
+
+/// Import the image at `src` into an explicit assets directory.
+fn import_image_file_in(
+    src: &Path,
+    assets_dir: &Path,
+    page: Option<usize>,
+    force_name: bool,
+    preserve_mtime: bool,
+) -> Result<ImportedImage, String> {
     // Read source bytes
     let image_bytes = fs::read(src).map_err(|e| format!("Failed to read image: {}", e))?;
-
-    // Determine destination directory and filename
-    let assets_dir = utils::get_assets_dir(&app_handle).map_err(|e| e.to_string())?;
-    fs::create_dir_all(&assets_dir).map_err(|e| e.to_string())?;
+    let mtime = if preserve_mtime {
+        Some(fs::metadata(src).and_then(|m| m.modified()).map_err(|e| e.to_string())?)
+    } else {
+        None
+    };
 
     let orig_name = src
         .file_name()
         .and_then(|n| n.to_str())
         .unwrap_or("image.png");
+
+    // Typst renders SVGs without access to files next to them, so inline referenced images
+    let is_svg = Path::new(orig_name)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+    if is_svg {
+        if let Ok(svg) = std::str::from_utf8(&image_bytes) {
+            let source_dir = src.parent().unwrap_or(Path::new("."));
+            let (inlined, warnings) = inline_svg_images(svg, source_dir);
+            let path = copy_image_into_assets(assets_dir, orig_name, inlined.as_bytes(), force_name, mtime)?;
+            return Ok(ImportedImage { path, warnings });
+        }
+    }
+
+    if let Some(page) = page {
+        if let Some(png) = extract_tiff_page(&image_bytes, page)? {
+            let stem = Path::new(orig_name).file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+            let name = format!("{}-p{}.png", stem, page);
+            let path = copy_image_into_assets(assets_dir, &name, &png, force_name, mtime)?;
+            return Ok(ImportedImage { path, warnings: Vec::new() });
+        }
+    }
+
+    let path = copy_image_into_assets(assets_dir, orig_name, &image_bytes, force_name, mtime)?;
+    Ok(ImportedImage { path, warnings: Vec::new() })
+}
+
+/// Inline raster images an SVG references by file path as base64 data URIs, resolving
+/// paths relative to `source_dir` (the SVG's own folder). Absolute and `../` paths
+/// are not followed, like [`copy_bibliography_references`].
+///
+/// Embedded data URIs are checked but kept as is. Returns the rewritten SVG and a
+/// warning for each reference that will render blank.
+fn inline_svg_images(svg: &str, source_dir: &Path) -> (String, Vec<String>) {
+    let re_image = Regex::new(r#"(<image\b[^>]*?\s(?:xlink:)?href\s*=\s*)(?:"([^"]*)"|'([^']*)')"#)
+        .expect("BUG: Invalid regex pattern for SVG images");
+    let mut warnings = Vec::new();
+
+    let inlined = re_image.replace_all(svg, |caps: &Captures| {
+        let href = caps.get(2).or_else(|| caps.get(3)).map_or("", |m| m.as_str());
+        let original = caps[0].to_string();
+
+        if let Some(data) = href.strip_prefix("data:") {
+            let decodes = match data.split_once(";base64,") {
+                Some((_, payload)) => base64::engine::general_purpose::STANDARD
+                    .decode(payload.trim())
+                    .is_ok(),
+                None => data.contains(','),
+            };
+            if !decodes {
+                warnings.push("SVG contains an embedded image that could not be decoded".to_string());
+            }
+            return original;
+        }
+        if href.starts_with("http://") || href.starts_with("https://") {
+            warnings.push(format!("SVG image '{}' is remote and will not be rendered", href));
+            return original;
+        }
+
+        // Only files inside the SVG's folder are read
+        let relative = Path::new(href.strip_prefix("file://").unwrap_or(href));
+        let escapes = relative.has_root()
+            || relative
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir | std::path::Component::Prefix(_)));
+        if escapes {
+            warnings.push(format!("SVG image '{}' points outside the SVG's folder", href));
+            return original;
+        }
+        let Ok(bytes) = fs::read(source_dir.join(relative)) else {
+            warnings.push(format!("SVG image '{}' could not be found", href));
+            return original;
+        };
+        let mime = match utils::detect_image_extension(&bytes) {
+            Some("jpg") => "image/jpeg".to_string(),
+            Some(ext) => format!("image/{}", ext),
+            None => {
+                warnings.push(format!("SVG image '{}' is not a supported raster image", href));
+                return original;
+            }
+        };
+        format!(
+            "{}\"data:{};base64,{}\"",
+            &caps[1],
+            mime,
+            base64::engine::general_purpose::STANDARD.encode(&bytes)
+        )
+    });
+
+    (inlined.into_owned(), warnings)
+}
+
+/// Extract one page (1-based) of a multi-page TIFF as PNG bytes.
+///
+/// Returns `Ok(None)` for anything that is not a multi-page TIFF, so callers can
+/// fall back to importing the file as-is.
+fn extract_tiff_page(bytes: &[u8], page: usize) -> Result<Option<Vec<u8>>, String> {
+    let is_tiff = bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*");
+    if !is_tiff {
+        return Ok(None);
+    }
+
+    let tiff_error = |e: tiff::TiffError| format!("Failed to read TIFF: {}", e);
+    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes)).map_err(tiff_error)?;
+    if !decoder.more_images() {
+        return Ok(None);
+    }
+    if page == 0 {
+        return Err("TIFF pages are numbered from 1".to_string());
+    }
+    decoder
+        .seek_to_image(page - 1)
+        .map_err(|_| format!("TIFF has no page {}", page))?;
+
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let color = decoder.colortype().map_err(tiff_error)?;
+    let tiff::decoder::DecodingResult::U8(pixels) = decoder.read_image().map_err(tiff_error)? else {
+        return Err(format!("Unsupported TIFF sample format: {:?}", color));
+    };
+    let image = match color {
+        tiff::ColorType::Gray(8) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        tiff::ColorType::GrayA(8) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        tiff::ColorType::RGB(8) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        tiff::ColorType::RGBA(8) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+        other => return Err(format!("Unsupported TIFF color type: {:?}", other)),
+    }
+    .ok_or_else(|| "TIFF page data is truncated".to_string())?;
+
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(Some(png))
+}
+
+/// Store image bytes under `assets_dir`, reusing an identical existing file anywhere in the tree.
+/// With `force_name`, a new file is written under `orig_name` even when identical content
+/// exists elsewhere. A newly written file gets `mtime` as its modification time when set.
+/// Returns the path relative to the content directory (e.g. "assets/...").
+fn copy_image_into_assets(
+    assets_dir: &Path,
+    orig_name: &str,
+    image_bytes: &[u8],
+    force_name: bool,
+    mtime: Option<SystemTime>,
+) -> Result<String, String> {
+    fs::create_dir_all(assets_dir).map_err(|e| e.to_string())?;
+
     let mut base = utils::sanitize_filename(orig_name);
 
+    // Re-importing the same picture should not create another copy
+    if let Some(existing) = find_identical_asset(assets_dir, image_bytes) {
+        if !force_name || existing == base {
+            return Ok(asset_link(assets_dir, &existing));
+        }
+    }
+
     // Ensure unique filename to avoid accidental overwrite
     let mut dest_path = assets_dir.join(&base);
     if dest_path.exists() {
@@ -97,8 +327,74 @@ pub async fn import_image_from_path(
     }
 
     fs::write(&dest_path, image_bytes).map_err(|e| e.to_string())?;
+    if let Some(mtime) = mtime {
+        fs::File::options()
+            .write(true)
+            .open(&dest_path)
+            .and_then(|file| file.set_modified(mtime))
+            .map_err(|e| format!("Failed to set modification time: {}", e))?;
+    }
+
+    Ok(asset_link(assets_dir, &base))
+}
+
+/// Content-relative link for a file under `assets_dir`, e.g. "assets/figure.png".
+/// The prefix follows the directory's name so a renamed assets folder (`media/`) works.
+fn asset_link(assets_dir: &Path, relative: &str) -> String {
+    let dir_name = assets_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| utils::DEFAULT_ASSETS_DIR_NAME.to_string());
+    format!("{}/{}", dir_name, relative)
+}
 
-    Ok(format!("assets/{}", base))
+/// Get the name of the assets subdirectory (default "assets").
+#[tauri::command]
+pub async fn get_assets_dir_name(assets_dir_name: State<'_, AssetsDirName>) -> Result<String, String> {
+    Ok(assets_dir_name.get())
+}
+
+/// Rename the assets subdirectory used for imports, cleanup and path rewriting
+/// (e.g. "images" or "media") and save it in preferences. The directory is created
+/// on next use.
+#[tauri::command]
+pub async fn set_assets_dir_name(
+    app_handle: AppHandle,
+    assets_dir_name: State<'_, AssetsDirName>,
+    name: String,
+) -> Result<(), String> {
+    assets_dir_name.set(&name).map_err(|e| e.to_string())?;
+    let name = assets_dir_name.get();
+    preferences::update_preferences(&app_handle, |prefs| prefs.assets_dir_name = name)
+}
+
+/// Find a file under `assets_dir` (recursively, skipping `.build`) whose content equals `bytes`.
+/// Returns its path relative to `assets_dir`, using `/` separators.
+fn find_identical_asset(assets_dir: &Path, bytes: &[u8]) -> Option<String> {
+    let entries = fs::read_dir(assets_dir).ok()?;
+    let mut subdirs = Vec::new();
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else { continue };
+        if metadata.is_dir() {
+            if entry.file_name() != ".build" {
+                subdirs.push(path);
+            }
+            continue;
+        }
+        // Cheap size check before comparing contents
+        if metadata.len() == bytes.len() as u64 && fs::read(&path).is_ok_and(|existing| existing == bytes) {
+            return Some(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    // Top-level matches win; then look inside subdirectories
+    subdirs.sort();
+    subdirs.into_iter().find_map(|dir| {
+        let name = dir.file_name()?.to_string_lossy().to_string();
+        find_identical_asset(&dir, bytes).map(|nested| format!("{}/{}", name, nested))
+    })
 }
 
 /// Import a bibliography file by copying it to the .build directory.
@@ -121,12 +417,344 @@ pub async fn import_bibliography_from_path(
     let content_dir = utils::get_content_dir(&app_handle).map_err(|e| e.to_string())?;
     let build_dir = content_dir.join(".build");
 
+    // Use original filename
+    let orig_name = src
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("references.bib");
+
+    let filename = store_bibliography(&build_dir, orig_name, &bib_bytes)?;
+
+    // CSL-YAML entries may reference files next to the original bibliography
+    if filename.ends_with(".yml") || filename.ends_with(".yaml") {
+        let source_dir = src.parent().unwrap_or(Path::new("."));
+        let bib_text = fs::read_to_string(build_dir.join(&filename)).map_err(|e| e.to_string())?;
+        for warning in copy_bibliography_references(source_dir, &build_dir, &bib_text) {
+            crate::log_warn!("Bibliography", "{}", warning);
+        }
+    }
+
+    Ok(filename)
+}
+
+/// Largest bibliography accepted from a URL.
+const MAX_BIBLIOGRAPHY_DOWNLOAD: u64 = 10 * 1024 * 1024;
+
+/// Content types a server may use for a BibTeX or CSL-YAML file. Generic text and
+/// binary types are allowed because many static hosts serve `.bib` as either.
+const BIBLIOGRAPHY_CONTENT_TYPES: [&str; 9] = [
+    "text/plain",
+    "text/x-bibtex",
+    "application/x-bibtex",
+    "text/yaml",
+    "text/x-yaml",
+    "application/yaml",
+    "application/x-yaml",
+    "text/csl+yaml",
+    "application/octet-stream",
+];
+
+lazy_static::lazy_static! {
+    /// Start of a BibTeX entry such as `@article{`
+    static ref BIBTEX_ENTRY: Regex = Regex::new(r"@[A-Za-z]+\s*[{(]").unwrap();
+    /// Entry type and citation key, as in `@article{smith2020,`
+    static ref BIBTEX_KEYED_ENTRY: Regex = Regex::new(r"@([A-Za-z]+)\s*[{(]\s*([^,\s{}()]*)\s*,?").unwrap();
+}
+
+/// Download a bibliography from `url` into the .build directory.
+/// Returns the filename for use in Typst bibliography(), like [`import_bibliography_from_path`].
+#[tauri::command]
+pub async fn import_bibliography_from_url(app_handle: AppHandle, url: String) -> Result<String, String> {
+    let content_dir = utils::get_content_dir(&app_handle).map_err(|e| e.to_string())?;
+    let build_dir = content_dir.join(".build");
+
+    run_blocking(move || download_bibliography_in(&url, &build_dir)).await?
+}
+
+/// Fetch a bibliography over HTTP(S), validate it and store it in `build_dir`.
+fn download_bibliography_in(url: &str, build_dir: &Path) -> Result<String, String> {
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return Err(format!("Not an http(s) URL: {}", url));
+    }
+
+    let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(30)).build();
+    let response = agent
+        .get(url)
+        .call()
+        .map_err(|e| format!("Failed to download bibliography: {}", e))?;
+
+    let content_type = response.content_type().to_ascii_lowercase();
+    if !BIBLIOGRAPHY_CONTENT_TYPES.contains(&content_type.as_str()) {
+        return Err(format!("URL did not return a bibliography (content type {})", content_type));
+    }
+    let declared_len = response
+        .header("Content-Length")
+        .and_then(|len| len.trim().parse::<u64>().ok());
+    if declared_len.is_some_and(|len| len > MAX_BIBLIOGRAPHY_DOWNLOAD) {
+        return Err("Bibliography is larger than 10 MB".to_string());
+    }
+
+    let name = bibliography_name_from_url(url, &content_type);
+
+    // The server may not send (or may understate) Content-Length
+    let mut bib_bytes = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_BIBLIOGRAPHY_DOWNLOAD + 1)
+        .read_to_end(&mut bib_bytes)
+        .map_err(|e| format!("Failed to read bibliography: {}", e))?;
+    if bib_bytes.len() as u64 > MAX_BIBLIOGRAPHY_DOWNLOAD {
+        return Err("Bibliography is larger than 10 MB".to_string());
+    }
+
+    if !looks_like_bibliography(&name, &decode_bibliography(&bib_bytes)) {
+        return Err("Downloaded file is not a BibTeX or CSL-YAML bibliography".to_string());
+    }
+
+    store_bibliography(build_dir, &name, &bib_bytes)
+}
+
+/// File name for a downloaded bibliography: the URL's last path segment when it has a
+/// bibliography extension, otherwise `references.bib`/`references.yml` by content type.
+fn bibliography_name_from_url(url: &str, content_type: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let segment = path.rsplit('/').next().unwrap_or("");
+    let lower = segment.to_ascii_lowercase();
+    if [".bib", ".yml", ".yaml"].iter().any(|ext| lower.ends_with(ext)) && segment.len() > 4 {
+        return segment.to_string();
+    }
+    if content_type.contains("yaml") {
+        "references.yml".to_string()
+    } else {
+        "references.bib".to_string()
+    }
+}
+
+/// Cheap content check so an error page served as `text/plain` is not stored.
+fn looks_like_bibliography(name: &str, text: &str) -> bool {
+    matches!(
+        bibliography_format(name, text),
+        Some(BibFormat::BibTex | BibFormat::CslYaml | BibFormat::Hayagriva)
+    )
+}
+
+/// Bibliography file formats recognised by [`inspect_bibliography`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BibFormat {
+    BibTex,
+    /// CSL-YAML: a list of entries with an `id`
+    CslYaml,
+    /// CSL-JSON: an array of entries with an `id`. Typst cannot read it, so it is
+    /// reported but not importable
+    CslJson,
+    /// Hayagriva YAML: a mapping from key to entry
+    Hayagriva,
+}
+
+/// Summary of a bibliography file, for checking it before import.
+#[derive(Debug, Serialize)]
+pub struct BibInfo {
+    pub entry_count: usize,
+    /// Citation keys in file order
+    pub keys: Vec<String>,
+    /// `None` when the content matches no known format
+    pub format: Option<BibFormat>,
+    pub warnings: Vec<String>,
+}
+
+/// Detect the format of bibliography `text` from its file name and content.
+fn bibliography_format(name: &str, text: &str) -> Option<BibFormat> {
+    let lower = name.to_ascii_lowercase();
+    if lower.ends_with(".bib") {
+        return BIBTEX_ENTRY.is_match(text).then_some(BibFormat::BibTex);
+    }
+    if lower.ends_with(".json") {
+        return matches!(serde_json::from_str(text), Ok(serde_json::Value::Array(_))).then_some(BibFormat::CslJson);
+    }
+    match serde_yaml::from_str::<serde_yaml::Value>(text) {
+        Ok(serde_yaml::Value::Sequence(_)) => Some(BibFormat::CslYaml),
+        Ok(serde_yaml::Value::Mapping(_)) => Some(BibFormat::Hayagriva),
+        _ => None,
+    }
+}
+
+/// Report the entries, format and problems of a bibliography file without importing it.
+#[tauri::command]
+pub async fn inspect_bibliography(source_path: &str) -> Result<BibInfo, String> {
+    inspect_bibliography_file(Path::new(source_path))
+}
+
+fn inspect_bibliography_file(path: &Path) -> Result<BibInfo, String> {
+    if !path.exists() {
+        return Err(format!("Bibliography file does not exist: {}", path.display()));
+    }
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read bibliography: {}", e))?;
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("references.bib");
+    Ok(inspect_bibliography_text(name, &decode_bibliography(&bytes)))
+}
+
+fn inspect_bibliography_text(name: &str, text: &str) -> BibInfo {
+    let format = bibliography_format(name, text);
+    let mut warnings = Vec::new();
+    let keys = match format {
+        Some(BibFormat::BibTex) => bibtex_keys(text, &mut warnings),
+        Some(BibFormat::CslYaml) => {
+            let entries: Vec<serde_yaml::Value> = serde_yaml::from_str(text).unwrap_or_default();
+            let ids = entries.iter().map(|entry| entry.get("id").and_then(serde_yaml::Value::as_str));
+            csl_ids(ids, &mut warnings)
+        }
+        Some(BibFormat::CslJson) => {
+            warnings.push("CSL-JSON cannot be used by Typst; convert it to BibTeX or CSL-YAML".to_string());
+            let entries: Vec<serde_json::Value> = serde_json::from_str(text).unwrap_or_default();
+            let ids = entries.iter().map(|entry| entry.get("id").and_then(serde_json::Value::as_str));
+            csl_ids(ids, &mut warnings)
+        }
+        Some(BibFormat::Hayagriva) => {
+            let entries: serde_yaml::Mapping = serde_yaml::from_str(text).unwrap_or_default();
+            entries.keys().filter_map(|key| key.as_str().map(str::to_string)).collect()
+        }
+        None => {
+            warnings.push(format!("'{}' is not a BibTeX, CSL-YAML or CSL-JSON bibliography", name));
+            Vec::new()
+        }
+    };
+
+    let mut seen = HashSet::new();
+    for key in &keys {
+        if !seen.insert(key) {
+            warnings.push(format!("Duplicate key '{}'", key));
+        }
+    }
+
+    BibInfo {
+        entry_count: keys.len(),
+        keys,
+        format,
+        warnings,
+    }
+}
+
+/// Citation keys of BibTeX entries, skipping `@string`, `@preamble` and `@comment`.
+fn bibtex_keys(text: &str, warnings: &mut Vec<String>) -> Vec<String> {
+    let mut keys = Vec::new();
+    for caps in BIBTEX_KEYED_ENTRY.captures_iter(text) {
+        let entry_type = caps[1].to_ascii_lowercase();
+        if matches!(entry_type.as_str(), "string" | "preamble" | "comment") {
+            continue;
+        }
+        if caps[2].is_empty() {
+            let line = text[..caps.get(0).unwrap().start()].lines().count() + 1;
+            warnings.push(format!("Line {}: @{} entry has no key", line, &caps[1]));
+        } else {
+            keys.push(caps[2].to_string());
+        }
+    }
+
+    let depth = text.chars().fold(0isize, |depth, c| match c {
+        '{' => depth + 1,
+        '}' => depth - 1,
+        _ => depth,
+    });
+    if depth != 0 {
+        warnings.push("Unbalanced braces; some entries may be cut short".to_string());
+    }
+    keys
+}
+
+/// `id`s of CSL entries, warning about entries without one.
+fn csl_ids<'a>(ids: impl Iterator<Item = Option<&'a str>>, warnings: &mut Vec<String>) -> Vec<String> {
+    let mut keys = Vec::new();
+    for (index, id) in ids.enumerate() {
+        match id {
+            Some(id) => keys.push(id.to_string()),
+            None => warnings.push(format!("Entry {} has no id", index + 1)),
+        }
+    }
+    keys
+}
+
+/// Copy files referenced by relative paths in a CSL-YAML bibliography into the
+/// build directory, so they resolve from where the template runs.
+///
+/// Returns a warning for each reference that could not be resolved.
+fn copy_bibliography_references(source_dir: &Path, build_dir: &Path, bib_text: &str) -> Vec<String> {
+    let Ok(entries) = serde_yaml::from_str::<serde_yaml::Value>(bib_text) else {
+        return Vec::new();
+    };
+
+    let mut references = Vec::new();
+    collect_relative_paths(&entries, None, &mut references);
+
+    let mut warnings = Vec::new();
+    for reference in references {
+        let relative = Path::new(&reference);
+        if relative.components().any(|c| matches!(c, std::path::Component::ParentDir)) {
+            warnings.push(format!("Bibliography file reference '{}' points outside its folder", reference));
+            continue;
+        }
+        let source = source_dir.join(relative);
+        if !source.is_file() {
+            warnings.push(format!("Bibliography file reference '{}' could not be found", reference));
+            continue;
+        }
+        let dest = build_dir.join(relative);
+        let copied = dest
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::copy(&source, &dest));
+        if let Err(e) = copied {
+            warnings.push(format!("Failed to copy bibliography file '{}': {}", reference, e));
+        }
+    }
+    warnings
+}
+
+/// Collect string values that look like relative file paths (`abstract.txt`,
+/// `notes/paper.pdf`). Identifier fields such as DOIs and URLs are skipped.
+fn collect_relative_paths(value: &serde_yaml::Value, key: Option<&str>, found: &mut Vec<String>) {
+    const IDENTIFIER_FIELDS: [&str; 7] = ["id", "DOI", "URL", "ISBN", "ISSN", "PMID", "PMCID"];
+
+    match value {
+        serde_yaml::Value::Mapping(map) => {
+            for (field, nested) in map {
+                collect_relative_paths(nested, field.as_str(), found);
+            }
+        }
+        serde_yaml::Value::Sequence(items) => {
+            for item in items {
+                collect_relative_paths(item, key, found);
+            }
+        }
+        serde_yaml::Value::String(text) => {
+            if key.is_some_and(|k| IDENTIFIER_FIELDS.iter().any(|f| f.eq_ignore_ascii_case(k))) {
+                return;
+            }
+            let path = Path::new(text);
+            let has_file_extension = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| (1..=5).contains(&ext.len()) && ext.chars().all(|c| c.is_ascii_alphanumeric()));
+            let looks_like_path = has_file_extension
+                && !text.chars().any(char::is_whitespace)
+                && !text.contains("://")
+                && !path.is_absolute();
+            if looks_like_path && !found.contains(text) {
+                found.push(text.clone());
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Write bibliography bytes into the build directory as UTF-8, replacing any previous
+/// bibliography. Returns the sanitized filename that was written.
+pub(crate) fn store_bibliography(build_dir: &Path, name: &str, bib_bytes: &[u8]) -> Result<String, String> {
     // Ensure build directory exists
-    fs::create_dir_all(&build_dir).map_err(|e| e.to_string())?;
+    fs::create_dir_all(build_dir).map_err(|e| e.to_string())?;
 
     // Clean up ALL old bibliography files in .build to prevent bloat
     // We only ever need one bibliography file at a time
-    if let Ok(entries) = fs::read_dir(&build_dir) {
+    if let Ok(entries) = fs::read_dir(build_dir) {
         for entry in entries.flatten() {
             if let Some(ext) = entry.path().extension() {
                 if ext == "bib" || ext == "yml" || ext == "yaml" {
@@ -136,18 +764,365 @@ pub async fn import_bibliography_from_path(
         }
     }
 
-    // Use original filename
-    let orig_name = src
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("references.bib");
-    let filename = utils::sanitize_filename(orig_name);
-
+    let filename = utils::sanitize_filename(name);
     let dest_path = build_dir.join(&filename);
 
+    // Typst only reads UTF-8, so transcode files exported by older tools
+    let bib_text = decode_bibliography(bib_bytes);
+
     // Write new bibliography file to .build directory
-    fs::write(&dest_path, bib_bytes).map_err(|e| e.to_string())?;
+    fs::write(&dest_path, bib_text).map_err(|e| e.to_string())?;
 
     // Return just the filename (Typst will look in .build directory where template runs)
     Ok(filename)
 }
+
+/// Decode bibliography bytes to UTF-8 text.
+///
+/// Handles UTF-8 (with or without BOM) and BOM-marked UTF-16. Anything else is
+/// assumed to be Latin-1, which is what most legacy BibTeX tools emit.
+fn decode_bibliography(bytes: &[u8]) -> String {
+    if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return String::from_utf8_lossy(rest).into_owned();
+    }
+
+    let utf16_le = bytes.strip_prefix(&[0xFF, 0xFE]).map(|rest| (rest, true));
+    let utf16_be = bytes.strip_prefix(&[0xFE, 0xFF]).map(|rest| (rest, false));
+    if let Some((rest, little_endian)) = utf16_le.or(utf16_be) {
+        let units: Vec<u16> = rest
+            .chunks_exact(2)
+            .map(|pair| {
+                if little_endian {
+                    u16::from_le_bytes([pair[0], pair[1]])
+                } else {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                }
+            })
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        // Latin-1 maps each byte directly to the code point of the same value
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_svg_images() {
+        let dir = std::env::temp_dir().join(format!("tideflow-svg-{}", Uuid::new_v4()));
+        fs::create_dir_all(dir.join("img")).unwrap();
+        let red = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(2, 2, image::Rgb([255, 0, 0])));
+        let mut png = Vec::new();
+        red.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png).unwrap();
+        fs::write(dir.join("img").join("red.png"), &png).unwrap();
+
+        let embedded = format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(&png));
+        let escaping = format!("../{}/img/red.png", dir.file_name().unwrap().to_string_lossy());
+        let svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink">
+<image x="0" y="0" width="2" height="2" xlink:href="img/red.png"/>
+<image href='{}'/>
+<image href="missing.png"/>
+<image href="{}"/>
+<image href="{}"/>
+</svg>"#,
+            embedded,
+            escaping,
+            dir.join("img").join("red.png").display()
+        );
+        let (inlined, warnings) = inline_svg_images(&svg, &dir);
+
+        assert!(!inlined.contains("\"img/red.png\""));
+        assert_eq!(inlined.matches(embedded.as_str()).count(), 2);
+        // Existing files reached through `..` or an absolute path are left alone
+        assert!(inlined.contains(&format!("href=\"{}\"", escaping)));
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(warnings[0], "SVG image 'missing.png' could not be found");
+        assert_eq!(warnings[1], format!("SVG image '{}' points outside the SVG's folder", escaping));
+        assert!(warnings[2].ends_with("points outside the SVG's folder"));
+
+        // The file reference now carries the red raster itself
+        let href = inlined.split("xlink:href=\"").nth(1).unwrap().split('"').next().unwrap();
+        let payload = href.split_once(";base64,").unwrap().1;
+        let decoded = base64::engine::general_purpose::STANDARD.decode(payload).unwrap();
+        let pixel = image::load_from_memory(&decoded).unwrap().to_rgb8().get_pixel(1, 1).0;
+        assert_eq!(pixel, [255, 0, 0]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_store_latin1_bibliography_as_utf8() {
+        let build_dir = std::env::temp_dir().join(format!("tideflow-bib-{}", Uuid::new_v4()));
+        fs::create_dir_all(&build_dir).unwrap();
+        fs::write(build_dir.join("old.bib"), "@misc{old,}").unwrap();
+
+        // "Müller" and "Société" encoded as Latin-1
+        let mut latin1 = b"@book{muller2001,\n  author = {M".to_vec();
+        latin1.push(0xFC);
+        latin1.extend_from_slice(b"ller and Soci");
+        latin1.push(0xE9);
+        latin1.extend_from_slice(b"t");
+        latin1.push(0xE9);
+        latin1.extend_from_slice(b"},\n}\n");
+        assert!(std::str::from_utf8(&latin1).is_err());
+
+        let filename = store_bibliography(&build_dir, "refs (latin1).bib", &latin1).unwrap();
+        assert_eq!(filename, "refs-latin1-.bib");
+
+        let written = fs::read(build_dir.join(&filename)).unwrap();
+        let text = String::from_utf8(written).expect("written bibliography must be UTF-8");
+        assert!(text.contains("author = {Müller and Société}"));
+        assert!(!build_dir.join("old.bib").exists());
+
+        let _ = fs::remove_dir_all(&build_dir);
+    }
+
+    #[test]
+    fn test_inspect_bibliography() {
+        let dir = std::env::temp_dir().join(format!("tideflow-bib-inspect-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("refs.bib");
+        fs::write(
+            &path,
+            "@string{jr = \"Journal\"}\n\
+             @article{smith2020,\n  title = {A {Study}},\n  journal = jr,\n}\n\n\
+             @book{jones2021,\n  title = {Book},\n}\n\n\
+             @misc{smith2020,\n  note = {again},\n}\n",
+        )
+        .unwrap();
+
+        let info = inspect_bibliography_file(&path).unwrap();
+        assert_eq!(info.format, Some(BibFormat::BibTex));
+        assert_eq!(info.entry_count, 3);
+        assert_eq!(info.keys, vec!["smith2020", "jones2021", "smith2020"]);
+        assert_eq!(info.warnings, vec!["Duplicate key 'smith2020'".to_string()]);
+        // Nothing is imported
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let info = inspect_bibliography_text("refs.yml", "- id: a\n  title: A\n- title: No id\n");
+        assert_eq!(info.format, Some(BibFormat::CslYaml));
+        assert_eq!(info.keys, vec!["a"]);
+        assert_eq!(info.warnings, vec!["Entry 2 has no id".to_string()]);
+
+        let info = inspect_bibliography_text("refs.json", r#"[{"id": "b", "type": "book"}]"#);
+        assert_eq!(info.format, Some(BibFormat::CslJson));
+        assert_eq!(info.keys, vec!["b"]);
+
+        assert_eq!(inspect_bibliography_text("refs.bib", "not a bibliography").format, None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_copy_bibliography_sibling_files() {
+        let root = std::env::temp_dir().join(format!("tideflow-bib-refs-{}", Uuid::new_v4()));
+        let source_dir = root.join("library");
+        let build_dir = root.join(".build");
+        fs::create_dir_all(source_dir.join("abstracts")).unwrap();
+        fs::create_dir_all(&build_dir).unwrap();
+        fs::write(source_dir.join("abstracts").join("smith.txt"), "Abstract text").unwrap();
+
+        let bib = "- id: smith2020\n  title: A study of things.\n  DOI: 10.1000/xyz.123\n  abstract-file: abstracts/smith.txt\n\
+                   - id: jones2021\n  note: missing.pdf\n";
+        let warnings = copy_bibliography_references(&source_dir, &build_dir, bib);
+
+        assert_eq!(fs::read_to_string(build_dir.join("abstracts").join("smith.txt")).unwrap(), "Abstract text");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("missing.pdf"));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_import_reuses_identical_nested_asset() {
+        let root = std::env::temp_dir().join(format!("tideflow-assets-{}", Uuid::new_v4()));
+        let assets_dir = root.join("assets");
+        let figures = assets_dir.join("figures").join("2024");
+        fs::create_dir_all(&figures).unwrap();
+        fs::create_dir_all(assets_dir.join(".build")).unwrap();
+        fs::write(figures.join("plot.png"), [0x89, b'P', b'N', b'G', 1, 2, 3]).unwrap();
+        fs::write(assets_dir.join(".build").join("copy.png"), [0x89, b'P', b'N', b'G', 9]).unwrap();
+        fs::write(assets_dir.join("other.png"), [0x89, b'P', b'N', b'G', 1, 2, 4]).unwrap();
+
+        let path = copy_image_into_assets(&assets_dir, "renamed.png", &[0x89, b'P', b'N', b'G', 1, 2, 3], false, None).unwrap();
+        assert_eq!(path, "assets/figures/2024/plot.png");
+        assert!(!assets_dir.join("renamed.png").exists());
+
+        // Content only present under .build is not reused
+        let path = copy_image_into_assets(&assets_dir, "copy.png", &[0x89, b'P', b'N', b'G', 9], false, None).unwrap();
+        assert_eq!(path, "assets/copy.png");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_import_with_force_name_writes_alias() {
+        let assets_dir = std::env::temp_dir().join(format!("tideflow-alias-{}", Uuid::new_v4())).join("assets");
+        let logo = [0x89, b'P', b'N', b'G', 7, 7, 7];
+
+        assert_eq!(copy_image_into_assets(&assets_dir, "logo.png", &logo, false, None).unwrap(), "assets/logo.png");
+        assert_eq!(copy_image_into_assets(&assets_dir, "company-logo.png", &logo, false, None).unwrap(), "assets/logo.png");
+        assert!(!assets_dir.join("company-logo.png").exists());
+
+        let path = copy_image_into_assets(&assets_dir, "company-logo.png", &logo, true, None).unwrap();
+        assert_eq!(path, "assets/company-logo.png");
+        assert_eq!(fs::read(assets_dir.join("company-logo.png")).unwrap(), logo);
+        // Forcing the name of the existing copy does not duplicate it
+        assert_eq!(copy_image_into_assets(&assets_dir, "logo.png", &logo, true, None).unwrap(), "assets/logo.png");
+        assert_eq!(fs::read_dir(&assets_dir).unwrap().count(), 2);
+
+        let _ = fs::remove_dir_all(assets_dir.parent().unwrap());
+    }
+
+    #[test]
+    fn test_import_preserving_mtime() {
+        let root = std::env::temp_dir().join(format!("tideflow-mtime-{}", Uuid::new_v4()));
+        let assets_dir = root.join("assets");
+        fs::create_dir_all(&root).unwrap();
+        let src = root.join("old.png");
+        fs::write(&src, [0x89, b'P', b'N', b'G', 4, 2]).unwrap();
+        let source_mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000);
+        fs::File::options().write(true).open(&src).unwrap().set_modified(source_mtime).unwrap();
+
+        let path = import_image_file_in(&src, &assets_dir, None, false, true).unwrap().path;
+        assert_eq!(path, "assets/old.png");
+        let imported = fs::metadata(assets_dir.join("old.png")).unwrap().modified().unwrap();
+        assert_eq!(imported, source_mtime);
+
+        // Default behaviour stamps the import time
+        let other = root.join("other.png");
+        fs::write(&other, [0x89, b'P', b'N', b'G', 5, 3]).unwrap();
+        fs::File::options().write(true).open(&other).unwrap().set_modified(source_mtime).unwrap();
+        let path = import_image_file_in(&other, &assets_dir, None, false, false).unwrap().path;
+        let copy = fs::metadata(root.join(&path)).unwrap().modified().unwrap();
+        assert!(copy > source_mtime);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_extract_tiff_pages() {
+        let mut tiff_bytes = Vec::new();
+        {
+            let mut encoder = tiff::encoder::TiffEncoder::new(Cursor::new(&mut tiff_bytes)).unwrap();
+            encoder.write_image::<tiff::encoder::colortype::Gray8>(2, 2, &[10; 4]).unwrap();
+            encoder.write_image::<tiff::encoder::colortype::Gray8>(2, 2, &[200; 4]).unwrap();
+        }
+
+        let first = extract_tiff_page(&tiff_bytes, 1).unwrap().unwrap();
+        let second = extract_tiff_page(&tiff_bytes, 2).unwrap().unwrap();
+        assert_ne!(first, second);
+        let first = image::load_from_memory(&first).unwrap().to_luma8();
+        let second = image::load_from_memory(&second).unwrap().to_luma8();
+        assert_eq!(first.get_pixel(0, 0).0, [10]);
+        assert_eq!(second.get_pixel(1, 1).0, [200]);
+        assert!(extract_tiff_page(&tiff_bytes, 3).is_err());
+
+        // Single-page TIFFs and other formats are imported unchanged
+        let mut single = Vec::new();
+        tiff::encoder::TiffEncoder::new(Cursor::new(&mut single))
+            .unwrap()
+            .write_image::<tiff::encoder::colortype::Gray8>(1, 1, &[0])
+            .unwrap();
+        assert_eq!(extract_tiff_page(&single, 2).unwrap(), None);
+        assert_eq!(extract_tiff_page(&[0x89, 0x50, 0x4E, 0x47], 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_import_image_appends_detected_extension() {
+        let root = std::env::temp_dir().join(format!("tideflow-import-{}", Uuid::new_v4()));
+        let assets_dir = root.join("assets");
+        let png = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+        let path = save_image_bytes(&assets_dir, Some("diagram".to_string()), &png).unwrap();
+        assert_eq!(path, "assets/diagram.png");
+        assert!(assets_dir.join("diagram.png").exists());
+
+        // Existing image extensions are kept, even if they differ from the detected format
+        assert_eq!(with_image_extension("photo.JPEG".to_string(), &png), "photo.JPEG");
+        assert_eq!(with_image_extension("my-photo".to_string(), &[0xFF, 0xD8, 0xFF, 0xE0]), "my-photo.jpg");
+        assert_eq!(with_image_extension("notes.v2".to_string(), &png), "notes.v2.png");
+        assert_eq!(with_image_extension("unknown".to_string(), b"text"), "unknown");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_import_into_renamed_assets_dir() {
+        let root = std::env::temp_dir().join(format!("tideflow-media-{}", Uuid::new_v4()));
+        let media_dir = root.join("media");
+        let png = [0x89, 0x50, 0x4E, 0x47, 7, 7];
+
+        assert_eq!(save_image_bytes(&media_dir, Some("chart.png".to_string()), &png).unwrap(), "media/chart.png");
+        // Re-importing the same bytes from disk links to the existing copy
+        assert_eq!(copy_image_into_assets(&media_dir, "other.png", &png, false, None).unwrap(), "media/chart.png");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_decode_bibliography_utf8_and_bom() {
+        assert_eq!(decode_bibliography("Müller".as_bytes()), "Müller");
+        assert_eq!(decode_bibliography(b"\xEF\xBB\xBF@misc{a,}"), "@misc{a,}");
+        assert_eq!(decode_bibliography(&[0xFF, 0xFE, b'@', 0, b'a', 0]), "@a");
+    }
+
+    /// Serve one canned HTTP response on a local port and return its URL prefix.
+    fn serve_once(response: Vec<u8>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            use std::io::Write;
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(&response);
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    fn http_response(content_type: &str, content_length: usize, body: &str) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            content_type, content_length, body
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_download_bibliography() {
+        let build_dir = std::env::temp_dir().join(format!("tideflow-bib-url-{}", Uuid::new_v4()));
+        let body = "\u{feff}@article{knuth1984,\n  title = {Literate Programming},\n}\n";
+        let url = serve_once(http_response("text/x-bibtex; charset=utf-8", body.len(), body));
+
+        let filename = download_bibliography_in(&format!("{}/shared/team.bib?raw=1", url), &build_dir).unwrap();
+        assert_eq!(filename, "team.bib");
+        let stored = fs::read_to_string(build_dir.join(&filename)).unwrap();
+        assert!(stored.starts_with("@article{knuth1984,"), "BOM is stripped");
+
+        // An HTML error page is not a bibliography
+        let url = serve_once(http_response("text/html", 13, "<h1>404</h1>\n"));
+        let err = download_bibliography_in(&format!("{}/refs.bib", url), &build_dir).unwrap_err();
+        assert!(err.contains("text/html"), "{}", err);
+
+        // Plain text that is not BibTeX is rejected too
+        let url = serve_once(http_response("text/plain", 9, "Not found"));
+        assert!(download_bibliography_in(&format!("{}/refs.bib", url), &build_dir).is_err());
+
+        // Oversized responses are refused from their declared length
+        let url = serve_once(http_response("text/plain", 64 * 1024 * 1024, "@misc{a,}"));
+        let err = download_bibliography_in(&format!("{}/refs.bib", url), &build_dir).unwrap_err();
+        assert!(err.contains("larger"), "{}", err);
+
+        assert_eq!(fs::read_to_string(build_dir.join("team.bib")).unwrap(), stored);
+        let _ = fs::remove_dir_all(&build_dir);
+    }
+}
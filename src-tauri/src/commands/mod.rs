@@ -6,9 +6,11 @@
 //! - `render_ops`: Markdown/Typst compilation to PDF
 //! - `cache_ops`: Cache management and cleanup
 //! - `debug_ops`: Diagnostics and debugging utilities
+//! - `document_ops`: Document preparation and analysis
 
 pub mod cache_ops;
 pub mod debug_ops;
+pub mod document_ops;
 pub mod file_ops;
 pub mod image_ops;
 pub mod render_ops;
@@ -16,6 +18,7 @@ pub mod render_ops;
 // Re-export all commands for convenient registration
 pub use cache_ops::*;
 pub use debug_ops::*;
+pub use document_ops::*;
 pub use file_ops::*;
 pub use image_ops::*;
 pub use render_ops::*;
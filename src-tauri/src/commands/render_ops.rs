@@ -87,8 +87,10 @@ pub async fn render_typst(
     content: &str,
     format: &str,
     current_file: Option<&str>,
+    preview_mode: Option<bool>,
 ) -> Result<RenderedDocument, String> {
-    match renderer::render_typst(&app_handle, content, format, current_file).await {
+    let preview_mode = preview_mode.unwrap_or(false);
+    match renderer::render_typst(&app_handle, content, format, current_file, preview_mode).await {
         Ok(document) => {
             let _ = app_handle.emit("compiled", &document);
             Ok(document)
@@ -93,6 +93,14 @@ pub enum AppError {
     Other(#[from] anyhow::Error),
 }
 
+/// Errors that stop the markdown preprocessor before it transforms anything.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PreprocessError {
+    /// Input over [`crate::preprocessor::PreprocessOptions::max_input_bytes`]
+    #[error("Document is too large to preprocess ({size} bytes, limit {limit} bytes)")]
+    TooLarge { size: usize, limit: usize },
+}
+
 /// Result type alias using AppError
 pub type AppResult<T> = Result<T, AppError>;
 
@@ -2,7 +2,7 @@ mod commands;
 mod error;
 mod image_export;
 mod preferences;
-mod preprocessor;
+pub mod preprocessor;
 mod render_pipeline;
 mod renderer;
 mod utils;
@@ -42,8 +42,12 @@ pub fn run() {
     .plugin(tauri_plugin_shell::init())
 
     .setup(|app| {
-        // Initialize app directories if needed
         let app_handle = app.handle();
+        // The assets directory name is app state, restored from preferences
+        let assets_dir_name = preferences::load_preferences(app_handle).assets_dir_name;
+        app.manage(utils::AssetsDirName::new(&assets_dir_name));
+
+        // Initialize app directories if needed
         utils::initialize_app_directories(app_handle)?;
 
         Ok(())
@@ -60,6 +64,10 @@ pub fn run() {
         commands::import_image,
         commands::import_image_from_path,
         commands::import_bibliography_from_path,
+        commands::import_bibliography_from_url,
+        commands::inspect_bibliography,
+        commands::get_assets_dir_name,
+        commands::set_assets_dir_name,
         commands::render_markdown,
         commands::export_markdown,
         commands::save_pdf_as,
@@ -73,8 +81,20 @@ pub fn run() {
         commands::get_runtime_files,
         commands::cleanup_temp_pdfs,
         commands::cleanup_unused_assets,
+        commands::compact_build_dir,
+        commands::preview_cache_eviction,
+        commands::evict_render_cache,
         commands::clear_bibliography,
         commands::open_pdf_in_viewer,
+        commands::prepare_document,
+        commands::get_document_stats,
+        commands::slugify_heading,
+        commands::diff_documents,
+        commands::validate_document,
+        commands::preprocess_project,
+        commands::build_asset_manifest,
+        commands::anchor_type_breakdown,
+        commands::estimate_page_count,
         preferences::get_preferences,
         preferences::set_preferences,
         preferences::apply_preferences
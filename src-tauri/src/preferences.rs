@@ -1,3 +1,6 @@
+use crate::preprocessor::{
+    AnchorSyntax, EmptyHeadingMode, HeadingLabelPlacement, MultiCitationMode, RawHtmlMode, RuleAnchorMode,
+};
 use crate::utils;
 use anyhow::Result;
 use lazy_static::lazy_static;
@@ -5,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, Manager};
 
 // Global monotonically increasing version for preference writes
 lazy_static! {
@@ -158,6 +161,56 @@ pub struct Preferences {
     /// Show all bibliography entries (true) or only cited works (false)
     #[serde(default)]
     pub bibliography_show_all: Option<bool>,
+
+    /// Underline citations so their link to the bibliography entry is visible
+    #[serde(default)]
+    pub link_citations: bool,
+
+    /// List the pages citing each source after the document, linked back to the citations
+    #[serde(default)]
+    pub citation_backrefs: bool,
+
+    /// How `[@a; @b]` citation groups are emitted
+    #[serde(default)]
+    pub multi_citation_mode: MultiCitationMode,
+
+    // Preprocessing settings
+
+    /// Where heading labels go: "end_of_line" or "next_line"
+    #[serde(default)]
+    pub heading_label_placement: HeadingLabelPlacement,
+
+    /// Syntax of injected anchor labels: "raw_typst_comment" or "metadata"
+    #[serde(default)]
+    pub anchor_syntax: AnchorSyntax,
+
+    /// Which horizontal rules get sync anchors: "all", "off" or "section_separators"
+    #[serde(default)]
+    pub rule_anchors: RuleAnchorMode,
+
+    /// Raw HTML blocks: "pass_through", "strip" or "escape"
+    #[serde(default)]
+    pub raw_html: RawHtmlMode,
+
+    /// Headings without sluggable text: "skip", "fallback_id" or "error"
+    #[serde(default)]
+    pub empty_headings: EmptyHeadingMode,
+
+    /// Convert `[^1]` footnotes into explicit Typst footnote calls
+    #[serde(default)]
+    pub explicit_footnotes: bool,
+
+    /// Convert LaTeX `\label{x}` / `\ref{x}` into Typst labels and references
+    #[serde(default)]
+    pub latex_cross_refs: bool,
+
+    /// Render local images at this Typst width (e.g. "100%") so they cannot overflow the page
+    #[serde(default)]
+    pub max_image_width: Option<String>,
+
+    /// Name of the assets subdirectory inside the content directory (e.g. "assets", "media")
+    #[serde(default = "default_assets_dir_name")]
+    pub assets_dir_name: String,
 }
 
 /// Page margin configuration
@@ -218,6 +271,10 @@ fn default_page_orientation() -> String {
     "portrait".to_string()
 }
 
+fn default_assets_dir_name() -> String {
+    utils::DEFAULT_ASSETS_DIR_NAME.to_string()
+}
+
 impl Default for Preferences {
     fn default() -> Self {
         Self {
@@ -266,6 +323,18 @@ impl Default for Preferences {
             bibliography_style: Some("ieee".to_string()),
             bibliography_title: None,
             bibliography_show_all: Some(false),
+            link_citations: false,
+            citation_backrefs: false,
+            multi_citation_mode: MultiCitationMode::default(),
+            heading_label_placement: HeadingLabelPlacement::default(),
+            anchor_syntax: AnchorSyntax::default(),
+            rule_anchors: RuleAnchorMode::default(),
+            raw_html: RawHtmlMode::default(),
+            empty_headings: EmptyHeadingMode::default(),
+            explicit_footnotes: false,
+            latex_cross_refs: false,
+            max_image_width: None,
+            assets_dir_name: default_assets_dir_name(),
         }
     }
 }
@@ -307,6 +376,11 @@ pub async fn set_preferences(
     app_handle: AppHandle,
     preferences: Preferences,
 ) -> Result<(), String> {
+    if let Some(assets_dir_name) = app_handle.try_state::<utils::AssetsDirName>() {
+        assets_dir_name
+            .set(&preferences.assets_dir_name)
+            .map_err(|e| e.to_string())?;
+    }
     save_preferences_to_file(&app_handle, &preferences)?;
     apply_preferences_internal(&app_handle, &preferences)
 }
@@ -317,6 +391,35 @@ pub async fn apply_preferences(app_handle: AppHandle) -> Result<(), String> {
     apply_preferences_internal(&app_handle, &preferences)
 }
 
+/// Saved preferences, or the defaults when none are saved or they cannot be parsed.
+/// Unlike [`get_preferences`] this neither creates the file nor emits events.
+pub(crate) fn load_preferences(app_handle: &AppHandle) -> Preferences {
+    get_preferences_path(app_handle)
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// Apply `update` to the saved preferences and write them back. Fails rather than
+/// overwriting a preferences file that cannot be parsed.
+pub(crate) fn update_preferences(
+    app_handle: &AppHandle,
+    update: impl FnOnce(&mut Preferences),
+) -> Result<(), String> {
+    let prefs_path = get_preferences_path(app_handle)?;
+    let mut preferences = if prefs_path.exists() {
+        let prefs_content = fs::read_to_string(&prefs_path)
+            .map_err(|e| format!("Failed to read preferences: {}", e))?;
+        serde_json::from_str(&prefs_content)
+            .map_err(|e| format!("Failed to parse preferences: {}", e))?
+    } else {
+        Preferences::default()
+    };
+    update(&mut preferences);
+    save_preferences_to_file(app_handle, &preferences)
+}
+
 fn get_preferences_path(app_handle: &AppHandle) -> Result<PathBuf, String> {
     let content_dir = utils::get_content_dir(app_handle).map_err(|e| e.to_string())?;
     Ok(content_dir.join("prefs.json"))
@@ -353,3 +456,32 @@ fn apply_preferences_internal(
     // The template will read this file directly
     save_preferences_to_file(app_handle, preferences)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preprocessing_preferences() {
+        let mut json = serde_json::to_value(Preferences::default()).unwrap();
+        for key in ["rule_anchors", "raw_html", "explicit_footnotes", "max_image_width", "assets_dir_name"] {
+            json.as_object_mut().unwrap().remove(key);
+        }
+
+        // Preferences saved before these settings existed get the defaults
+        let old: Preferences = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(old.rule_anchors, RuleAnchorMode::All);
+        assert_eq!(old.raw_html, RawHtmlMode::PassThrough);
+        assert!(!old.explicit_footnotes);
+        assert_eq!(old.max_image_width, None);
+        assert_eq!(old.assets_dir_name, "assets");
+
+        json["rule_anchors"] = "section_separators".into();
+        json["raw_html"] = "escape".into();
+        json["heading_label_placement"] = "next_line".into();
+        let set: Preferences = serde_json::from_value(json).unwrap();
+        assert_eq!(set.rule_anchors, RuleAnchorMode::SectionSeparators);
+        assert_eq!(set.raw_html, RawHtmlMode::Escape);
+        assert_eq!(set.heading_label_placement, HeadingLabelPlacement::NextLine);
+    }
+}
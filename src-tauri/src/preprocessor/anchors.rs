@@ -5,52 +5,188 @@
 
 use anyhow::Result;
 use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+use regex::Regex;
 use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::ops::Range;
 
-use super::types::{offset_to_line_column, AnchorMeta, PreprocessorOutput};
+use super::normalize::PAGEBREAK_MARKUP;
+use super::types::{
+    is_offset_anchor, offset_to_line_column, AnchorMeta, AnchorSyntax, CodeBlockInfo, EditorPosition, EmptyHeadingMode, HeadingLabelPlacement, PreprocessOptions,
+    PreprocessorOutput, RuleAnchorMode, SlugSuffixStyle,
+};
+
+/// Heading class (`# Title {.no-sync}`) that opts a heading out of scroll sync.
+const NO_SYNC_CLASS: &str = "no-sync";
+
+/// Tables with more columns than this likely overflow a portrait page.
+const WIDE_TABLE_COLUMNS: usize = 6;
+
+/// Bytes around a focus range whose anchors are recomputed rather than carried forward.
+pub const FOCUS_PADDING: usize = 256;
+
+lazy_static::lazy_static! {
+    /// Path of a Typst `#image("...")` call, possibly inside `#figure(..)`, in a
+    /// raw-typst comment.
+    static ref RAW_IMAGE_RE: Regex = Regex::new(r#"#(?:figure\(\s*)?image\(\s*"([^"]+)""#).unwrap();
+}
+
+/// Characters of element text kept in an anchor preview.
+const PREVIEW_CHARS: usize = 40;
+
+/// Label on the per-heading `#metadata` emitted for the PDF outline.
+const HEADING_METADATA_LABEL: &str = "tf-heading";
 
 /// Inject Typst anchors into markdown for scroll synchronization.
 pub fn inject_anchors(markdown: &str) -> Result<PreprocessorOutput> {
-    let mut ctx = InjectionContext::new(markdown);
+    inject_anchors_with_options(markdown, &PreprocessOptions::default())
+}
+
+/// Inject Typst anchors, honouring the anchor-related [`PreprocessOptions`].
+pub fn inject_anchors_with_options(
+    markdown: &str,
+    options: &PreprocessOptions,
+) -> Result<PreprocessorOutput> {
+    let mut ctx = InjectionContext::new(markdown, options);
     ctx.process()?;
     ctx.build_output(markdown)
 }
 
+/// Inject Typst anchors, carrying forward `previous` anchors outside `focus`.
+///
+/// Anchors outside `focus` (widened by [`FOCUS_PADDING`] bytes either side) keep
+/// the id and position they had in `previous` when one exists at the same offset,
+/// so only the edited region is recomputed.
+pub fn inject_anchors_with_previous(
+    markdown: &str,
+    options: &PreprocessOptions,
+    focus: Range<usize>,
+    previous: &[AnchorMeta],
+) -> Result<PreprocessorOutput> {
+    let window = focus.start.saturating_sub(FOCUS_PADDING)..focus.end.saturating_add(FOCUS_PADDING);
+    let mut ctx = InjectionContext::new(markdown, options);
+    ctx.carried = previous
+        .iter()
+        .filter(|anchor| !window.contains(&anchor.offset))
+        .map(|anchor| (anchor.offset, anchor.clone()))
+        .collect();
+    ctx.process()?;
+    ctx.build_output(markdown)
+}
+
+/// Inject Typst anchors, streaming the anchored markdown into `out`.
+///
+/// Returns the anchor metadata; the written bytes match [`inject_anchors_with_options`].
+pub fn write_anchored<W: Write>(
+    markdown: &str,
+    options: &PreprocessOptions,
+    out: W,
+) -> Result<Vec<AnchorMeta>> {
+    let mut ctx = InjectionContext::new(markdown, options);
+    ctx.process()?;
+    ctx.write_output(markdown, out)
+}
+
 /// Context for anchor injection, tracking state during markdown parsing.
 struct InjectionContext<'a> {
     markdown: &'a str,
     insertions: Vec<(usize, String)>,
     anchors: Vec<AnchorMeta>,
     seen_offsets: HashSet<usize>,
+    /// Previous anchors outside the focus range, by source offset
+    carried: HashMap<usize, AnchorMeta>,
+    /// Footnote label -> position of its definition
+    footnotes: HashMap<String, EditorPosition>,
+    /// Code anchor id -> fence info of its block
+    code_blocks: HashMap<String, CodeBlockInfo>,
     
     // Heading tracking
     current_heading_text: String,
     current_heading_explicit_id: Option<String>,
+    current_heading_no_sync: bool,
+    current_heading_level: usize,
     in_heading: bool,
+    /// Nesting depth of images inside the current heading, whose alt text is not slugged
+    heading_image_depth: usize,
+    stable_heading_ids: bool,
+    heading_label_placement: HeadingLabelPlacement,
+    heading_metadata: bool,
+    anchor_syntax: AnchorSyntax,
     slug_counts: HashMap<String, usize>,
+    slug_suffix: SlugSuffixStyle,
+    empty_headings: EmptyHeadingMode,
+    empty_heading_count: usize,
+    
+    // Blockquote tracking
+    quote_anchor_threshold: Option<usize>,
+    blockquote_depth: usize,
+    quote_count: usize,
+    admonition_count: usize,
+    /// Paragraphs anchored so far in the current long blockquote, if inside one
+    long_quote_paragraphs: Option<usize>,
     
     // Element counters
+    /// Nesting depth of lists; block anchors inside lists would split them
+    list_depth: usize,
     table_depth: usize,
+    /// Layout suggestions collected while walking the document
+    warnings: Vec<String>,
+    /// Source offset and anchor index of the last generic paragraph anchor,
+    /// so an image opening that paragraph can claim it
+    last_paragraph_anchor: Option<(usize, usize)>,
     code_block_count: usize,
+    /// Language assumed for fences without an info string
+    default_code_lang: Option<String>,
     image_count: usize,
     hr_count: usize,
+    rule_anchors: RuleAnchorMode,
+    pagebreak_count: usize,
+    /// Emit `tf-doc-end` after the last block
+    doc_end_anchor: bool,
+    anchor_previews: bool,
 }
 
 impl<'a> InjectionContext<'a> {
-    fn new(markdown: &'a str) -> Self {
+    fn new(markdown: &'a str, options: &PreprocessOptions) -> Self {
         Self {
             markdown,
             insertions: Vec::new(),
             anchors: Vec::new(),
             seen_offsets: HashSet::new(),
+            carried: HashMap::new(),
+            footnotes: HashMap::new(),
+            code_blocks: HashMap::new(),
             current_heading_text: String::new(),
             current_heading_explicit_id: None,
+            current_heading_no_sync: false,
+            current_heading_level: 1,
             in_heading: false,
+            heading_image_depth: 0,
+            stable_heading_ids: options.stable_heading_ids,
+            heading_label_placement: options.heading_label_placement,
+            heading_metadata: options.heading_metadata,
+            anchor_syntax: options.anchor_syntax,
             slug_counts: HashMap::new(),
+            slug_suffix: options.slug_suffix.clone(),
+            empty_headings: options.empty_headings,
+            empty_heading_count: 0,
+            quote_anchor_threshold: options.quote_anchor_threshold,
+            blockquote_depth: 0,
+            quote_count: 0,
+            admonition_count: 0,
+            long_quote_paragraphs: None,
+            list_depth: 0,
             table_depth: 0,
+            warnings: Vec::new(),
+            last_paragraph_anchor: None,
             code_block_count: 0,
+            default_code_lang: options.default_code_lang.clone(),
             image_count: 0,
             hr_count: 0,
+            rule_anchors: options.rule_anchors,
+            pagebreak_count: 0,
+            doc_end_anchor: options.doc_end_anchor,
+            anchor_previews: options.anchor_previews,
         }
     }
 
@@ -72,32 +208,70 @@ impl<'a> InjectionContext<'a> {
         for (event, range) in parser.into_offset_iter() {
             self.handle_event(event, range);
         }
+
+        if self.doc_end_anchor {
+            self.add_doc_end_anchor();
+        }
+
+        if self.anchor_previews {
+            for anchor in &mut self.anchors {
+                anchor.preview = anchor_preview(self.markdown, anchor.offset);
+            }
+        }
         
         Ok(())
     }
 
     fn add_doc_start_anchor(&mut self) {
         let id = "tf-doc-start".to_string();
-        let markup = build_anchor_markup(self.markdown, 0, &id, false);
+        let markup = build_anchor_markup(self.markdown, 0, &id, 0, false, self.anchor_syntax);
         self.insertions.push((0, markup));
         self.anchors.push(AnchorMeta {
             id,
             offset: 0,
             line: 0,
             column: 0,
+            preview: None,
+            seq: 0,
         });
         self.seen_offsets.insert(0);
     }
 
+    /// Anchor after the last block, so positions in trailing content interpolate
+    /// between the last block and the end of the document.
+    fn add_doc_end_anchor(&mut self) {
+        let offset = self.markdown.len();
+        if self.seen_offsets.contains(&offset) {
+            return;
+        }
+        let (line, column) = self.position(offset);
+        let markup = build_anchor_markup(self.markdown, offset, "tf-doc-end", line, false, self.anchor_syntax);
+        self.insertions.push((offset, markup));
+        self.anchors.push(AnchorMeta {
+            id: "tf-doc-end".to_string(),
+            offset,
+            line,
+            column,
+            preview: None,
+            seq: 0,
+        });
+        self.seen_offsets.insert(offset);
+    }
+
     fn handle_event(&mut self, event: Event, range: std::ops::Range<usize>) {
         match event {
-            Event::Start(Tag::Heading(_, id, _)) => {
+            Event::Start(Tag::Heading(level, id, classes)) => {
                 self.current_heading_text.clear();
+                self.current_heading_level = level as usize;
                 self.in_heading = true;
                 self.current_heading_explicit_id = id.map(|s| s.to_string());
+                // `{.no-sync}` keeps the heading linkable but out of scroll sync
+                self.current_heading_no_sync = classes.contains(&NO_SYNC_CLASS);
             }
             
-            Event::Text(text) if self.in_heading => {
+            // Link text and emphasized text count toward the slug; image alt text does
+            // not, matching GitHub, which slugs the rendered text content
+            Event::Text(text) if self.in_heading && self.heading_image_depth == 0 => {
                 self.current_heading_text.push_str(&text);
             }
             
@@ -109,8 +283,28 @@ impl<'a> InjectionContext<'a> {
                 self.handle_horizontal_rule(range);
             }
             
-            Event::Start(Tag::Table(_)) 
-            | Event::Start(Tag::TableHead) 
+            Event::Html(html) if html.trim() == PAGEBREAK_MARKUP => {
+                self.handle_page_break(range);
+            }
+            
+            Event::Html(html) if html.trim_start().starts_with("<!--raw-typst") => {
+                if let Some(caps) = RAW_IMAGE_RE.captures(&html) {
+                    self.handle_raw_typst_image(&caps[1], range);
+                }
+            }
+            
+            Event::Start(Tag::Table(alignments)) => {
+                self.table_depth = self.table_depth.saturating_add(1);
+                if alignments.len() > WIDE_TABLE_COLUMNS {
+                    self.warnings.push(format!(
+                        "A table with {} columns may be too wide for a portrait page; \
+                         consider landscape orientation or a smaller font",
+                        alignments.len()
+                    ));
+                }
+            }
+
+            Event::Start(Tag::TableHead) 
             | Event::Start(Tag::TableRow) 
             | Event::Start(Tag::TableCell) => {
                 self.table_depth = self.table_depth.saturating_add(1);
@@ -124,10 +318,60 @@ impl<'a> InjectionContext<'a> {
                 self.in_heading = false;
             }
             
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                let (line, column) = self.position(range.start);
+                self.footnotes.insert(
+                    label.to_string(),
+                    EditorPosition {
+                        offset: range.start,
+                        line,
+                        column,
+                    },
+                );
+                self.handle_start_tag(Tag::FootnoteDefinition(label), range);
+            }
+            
+            Event::Start(Tag::List(_)) => {
+                self.list_depth += 1;
+            }
+            
+            Event::End(Tag::List(_)) => {
+                self.list_depth = self.list_depth.saturating_sub(1);
+                self.in_heading = false;
+            }
+            
+            Event::Start(Tag::BlockQuote) => {
+                self.handle_blockquote_start(range);
+            }
+            
+            Event::End(Tag::BlockQuote) => {
+                self.blockquote_depth = self.blockquote_depth.saturating_sub(1);
+                if self.blockquote_depth == 0 {
+                    self.long_quote_paragraphs = None;
+                }
+                self.in_heading = false;
+            }
+            
+            Event::End(Tag::Image(..)) if self.in_heading => {
+                self.heading_image_depth = self.heading_image_depth.saturating_sub(1);
+            }
+            
+            // Inline markup closing inside a heading does not end it
+            Event::End(tag) if !is_block_level(&tag) => {}
+            
             Event::End(_) => {
                 self.in_heading = false;
             }
             
+            // Images are inline, so they are handled before the block-level filter.
+            // Reference-style images arrive here with their definition already resolved.
+            Event::Start(Tag::Image(_, dest, _)) => {
+                if self.in_heading {
+                    self.heading_image_depth += 1;
+                }
+                self.handle_image(&dest, range);
+            }
+            
             Event::Start(tag) => {
                 self.handle_start_tag(tag, range);
             }
@@ -137,57 +381,138 @@ impl<'a> InjectionContext<'a> {
     }
 
     fn handle_heading_end(&mut self, range: std::ops::Range<usize>) {
-        let base_slug = if let Some(ref id) = self.current_heading_explicit_id {
-            id.clone()
-        } else {
-            slugify(&self.current_heading_text)
+        // In stable-id mode the explicit id only drives sync; links follow the text
+        let stable_id = self
+            .current_heading_explicit_id
+            .clone()
+            .filter(|_| self.stable_heading_ids);
+        let mut base_slug = match (&self.current_heading_explicit_id, &stable_id) {
+            (Some(id), None) => id.clone(),
+            (Some(id), Some(_)) if slugify(&self.current_heading_text).is_empty() => id.clone(),
+            _ => slugify(&self.current_heading_text),
         };
         
         if base_slug.is_empty() {
-            self.in_heading = false;
-            self.current_heading_explicit_id = None;
-            return;
+            self.empty_heading_count += 1;
+            if self.empty_headings == EmptyHeadingMode::Error {
+                let source = self.markdown[range.clone()].lines().next().unwrap_or("").trim();
+                self.warnings.push(format!(
+                    "Heading `{}` has no text to link to, so it gets no anchor",
+                    source
+                ));
+            }
+            if self.empty_headings != EmptyHeadingMode::FallbackId {
+                self.in_heading = false;
+                self.current_heading_explicit_id = None;
+                self.current_heading_no_sync = false;
+                return;
+            }
+            base_slug = format!("tf-heading-{}", self.empty_heading_count);
         }
         
-        // Handle duplicate slugs GitHub-style
+        // Handle duplicate slugs (GitHub-style `-1`, `-2` by default)
         let count = self.slug_counts.entry(base_slug.clone()).or_insert(0);
         let slug = if *count == 0 {
             base_slug.clone()
         } else {
-            format!("{}-{}", base_slug, count)
+            let suffix = &self.slug_suffix;
+            format!("{}{}{}", base_slug, suffix.separator, suffix.start + *count - 1)
         };
         *count += 1;
 
-        let insertion_point = self.find_line_end(range.start);
+        let line = self.position(range.start).0;
+        let (insertion_point, mut markup) = match self.heading_label_placement {
+            HeadingLabelPlacement::EndOfLine => (
+                self.find_line_end(range.start),
+                format!(" {}", label_markup(&slug, line, self.anchor_syntax)),
+            ),
+            // After the heading's last line, so setext underlines stay attached
+            HeadingLabelPlacement::NextLine => (
+                self.find_line_end(range.end.saturating_sub(1).max(range.start)),
+                format!("\n{}", label_markup(&slug, line, self.anchor_syntax)),
+            ),
+        };
+        if self.heading_metadata {
+            markup.push_str(&format!(
+                " <!--raw-typst #metadata((level: {}, slug: \"{}\")) <{}> -->",
+                self.current_heading_level, slug, HEADING_METADATA_LABEL
+            ));
+        }
 
         if !self.seen_offsets.contains(&insertion_point) {
-            let markup = format!(" <!--raw-typst #label(\"{}\") -->", slug);
             self.insertions.push((insertion_point, markup));
             self.seen_offsets.insert(insertion_point);
             
-            let (line, column) = offset_to_line_column(self.markdown, range.start);
-            self.anchors.push(AnchorMeta {
-                id: slug,
-                offset: range.start,
-                line,
-                column,
-            });
+            if self.current_heading_no_sync {
+                // Linkable only
+            } else if let Some(stable_id) = stable_id.filter(|id| *id != slug) {
+                // The slug label moves with the text, so sync gets its own label.
+                // The line start may already hold the doc-start anchor, so skip dedup.
+                let line_start = self.find_line_start(range.start);
+                self.push_anchor(line_start, range.start, &stable_id);
+            } else {
+                let (line, column) = self.position(range.start);
+                self.anchors.push(AnchorMeta {
+                    id: slug,
+                    offset: range.start,
+                    line,
+                    column,
+                    preview: None,
+                    seq: 0,
+                });
+            }
         }
         
         self.in_heading = false;
+        self.heading_image_depth = 0;
         self.current_heading_explicit_id = None;
+        self.current_heading_no_sync = false;
     }
 
     fn handle_horizontal_rule(&mut self, range: std::ops::Range<usize>) {
         self.hr_count += 1;
+        if self.list_depth > 0 {
+            return;
+        }
+        let anchored = match self.rule_anchors {
+            RuleAnchorMode::All => true,
+            RuleAnchorMode::Off => false,
+            RuleAnchorMode::SectionSeparators => self.heading_follows(range.end),
+        };
+        if !anchored {
+            return;
+        }
         let id = format!("tf-hr-{}", self.hr_count);
         let line_start = self.find_line_start(range.start);
         
-        if self.try_add_anchor(line_start, range.start, &id) {
-            // Successfully added
+        if line_start == 0 {
+            // A rule opening the body shares offset 0 with tf-doc-start but still
+            // needs its own label (e.g. `---` right after the frontmatter)
+            self.push_anchor(line_start, range.start, &id);
+        } else {
+            self.try_add_anchor(line_start, range.start, &id);
         }
     }
 
+    /// Check whether the next non-blank line after `offset` is an ATX heading.
+    fn heading_follows(&self, offset: usize) -> bool {
+        self.markdown[offset..]
+            .lines()
+            .map(str::trim_start)
+            .find(|line| !line.is_empty())
+            .is_some_and(|line| line.starts_with('#'))
+    }
+
+    fn handle_page_break(&mut self, range: std::ops::Range<usize>) {
+        self.pagebreak_count += 1;
+        if self.list_depth > 0 {
+            return;
+        }
+        let id = format!("tf-pagebreak-{}", self.pagebreak_count);
+        let line_start = self.find_line_start(range.start);
+        self.try_add_anchor(line_start, range.start, &id);
+    }
+
     fn handle_start_tag(&mut self, tag: Tag, range: std::ops::Range<usize>) {
         if !is_block_level(&tag) {
             return;
@@ -198,20 +523,25 @@ impl<'a> InjectionContext<'a> {
             return;
         }
 
+        // A column-0 anchor line inside a list ends it, so the remaining items
+        // become a new list and a tight list can turn loose. Leave list content alone.
+        if self.list_depth > 0 {
+            return;
+        }
+
         // Handle code blocks
         if let Tag::CodeBlock(kind) = &tag {
             self.handle_code_block(kind, range);
             return;
         }
 
-        // Handle images
-        if let Tag::Image(_, dest, _) = &tag {
-            self.handle_image(dest, range);
+        // Skip if inside table
+        if self.table_depth > 0 {
             return;
         }
 
-        // Skip if inside table
-        if self.table_depth > 0 {
+        if matches!(tag, Tag::Paragraph) && self.long_quote_paragraphs.is_some() {
+            self.handle_quote_paragraph(range);
             return;
         }
 
@@ -221,28 +551,147 @@ impl<'a> InjectionContext<'a> {
             return;
         }
 
+        // MkDocs-style `!!! note` admonitions get a typed anchor
+        if matches!(tag, Tag::Paragraph) {
+            if let Some(kind) = admonition_type(&self.markdown[range.clone()]) {
+                self.admonition_count += 1;
+                let id = format!("tf-admonition-{}-{}", kind, self.admonition_count);
+                self.try_add_anchor(line_start, range.start, &id);
+                return;
+            }
+        }
+
         // Add generic paragraph anchor
         let id = format!("tf-{}-{}", range.start, self.anchors.len());
-        self.try_add_anchor(line_start, range.start, &id);
+        if self.try_add_anchor(line_start, range.start, &id) {
+            self.last_paragraph_anchor = Some((range.start, self.anchors.len() - 1));
+        }
+    }
+
+    fn handle_blockquote_start(&mut self, range: std::ops::Range<usize>) {
+        self.blockquote_depth += 1;
+        if self.blockquote_depth > 1 {
+            return;
+        }
+
+        self.quote_count += 1;
+        let is_long = self
+            .quote_anchor_threshold
+            .is_some_and(|threshold| range.len() > threshold);
+        if is_long {
+            self.long_quote_paragraphs = Some(0);
+        }
+    }
+
+    /// Anchor a paragraph inside a long blockquote.
+    ///
+    /// The label goes on its own line carrying the same `>` markers as the
+    /// paragraph, so the quote is not split in two.
+    fn handle_quote_paragraph(&mut self, range: std::ops::Range<usize>) {
+        let line_start = self.find_line_start(range.start);
+        let prefix = &self.markdown[line_start..range.start];
+        // Paragraphs inside lists within the quote would need list markers too
+        if !prefix.chars().all(|c| c == '>' || c == ' ' || c == '\t') {
+            return;
+        }
+        if !self.seen_offsets.insert(line_start) {
+            return;
+        }
+
+        let paragraph = self.long_quote_paragraphs.unwrap_or(0) + 1;
+        self.long_quote_paragraphs = Some(paragraph);
+        let id = format!("tf-quote-{}-{}", self.quote_count, paragraph);
+
+        let (line, column) = self.position(range.start);
+        let markup = format!("{}{}\n", prefix, label_markup(&id, line, self.anchor_syntax));
+        self.insertions.push((line_start, markup));
+        self.anchors.push(AnchorMeta {
+            id,
+            offset: range.start,
+            line,
+            column,
+            preview: None,
+            seq: 0,
+        });
     }
 
     fn handle_code_block(&mut self, kind: &CodeBlockKind, range: std::ops::Range<usize>) {
         self.code_block_count += 1;
-        let lang = match kind {
-            CodeBlockKind::Fenced(lang) if !lang.is_empty() => {
-                format!("-{}", lang.split_whitespace().next().unwrap_or(""))
-            }
-            _ => String::new(),
+        let (info, inferred) = match kind {
+            CodeBlockKind::Fenced(info) if info.trim().is_empty() => match &self.default_code_lang {
+                Some(lang) => (lang.clone(), true),
+                None => (String::new(), false),
+            },
+            CodeBlockKind::Fenced(info) => (info.trim().to_string(), false),
+            CodeBlockKind::Indented => (String::new(), false),
+        };
+        let (language, attributes) = info.split_once(char::is_whitespace).unwrap_or((&info, ""));
+        let lang = if language.is_empty() {
+            String::new()
+        } else {
+            format!("-{}", language)
         };
         let id = format!("tf-code{}{}", lang, self.code_block_count);
+
+        // Write the default language into the bare fence so highlighting sees it too
+        if inferred {
+            let fence_start = range.start + self.markdown[range.start..].len()
+                - self.markdown[range.start..].trim_start_matches(' ').len();
+            let fence = &self.markdown[fence_start..];
+            let fence_char = fence.chars().next().unwrap_or('`');
+            let fence_len = fence.len() - fence.trim_start_matches(fence_char).len();
+            self.insertions.push((fence_start + fence_len, language.to_string()));
+        }
+
         let line_start = self.find_line_start(range.start);
-        self.try_add_anchor(line_start, range.start, &id);
+        if self.try_add_anchor(line_start, range.start, &id) && !language.is_empty() {
+            self.code_blocks.insert(
+                id,
+                CodeBlockInfo {
+                    language: language.to_string(),
+                    attributes: attributes.trim().to_string(),
+                },
+            );
+        }
     }
 
     fn handle_image(&mut self, dest: &str, range: std::ops::Range<usize>) {
+        let id = self.next_image_id(dest);
+        
+        // Never inject inside table rows
+        if self.table_depth > 0 {
+            return;
+        }
+
+        // An image opening a paragraph shares its line, which the paragraph
+        // anchor has already claimed; rename that anchor instead
+        if let Some((offset, index)) = self.last_paragraph_anchor {
+            if offset == range.start {
+                self.rename_anchor(index, id);
+                self.last_paragraph_anchor = None;
+            }
+        }
+    }
+
+    /// Anchor a hand-written `<!--raw-typst #image("...") -->` on its own line.
+    ///
+    /// Inline raw images are left to the surrounding paragraph's anchor.
+    fn handle_raw_typst_image(&mut self, path: &str, range: std::ops::Range<usize>) {
+        let id = self.next_image_id(path);
+        if self.table_depth > 0 || self.list_depth > 0 || self.blockquote_depth > 0 {
+            return;
+        }
+        let line_start = self.find_line_start(range.start);
+        if self.markdown[line_start..range.start].trim().is_empty() {
+            self.try_add_anchor(line_start, range.start, &id);
+        }
+    }
+
+    /// Next `tf-img-<name>-N` id, named after the image file.
+    fn next_image_id(&mut self, dest: &str) -> String {
         self.image_count += 1;
         let short_name: String = dest
-            .rsplit('/')
+            .rsplit(['/', '\\'])
             .next()
             .unwrap_or("")
             .split('.')
@@ -253,14 +702,21 @@ impl<'a> InjectionContext<'a> {
             .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
             .collect();
             
-        let id = if short_name.is_empty() {
+        if short_name.is_empty() {
             format!("tf-img-{}", self.image_count)
         } else {
             format!("tf-img-{}-{}", short_name, self.image_count)
-        };
-        
-        let line_start = self.find_line_start(range.start);
-        self.try_add_anchor(line_start, range.start, &id);
+        }
+    }
+
+    /// Replace the id of an already-registered anchor and its injected markup.
+    fn rename_anchor(&mut self, index: usize, id: String) {
+        let old_id = std::mem::replace(&mut self.anchors[index].id, id);
+        let old_label = format!("#label(\"{}\")", old_id);
+        let new_label = format!("#label(\"{}\")", self.anchors[index].id);
+        if let Some((_, markup)) = self.insertions.iter_mut().find(|(_, m)| m.contains(&old_label)) {
+            *markup = markup.replace(&old_label, &new_label);
+        }
     }
 
     fn try_add_anchor(&mut self, insertion_offset: usize, source_offset: usize, id: &str) -> bool {
@@ -269,16 +725,38 @@ impl<'a> InjectionContext<'a> {
         }
         
         self.seen_offsets.insert(insertion_offset);
-        let (line, column) = offset_to_line_column(self.markdown, source_offset);
-        let markup = build_anchor_markup(self.markdown, insertion_offset, id, false);
+        self.push_anchor(insertion_offset, source_offset, id);
+        true
+    }
+
+    /// Register a block anchor without checking for an existing anchor at the same offset.
+    fn push_anchor(&mut self, insertion_offset: usize, source_offset: usize, id: &str) {
+        // Generic paragraph ids embed an anchor index, so carry the previous one
+        // forward instead of renumbering everything after an edit
+        let id = match self.carried.get(&source_offset) {
+            Some(previous) if is_offset_anchor(id) && is_offset_anchor(&previous.id) => previous.id.clone(),
+            _ => id.to_string(),
+        };
+        let id = id.as_str();
+        let (line, column) = self.position(source_offset);
+        let markup = build_anchor_markup(self.markdown, insertion_offset, id, line, false, self.anchor_syntax);
         self.insertions.push((insertion_offset, markup));
         self.anchors.push(AnchorMeta {
             id: id.to_string(),
             offset: source_offset,
             line,
             column,
+            preview: None,
+            seq: 0,
         });
-        true
+    }
+
+    /// Editor line and column of a source offset, reusing a carried anchor's position.
+    fn position(&self, offset: usize) -> (usize, usize) {
+        match self.carried.get(&offset) {
+            Some(previous) => (previous.line, previous.column),
+            None => offset_to_line_column(self.markdown, offset),
+        }
     }
 
     fn find_line_start(&self, offset: usize) -> usize {
@@ -305,9 +783,24 @@ impl<'a> InjectionContext<'a> {
         trimmed.starts_with('>') || trimmed.starts_with('|')
     }
 
-    fn build_output(mut self, markdown: &str) -> Result<PreprocessorOutput> {
+    /// Order insertions by offset and keep the document's trailing-newline state.
+    fn finalize_insertions(&mut self, markdown: &str) {
         self.insertions.sort_by_key(|(offset, _)| *offset);
+
+        // A block anchor appended at the very end must not add a final newline
+        if !markdown.ends_with('\n') {
+            for (offset, snippet) in &mut self.insertions {
+                if *offset == markdown.len() && snippet.ends_with('\n') {
+                    snippet.pop();
+                }
+            }
+        }
+    }
+
+    fn build_output(mut self, markdown: &str) -> Result<PreprocessorOutput> {
+        self.finalize_insertions(markdown);
         
+        let insertions = self.insertions.iter().map(|(offset, snippet)| (*offset, snippet.len())).collect();
         let mut output = markdown.to_owned();
         for (offset, snippet) in self.insertions.into_iter().rev() {
             output.insert_str(offset, &snippet);
@@ -316,12 +809,39 @@ impl<'a> InjectionContext<'a> {
         Ok(PreprocessorOutput {
             markdown: output,
             anchors: self.anchors,
+            footnotes: self.footnotes,
+            code_blocks: self.code_blocks,
+            insertions,
+            warnings: self.warnings,
+            ..Default::default()
         })
     }
+
+    fn write_output<W: Write>(mut self, markdown: &str, mut out: W) -> Result<Vec<AnchorMeta>> {
+        self.finalize_insertions(markdown);
+
+        // Walk insertions forward, copying the original span before each snippet
+        let mut cursor = 0;
+        for (offset, snippet) in &self.insertions {
+            out.write_all(&markdown.as_bytes()[cursor..*offset])?;
+            out.write_all(snippet.as_bytes())?;
+            cursor = *offset;
+        }
+        out.write_all(&markdown.as_bytes()[cursor..])?;
+
+        Ok(self.anchors)
+    }
 }
 
 /// Build the Typst anchor markup string.
-fn build_anchor_markup(source: &str, offset: usize, id: &str, inline: bool) -> String {
+fn build_anchor_markup(
+    source: &str,
+    offset: usize,
+    id: &str,
+    line: usize,
+    inline: bool,
+    syntax: AnchorSyntax,
+) -> String {
     let mut snippet = String::new();
     
     if offset > 0 && !inline && !source[..offset].ends_with('\n') {
@@ -332,9 +852,7 @@ fn build_anchor_markup(source: &str, offset: usize, id: &str, inline: bool) -> S
         snippet.push(' ');
     }
     
-    snippet.push_str("<!--raw-typst #label(\"");
-    snippet.push_str(id);
-    snippet.push_str("\") -->");
+    snippet.push_str(&label_markup(id, line, syntax));
     
     if !inline {
         snippet.push('\n');
@@ -343,6 +861,33 @@ fn build_anchor_markup(source: &str, offset: usize, id: &str, inline: bool) -> S
     snippet
 }
 
+/// Type of a MkDocs-style admonition (`!!! warning "Title"`), lowercased.
+///
+/// Returns `None` unless the paragraph's first line opens with `!!!` and a type word.
+fn admonition_type(paragraph: &str) -> Option<String> {
+    let first_line = paragraph.lines().next()?;
+    let kind = first_line.strip_prefix("!!!")?.split_whitespace().next()?;
+    let kind: String = kind
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .collect::<String>()
+        .to_lowercase();
+    (!kind.is_empty()).then_some(kind)
+}
+
+/// The `#label("id")` passthrough in the requested syntax.
+///
+/// `line` is the anchor's 0-based source line, only emitted by [`AnchorSyntax::Metadata`].
+fn label_markup(id: &str, line: usize, syntax: AnchorSyntax) -> String {
+    match syntax {
+        AnchorSyntax::RawTypstComment => format!("<!--raw-typst #label(\"{}\") -->", id),
+        AnchorSyntax::Metadata => format!(
+            "<!--raw-typst #metadata((id: \"{}\", line: {})) #label(\"{}\") -->",
+            id, line, id
+        ),
+    }
+}
+
 /// Check if a tag represents a block-level element.
 fn is_block_level(tag: &Tag<'_>) -> bool {
     matches!(
@@ -361,8 +906,85 @@ fn is_block_level(tag: &Tag<'_>) -> bool {
     )
 }
 
+/// Common named HTML entities and the characters they stand for.
+const NAMED_ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+    ("nbsp", '\u{a0}'),
+    ("ndash", '–'),
+    ("mdash", '—'),
+    ("hellip", '…'),
+    ("lsquo", '‘'),
+    ("rsquo", '’'),
+    ("ldquo", '“'),
+    ("rdquo", '”'),
+    ("laquo", '«'),
+    ("raquo", '»'),
+    ("copy", '©'),
+    ("reg", '®'),
+    ("trade", '™'),
+];
+
+/// Decode named (`&amp;`) and numeric (`&#8212;`, `&#x2014;`) HTML entities.
+///
+/// Heading text from the parser is already decoded; this is for raw editor text,
+/// so slugs predicted from it match the injected labels. Unknown entities are kept.
+pub fn decode_html_entities(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..].find(';').filter(|&end| end <= 10).and_then(|end| {
+            let name = &rest[1..end + 1];
+            let ch = match name.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => {
+                    u32::from_str_radix(&hex[1..], 16).ok().and_then(char::from_u32)
+                }
+                Some(dec) => dec.parse().ok().and_then(char::from_u32),
+                None => NAMED_ENTITIES.iter().find(|(n, _)| *n == name).map(|(_, c)| *c),
+            }?;
+            Some((ch, end + 2))
+        });
+        match decoded {
+            Some((ch, len)) => {
+                result.push(ch);
+                rest = &rest[len..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// First [`PREVIEW_CHARS`] characters of the block starting at `offset`, with
+/// whitespace collapsed and heading markers dropped. `None` for an empty block.
+fn anchor_preview(markdown: &str, offset: usize) -> Option<String> {
+    let block = markdown[offset..].split("\n\n").next().unwrap_or("");
+    let text = block.trim_start().trim_start_matches('#');
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.is_empty() {
+        return None;
+    }
+    let mut preview: String = collapsed.chars().take(PREVIEW_CHARS).collect();
+    if collapsed.chars().count() > PREVIEW_CHARS {
+        preview.push('…');
+    }
+    Some(preview)
+}
+
 /// Convert heading text to a URL-friendly slug (GitHub-style).
-fn slugify(text: &str) -> String {
+///
+/// Duplicate headings get `-1`, `-2`, ... suffixes during injection; that
+/// counter is document state and is not applied here.
+pub fn slugify(text: &str) -> String {
     let slug: String = text
         .chars()
         .map(|c| {
@@ -406,6 +1028,18 @@ mod tests {
         assert_eq!(slugify("What's New?"), "whats-new");
     }
 
+    #[test]
+    fn test_heading_entities_are_decoded() {
+        let md = "# Tips &amp; Tricks\n\n## Before&#8212;After\n\n## A &#x2014; B\n";
+        let result = inject_anchors(md).unwrap();
+        let ids: Vec<&str> = result.anchors.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, vec!["tf-doc-start", "tips-tricks", "before-after", "a-b"]);
+
+        assert_eq!(decode_html_entities("Tips &amp; Tricks"), "Tips & Tricks");
+        assert_eq!(decode_html_entities("Before&#8212;After&#x2014;"), "Before—After—");
+        assert_eq!(decode_html_entities("AT&T &bogus; &#xZZ; &"), "AT&T &bogus; &#xZZ; &");
+    }
+
     #[test]
     fn test_inject_anchors_basic() {
         let md = "# Hello\n\nWorld";
@@ -414,6 +1048,292 @@ mod tests {
         assert!(result.markdown.contains("#label(\"hello\")"));
     }
 
+    #[test]
+    fn test_no_sync_heading() {
+        let md = "# Intro\n\n## Divider {.no-sync}\n\nText";
+        let result = inject_anchors(md).unwrap();
+
+        // Still linkable via its slug label...
+        assert!(result.markdown.contains("#label(\"divider\")"));
+        // ...but not part of the scroll-sync metadata
+        assert!(result.anchors.iter().any(|a| a.id == "intro"));
+        assert!(!result.anchors.iter().any(|a| a.id == "divider"));
+    }
+
+    #[test]
+    fn test_reference_style_images() {
+        let md = "Intro text.\n\n![Diagram][arch]\n\n![Broken][missing]\n\n[arch]: assets/architecture-v2.png\n";
+        let result = inject_anchors(md).unwrap();
+
+        let img = result.anchors.iter().find(|a| a.id.starts_with("tf-img-")).expect("image anchor");
+        assert_eq!(img.id, "tf-img-architecture-v2-1");
+        assert_eq!(img.offset, md.find("![Diagram]").unwrap());
+        assert!(result.markdown.contains("<!--raw-typst #label(\"tf-img-architecture-v2-1\") -->\n![Diagram][arch]"));
+
+        // An undefined reference is plain text: it gets a paragraph anchor, not an image one
+        assert_eq!(result.anchors.iter().filter(|a| a.id.starts_with("tf-img-")).count(), 1);
+        assert!(result.markdown.contains("![Broken][missing]"));
+    }
+
+    #[test]
+    fn test_backslash_image_paths() {
+        let md = "Intro.\n\n![Figure](assets\\fig.png)\n\n![Nested](assets\\charts\\q3-sales.png)\n";
+        let result = inject_anchors(md).unwrap();
+
+        let ids: Vec<&str> = result.anchors.iter().map(|a| a.id.as_str()).collect();
+        assert!(ids.contains(&"tf-img-fig-1"), "{:?}", ids);
+        assert!(ids.contains(&"tf-img-q3-sales-2"), "{:?}", ids);
+    }
+
+    #[test]
+    fn test_heading_inline_markup_slugs() {
+        let md = "Intro.\n\n## See [the docs](url) for **details**\n\n## ![Tideflow logo](logo.png) Setup\n";
+        let result = inject_anchors(md).unwrap();
+
+        let ids: Vec<&str> = result.anchors.iter().map(|a| a.id.as_str()).collect();
+        assert!(ids.contains(&"see-the-docs-for-details"), "{:?}", ids);
+        // Image alt text is left out of the slug
+        assert!(ids.contains(&"setup"), "{:?}", ids);
+        assert!(result.markdown.contains("**details** <!--raw-typst #label(\"see-the-docs-for-details\") -->"));
+    }
+
+    #[test]
+    fn test_anchor_previews() {
+        let md = "# Getting   Started\n\nThis paragraph is long enough\nto be cut off in the minimap preview.\n";
+        let options = PreprocessOptions {
+            anchor_previews: true,
+            ..Default::default()
+        };
+        let result = inject_anchors_with_options(md, &options).unwrap();
+
+        let heading = result.anchors.iter().find(|a| a.id == "getting-started").unwrap();
+        assert_eq!(heading.preview.as_deref(), Some("Getting Started"));
+        let paragraph = result.anchors.iter().find(|a| is_offset_anchor(&a.id)).unwrap();
+        assert_eq!(paragraph.preview.as_deref(), Some("This paragraph is long enough to be cut …"));
+
+        // Off by default, and absent from the serialized anchor
+        let plain = inject_anchors(md).unwrap();
+        assert!(plain.anchors.iter().all(|a| a.preview.is_none()));
+        assert!(!serde_json::to_string(&plain.anchors[0]).unwrap().contains("preview"));
+    }
+
+    #[test]
+    fn test_raw_typst_image_anchor() {
+        let md = "Intro.\n\n<!--raw-typst #image(\"assets/x.png\", width: 50%) -->\n\nText with <!--raw-typst #image(\"assets/inline.png\") --> inline.\n";
+        let result = inject_anchors(md).unwrap();
+
+        let img = result.anchors.iter().find(|a| a.id.starts_with("tf-img-")).expect("image anchor");
+        assert_eq!(img.id, "tf-img-x-1");
+        assert_eq!(img.offset, md.find("<!--raw-typst #image").unwrap());
+        assert!(result.markdown.contains("<!--raw-typst #label(\"tf-img-x-1\") -->\n<!--raw-typst #image("));
+
+        // The inline image stays inside its paragraph
+        assert_eq!(result.anchors.iter().filter(|a| a.id.starts_with("tf-img-")).count(), 1);
+    }
+
+    #[test]
+    fn test_code_block_info_string() {
+        let md = "Intro.\n\n```rust {linenos}\nfn main() {}\n```\n\n```\nplain\n```\n";
+        let result = inject_anchors(md).unwrap();
+
+        assert!(result.anchors.iter().any(|a| a.id == "tf-code-rust1"));
+        assert_eq!(
+            result.code_blocks.get("tf-code-rust1"),
+            Some(&CodeBlockInfo {
+                language: "rust".to_string(),
+                attributes: "{linenos}".to_string(),
+            })
+        );
+        // Blocks without an info string carry no metadata
+        assert!(result.anchors.iter().any(|a| a.id == "tf-code2"));
+        assert_eq!(result.code_blocks.len(), 1);
+    }
+
+    #[test]
+    fn test_stable_heading_ids() {
+        let options = PreprocessOptions {
+            stable_heading_ids: true,
+            ..Default::default()
+        };
+        let before = inject_anchors_with_options("# Setup Guide {#sec-setup}\n\nText.\n", &options).unwrap();
+        let after = inject_anchors_with_options("# Installing Tideflow {#sec-setup}\n\nText.\n", &options).unwrap();
+
+        // The sync anchor keeps the explicit id while the link label follows the text
+        for output in [&before, &after] {
+            assert!(output.anchors.iter().any(|a| a.id == "sec-setup" && a.offset == 0));
+            assert!(output.markdown.contains("<!--raw-typst #label(\"sec-setup\") -->\n# "));
+        }
+        assert!(before.markdown.contains("#label(\"setup-guide\")"));
+        assert!(after.markdown.contains("#label(\"installing-tideflow\")"));
+
+        // Without the option the explicit id remains the only label
+        let plain = inject_anchors("# Setup Guide {#sec-setup}\n").unwrap();
+        assert!(plain.anchors.iter().any(|a| a.id == "sec-setup"));
+        assert!(!plain.markdown.contains("setup-guide"));
+    }
+
+    #[test]
+    fn test_long_blockquote_paragraph_anchors() {
+        let quote = "> First speaker talks for a while.\n>\n> Second speaker replies at length.\n>\n> > Nested aside.\n>\n> Third speaker closes.\n";
+        let md = format!("Intro.\n\n{}\nAfter.\n\n> Short quote.\n", quote);
+        let options = PreprocessOptions {
+            quote_anchor_threshold: Some(40),
+            ..Default::default()
+        };
+        let result = inject_anchors_with_options(&md, &options).unwrap();
+
+        let ids: Vec<&str> = result
+            .anchors
+            .iter()
+            .map(|a| a.id.as_str())
+            .filter(|id| id.starts_with("tf-quote-"))
+            .collect();
+        assert_eq!(ids, vec!["tf-quote-1-1", "tf-quote-1-2", "tf-quote-1-3", "tf-quote-1-4"]);
+
+        // Every quoted line keeps its `>` marker, so the quote stays a single block
+        assert!(result.markdown.contains("> <!--raw-typst #label(\"tf-quote-1-2\") -->\n> Second speaker"));
+        assert!(result.markdown.contains("> > <!--raw-typst #label(\"tf-quote-1-3\") -->\n> > Nested aside."));
+        assert!(result.markdown.contains("\n\n> Short quote."));
+
+        // Anchor offsets still point at the original paragraph text
+        let second = result.anchors.iter().find(|a| a.id == "tf-quote-1-2").unwrap();
+        assert_eq!(second.offset, md.find("Second speaker").unwrap());
+    }
+
+    #[test]
+    fn test_heading_label_placement() {
+        let md = "# Title\n\nText.\n\nSetext\n======\n";
+
+        let inline = inject_anchors(md).unwrap();
+        assert!(inline.markdown.contains("# Title <!--raw-typst #label(\"title\") -->\n"));
+        assert!(inline.markdown.contains("Setext <!--raw-typst #label(\"setext\") -->\n======"));
+
+        let options = PreprocessOptions {
+            heading_label_placement: HeadingLabelPlacement::NextLine,
+            ..Default::default()
+        };
+        let next_line = inject_anchors_with_options(md, &options).unwrap();
+        assert!(next_line.markdown.contains("# Title\n<!--raw-typst #label(\"title\") -->\n\n"));
+        assert!(next_line.markdown.contains("Setext\n======\n<!--raw-typst #label(\"setext\") -->\n"));
+
+        // Anchor metadata still points at the heading itself
+        let title = next_line.anchors.iter().find(|a| a.id == "title").unwrap();
+        assert_eq!(title.offset, 0);
+    }
+
+    #[test]
+    fn test_rule_anchor_modes() {
+        let md = "Intro.\n\n---\n\nMore.\n\n***\n\n## Next\n\nText.\n\n---\n\nEnd.";
+        let hr_ids = |mode| {
+            let options = PreprocessOptions {
+                rule_anchors: mode,
+                ..Default::default()
+            };
+            inject_anchors_with_options(md, &options)
+                .unwrap()
+                .anchors
+                .into_iter()
+                .map(|a| a.id)
+                .filter(|id| id.starts_with("tf-hr-"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(hr_ids(RuleAnchorMode::All), vec!["tf-hr-1", "tf-hr-2", "tf-hr-3"]);
+        assert!(hr_ids(RuleAnchorMode::Off).is_empty());
+        assert_eq!(hr_ids(RuleAnchorMode::SectionSeparators), vec!["tf-hr-2"]);
+    }
+
+    #[test]
+    fn test_heading_metadata() {
+        let md = "# Top\n\nText.\n\n### Deep Dive\n";
+        let options = PreprocessOptions {
+            heading_metadata: true,
+            ..Default::default()
+        };
+        let result = inject_anchors_with_options(md, &options).unwrap();
+
+        assert!(result.markdown.contains(
+            "### Deep Dive <!--raw-typst #label(\"deep-dive\") --> <!--raw-typst #metadata((level: 3, slug: \"deep-dive\")) <tf-heading> -->\n"
+        ));
+        assert!(result.markdown.contains("#metadata((level: 1, slug: \"top\"))"));
+
+        // Off by default
+        assert!(!inject_anchors(md).unwrap().markdown.contains("#metadata"));
+    }
+
+    #[test]
+    fn test_slug_suffix_style() {
+        let md = "# Intro\n\n# Intro\n\n# Intro\n";
+        let heading_ids = |options: &PreprocessOptions| -> Vec<String> {
+            let result = inject_anchors_with_options(md, options).unwrap();
+            result.anchors.into_iter().map(|a| a.id).filter(|id| id.starts_with("intro")).collect()
+        };
+
+        assert_eq!(heading_ids(&PreprocessOptions::default()), vec!["intro", "intro-1", "intro-2"]);
+
+        let options = PreprocessOptions {
+            slug_suffix: SlugSuffixStyle {
+                separator: "_".to_string(),
+                start: 2,
+            },
+            ..Default::default()
+        };
+        assert_eq!(heading_ids(&options), vec!["intro", "intro_2", "intro_3"]);
+    }
+
+    #[test]
+    fn test_empty_heading_modes() {
+        let md = "Intro text.\n\n#\n\n## !!!\n";
+        let run = |empty_headings| {
+            let options = PreprocessOptions {
+                empty_headings,
+                ..Default::default()
+            };
+            inject_anchors_with_options(md, &options).unwrap()
+        };
+
+        let skipped = run(EmptyHeadingMode::Skip);
+        assert_eq!(skipped.anchors.len(), 1, "only tf-doc-start");
+        assert!(skipped.warnings.is_empty());
+
+        let fallback = run(EmptyHeadingMode::FallbackId);
+        let ids: Vec<_> = fallback.anchors.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, vec!["tf-doc-start", "tf-heading-1", "tf-heading-2"]);
+        assert_eq!(fallback.anchors[2].offset, md.find("## !!!").unwrap());
+        assert!(fallback.markdown.contains("## !!! <!--raw-typst #label(\"tf-heading-2\") -->"));
+
+        let reported = run(EmptyHeadingMode::Error);
+        assert_eq!(reported.anchors.len(), 1);
+        assert_eq!(reported.warnings.len(), 2);
+        assert!(reported.warnings[1].contains("`## !!!`"));
+    }
+
+    #[test]
+    fn test_wide_table_warning() {
+        let wide = "| a | b | c | d | e | f | g | h |\n|---|---|---|---|---|---|---|---|\n| 1 | 2 | 3 | 4 | 5 | 6 | 7 | 8 |\n";
+        let result = inject_anchors(wide).unwrap();
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.warnings[0].contains("8 columns"));
+        assert!(result.warnings[0].contains("landscape"));
+
+        let narrow = "| a | b |\n|---|---|\n| 1 | 2 |\n";
+        assert!(inject_anchors(narrow).unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn test_admonition_anchor() {
+        let md = "Intro paragraph.\n\n!!! warning \"Careful\"\n    Hot surface.\n\n!!!\n\nEnd.\n";
+        let result = inject_anchors(md).unwrap();
+        let ids: Vec<&str> = result.anchors.iter().map(|a| a.id.as_str()).collect();
+
+        assert!(ids.contains(&"tf-admonition-warning-1"), "{:?}", ids);
+        assert_eq!(ids.iter().filter(|id| id.starts_with("tf-admonition")).count(), 1);
+        // Ordinary paragraphs keep their generic anchors
+        let end = result.anchors.iter().find(|a| a.offset == md.find("End.").unwrap()).unwrap();
+        assert!(end.id.starts_with(&format!("tf-{}-", end.offset)));
+        assert!(result.markdown.contains("<!--raw-typst #label(\"tf-admonition-warning-1\") -->\n!!! warning"));
+    }
+
     #[test]
     fn test_nested_lists() {
         let md = "- Item 1\n  - Nested 1\n  - Nested 2\n- Item 2";
@@ -423,4 +1343,44 @@ mod tests {
         assert!(result.markdown.contains("  - Nested 1"));
         assert!(result.markdown.contains("  - Nested 2"));
     }
+
+    /// Shape of every list in `md`: item count and whether it is loose.
+    fn list_shapes(md: &str) -> Vec<(usize, bool)> {
+        let mut shapes = Vec::new();
+        let mut open: Vec<usize> = Vec::new();
+        for event in Parser::new_ext(md, Options::all()) {
+            match event {
+                Event::Start(Tag::List(_)) => {
+                    open.push(shapes.len());
+                    shapes.push((0, false));
+                }
+                Event::End(Tag::List(_)) => {
+                    open.pop();
+                }
+                Event::Start(Tag::Item) => {
+                    if let Some(&i) = open.last() {
+                        shapes[i].0 += 1;
+                    }
+                }
+                Event::Start(Tag::Paragraph) => {
+                    if let Some(&i) = open.last() {
+                        shapes[i].1 = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        shapes
+    }
+
+    #[test]
+    fn test_anchors_keep_tight_lists_tight() {
+        let md = "Intro paragraph.\n- one\n- two\n  ```rust\n  let x = 1;\n  ```\n- three\n  ![Chart](assets/chart.png)\n- four\n\n1. first\n2. second\n\nAfter.\n";
+        let result = inject_anchors(md).unwrap();
+
+        assert_eq!(list_shapes(md), vec![(4, false), (2, false)]);
+        assert_eq!(list_shapes(&result.markdown), list_shapes(md));
+        // The code block inside the item is not anchored at column 0
+        assert!(result.markdown.contains("- two\n  ```rust"));
+    }
 }
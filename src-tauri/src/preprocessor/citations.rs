@@ -0,0 +1,563 @@
+//! Pandoc-style citation conversion.
+//!
+//! Rewrites `[@key]` citations into raw Typst `#cite()` calls and collects
+//! information about the citations encountered along the way.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::normalize::code_ranges;
+use super::types::{MultiCitationMode, PreprocessOptions};
+
+/// Longest citation body (between `[@` and `]`) that is still treated as a citation.
+///
+/// Bounding the match keeps conversion linear on pathological input such as an
+/// unclosed `[@` followed by megabytes of text, which runs on every keystroke.
+const MAX_CITATION_LEN: usize = 512;
+
+lazy_static! {
+    // Pattern matches: [@citation-key] or [@key1; @key2] or [@key, p. 42]
+    static ref CITATION_RE: Regex = Regex::new(&format!(r"\[@([^\]\[]{{1,{}}})\]", MAX_CITATION_LEN))
+        .expect("BUG: Invalid regex pattern for citations");
+}
+
+/// Result of converting citations in a markdown document.
+#[derive(Debug, Clone, Default)]
+pub struct CitationConversion {
+    pub markdown: String,
+    /// Placeholder keys (containing `?` or `...`) that were left unconverted
+    pub draft_citations: Vec<String>,
+    /// Keys emitted as `#cite` calls (prefix stripped), deduplicated in first-appearance order
+    pub cited_keys: Vec<String>,
+}
+
+/// Convert Pandoc-style citations to Typst format.
+///
+/// Converts:
+/// - `[@key]` → `<!--raw-typst #cite(<key>) -->`
+/// - `[@key1; @key2]` → `<!--raw-typst #cite(<key1>) #cite(<key2>) -->`
+/// - `[@key, p. 42]` → `<!--raw-typst #cite(<key>, supplement: [p. 42]) -->`
+/// - `[@key, page 42, note 7]` → `<!--raw-typst #cite(<key>, supplement: [p. 42, n. 7]) -->`
+///
+/// Citations inside code blocks and code spans are examples, not citations, and stay
+/// literal. A citation may still wrap across a soft line break.
+///
+/// Draft placeholders such as `[@smith20??]` are left literal and recorded in
+/// `draft_citations`, since emitting them would fail to resolve in Typst. Every
+/// emitted key is recorded once in `cited_keys`, in the order this single pass
+/// meets them, so numeric styles can number references by first citation.
+///
+/// Typst already links every citation to its bibliography entry, but nothing marks it.
+/// With `link_citations`, each call is wrapped as `#underline(cite(<key>))` so readers
+/// can see the link. With `citation_backrefs`, each call is labelled `tf-cite-N` and a
+/// list after the body gives every cited source with links to the pages citing it.
+///
+/// With [`MultiCitationMode::GroupedBracket`], `[@key1; @key2]` becomes one adjacent
+/// group `#[#cite(<key1>)#cite(<key2>)]` that Typst renders as a single citation.
+///
+/// Keys starting with one of `citation_key_prefixes` (e.g. `zotero:smith2020`) are
+/// emitted without the prefix, matching the bibliography's own keys.
+///
+/// This enables bibliography support using familiar Pandoc citation syntax.
+pub fn convert_citations(markdown: &str, options: &PreprocessOptions) -> CitationConversion {
+    let link_citations = options.link_citations;
+    let mut draft_citations: Vec<String> = Vec::new();
+    let mut emitted_keys: Vec<String> = Vec::new();
+    // Cited key of each `tf-cite-N` label, N = index + 1
+    let mut sites: Vec<String> = Vec::new();
+
+    let mut emit_cite = |key: &str, supplement: Option<&str>| -> String {
+        let key = strip_key_prefix(key, options);
+        push_unique(&mut emitted_keys, key);
+        let call = cite_call(key, supplement, link_citations);
+        if !options.citation_backrefs {
+            return call;
+        }
+        sites.push(key.to_string());
+        format!("{}#label(\"{}\")", call, citation_site_label(sites.len()))
+    };
+
+    let mut convert = |caps: &regex::Captures| -> String {
+        let inner = &caps[1];
+
+        // Check if this is multiple citations (contains semicolon)
+        if inner.contains(';') {
+            // Multiple citations: [@key1; @key2] → #cite(<key1>) #cite(<key2>)
+            let citations: Vec<&str> = inner.split(';')
+                .map(clean_key)
+                .filter(|key| !key.is_empty()) // Skip empty keys
+                .collect();
+
+            // If no valid citations, return original text
+            if citations.is_empty() {
+                return caps[0].to_string();
+            }
+
+            // A single placeholder key keeps the whole group literal
+            let drafts: Vec<&str> = citations.iter().copied().filter(|key| is_draft_key(key)).collect();
+            if !drafts.is_empty() {
+                draft_citations.extend(drafts.into_iter().map(str::to_string));
+                return caps[0].to_string();
+            }
+
+            let cite_calls = citations.iter()
+                .map(|key| emit_cite(key, None))
+                .collect::<Vec<_>>();
+            match options.multi_citation_mode {
+                MultiCitationMode::SeparateCalls => format!("<!--raw-typst {} -->", cite_calls.join(" ")),
+                MultiCitationMode::GroupedBracket => format!("<!--raw-typst #[{}] -->", cite_calls.concat()),
+            }
+        } else if inner.contains(',') {
+            // Citation with supplement: [@key, p. 42] → #cite(<key>, supplement: [p. 42])
+            let parts: Vec<&str> = inner.splitn(2, ',').collect();
+            let key = clean_key(parts[0]);
+
+            // If key is empty, return original text
+            if key.is_empty() {
+                return caps[0].to_string();
+            }
+
+            if is_draft_key(key) {
+                draft_citations.push(key.to_string());
+                return caps[0].to_string();
+            }
+
+            let supplement = format_supplement(parts[1]);
+            format!("<!--raw-typst {} -->", emit_cite(key, Some(&supplement)))
+        } else {
+            // Simple citation: [@key] → #cite(<key>)
+            let key = clean_key(inner);
+
+            // If key is empty, return original text (incomplete citation)
+            if key.is_empty() {
+                return caps[0].to_string();
+            }
+
+            if is_draft_key(key) {
+                draft_citations.push(key.to_string());
+                return caps[0].to_string();
+            }
+
+            format!("<!--raw-typst {} -->", emit_cite(key, None))
+        }
+    };
+
+    // Back-to-back citations (`[@a][@b]`) would otherwise render glued together
+    let mut last_cite_end = None;
+    let code = citation_code_ranges(markdown);
+    let converted = CITATION_RE.replace_all(markdown, |caps: &regex::Captures| {
+        let whole = caps.get(0).expect("BUG: capture group 0 always matches");
+        if overlaps_code(&code, whole.range()) {
+            return whole.as_str().to_string();
+        }
+        let replacement = convert(caps);
+        if replacement == whole.as_str() {
+            return replacement;
+        }
+        let adjacent = last_cite_end == Some(whole.start());
+        last_cite_end = Some(whole.end());
+        if adjacent {
+            format!(" {}", replacement)
+        } else {
+            replacement
+        }
+    }).to_string();
+
+    let converted = if sites.is_empty() {
+        converted
+    } else {
+        format!("{}\n\n{}\n", converted, backref_list(&emitted_keys, &sites))
+    };
+
+    CitationConversion {
+        markdown: converted,
+        draft_citations,
+        cited_keys: emitted_keys,
+    }
+}
+
+/// Code ranges of `markdown`, without parsing text that has no citation at all.
+fn citation_code_ranges(markdown: &str) -> Vec<Range<usize>> {
+    if markdown.contains("[@") {
+        code_ranges(markdown)
+    } else {
+        Vec::new()
+    }
+}
+
+/// Whether `span` touches any of the sorted, disjoint `code` ranges.
+fn overlaps_code(code: &[Range<usize>], span: Range<usize>) -> bool {
+    let next = code.partition_point(|range| range.end <= span.start);
+    code.get(next).is_some_and(|range| range.start < span.end)
+}
+
+/// Every citation outside code, as in [`convert_citations`].
+fn citations_outside_code<'a>(markdown: &'a str) -> impl Iterator<Item = regex::Captures<'a>> {
+    let code = citation_code_ranges(markdown);
+    CITATION_RE.captures_iter(markdown).filter(move |caps| {
+        let whole = caps.get(0).expect("BUG: capture group 0 always matches");
+        !overlaps_code(&code, whole.range())
+    })
+}
+
+/// Label of the `site`-th (1-based) converted citation.
+fn citation_site_label(site: usize) -> String {
+    format!("tf-cite-{}", site)
+}
+
+/// Raw Typst list of every cited source (in `keys` order) followed by the page numbers
+/// of its citations, each linking back to the citation.
+fn backref_list(keys: &[String], sites: &[String]) -> String {
+    let items: Vec<String> = keys
+        .iter()
+        .map(|key| {
+            let pages: Vec<String> = sites
+                .iter()
+                .enumerate()
+                .filter(|(_, cited)| *cited == key)
+                .map(|(index, _)| {
+                    let label = citation_site_label(index + 1);
+                    format!("#link(<{0}>)[#context counter(page).at(<{0}>).first()]", label)
+                })
+                .collect();
+            format!("[#cite(<{}>, form: \"full\"): {}]", key, pages.join(", "))
+        })
+        .collect();
+    format!("<!--raw-typst #list({}) -->", items.join(", "))
+}
+
+/// Append `key` unless it is already listed.
+fn push_unique(keys: &mut Vec<String>, key: &str) {
+    if !keys.iter().any(|k| k == key) {
+        keys.push(key.to_string());
+    }
+}
+
+/// Locator words and their abbreviated forms.
+const LOCATOR_ABBREVIATIONS: &[(&str, &str)] = &[
+    ("page", "p."),
+    ("pages", "pp."),
+    ("note", "n."),
+    ("notes", "nn."),
+    ("chapter", "chap."),
+    ("chapters", "chaps."),
+    ("section", "sec."),
+    ("paragraph", "para."),
+];
+
+/// Normalize a citation supplement made of one or more comma-separated locators.
+///
+/// `p. 42, n. 7` and `page 42 ,note  7` both become `p. 42, n. 7`: each locator
+/// is trimmed, its internal whitespace collapsed, a spelled-out locator word
+/// abbreviated, and empty locators dropped.
+fn format_supplement(raw: &str) -> String {
+    raw.split(',')
+        .map(|locator| {
+            let mut words: Vec<&str> = locator.split_whitespace().collect();
+            if let Some(first) = words.first_mut() {
+                let lower = first.to_lowercase();
+                if let Some((_, abbr)) = LOCATOR_ABBREVIATIONS.iter().find(|(word, _)| *word == lower) {
+                    *first = abbr;
+                }
+            }
+            words.join(" ")
+        })
+        .filter(|locator| !locator.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Remove the first matching configured prefix (e.g. `zotero:`) from a citation key.
+fn strip_key_prefix<'a>(key: &'a str, options: &PreprocessOptions) -> &'a str {
+    options
+        .citation_key_prefixes
+        .iter()
+        .filter(|prefix| !prefix.is_empty())
+        .find_map(|prefix| key.strip_prefix(prefix.as_str()))
+        .filter(|stripped| !stripped.is_empty())
+        .unwrap_or(key)
+}
+
+/// Build the Typst call for a single citation.
+fn cite_call(key: &str, supplement: Option<&str>, linked: bool) -> String {
+    let cite = match supplement {
+        Some(supplement) => format!("cite(<{}>, supplement: [{}])", key, supplement),
+        None => format!("cite(<{}>)", key),
+    };
+    if linked {
+        format!("#underline({})", cite)
+    } else {
+        format!("#{}", cite)
+    }
+}
+
+/// Count how often each citation key is cited.
+///
+/// Every key in a `[@a; @b]` group and the key of a `[@a, p. 4]` supplement
+/// citation counts once per occurrence. Draft placeholders are not counted.
+/// Citations in code are not counted. Works regardless of whether a bibliography
+/// is loaded.
+pub fn count_citations(markdown: &str) -> HashMap<String, usize> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+
+    for caps in citations_outside_code(markdown) {
+        for key in cited_keys(&caps[1]) {
+            *counts.entry(key.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    counts
+}
+
+/// Every cited key with the 0-based line it appears on, in document order.
+///
+/// Draft placeholders and citations in code are skipped, as in [`count_citations`].
+pub fn citation_keys_by_line(markdown: &str) -> Vec<(usize, String)> {
+    let mut keys = Vec::new();
+    let mut line = 0;
+    let mut scanned = 0;
+
+    for caps in citations_outside_code(markdown) {
+        let start = caps.get(0).expect("BUG: capture group 0 always matches").start();
+        line += markdown[scanned..start].matches('\n').count();
+        scanned = start;
+        keys.extend(cited_keys(&caps[1]).map(|key| (line, key.to_string())));
+    }
+
+    keys
+}
+
+/// Keys named inside one `[@...]` citation body, without drafts or empty keys.
+fn cited_keys(inner: &str) -> impl Iterator<Item = &str> {
+    let keys: Vec<&str> = if inner.contains(';') {
+        inner.split(';').map(clean_key).collect()
+    } else {
+        // Anything after the first comma is a supplement
+        let key = inner.split(',').next().unwrap_or("");
+        vec![clean_key(key)]
+    };
+    keys.into_iter().filter(|key| !key.is_empty() && !is_draft_key(key))
+}
+
+/// Citation key from one `@key` part of a citation body.
+///
+/// Citations may wrap lines inside the brackets, so whitespace and newlines are
+/// trimmed on both sides of the `@` (`"\n@key2"` and `"@\nkey2"` give `key2`).
+fn clean_key(raw: &str) -> &str {
+    raw.trim().trim_start_matches('@').trim()
+}
+
+/// Check whether a citation key is a drafting placeholder (e.g. `smith20??` or `smith...`).
+fn is_draft_key(key: &str) -> bool {
+    key.contains('?') || key.contains("...")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draft_citation_left_literal() {
+        let result = convert_citations("Later work [@smith20??] and [@jones...; @doe2001] agree [@doe2001].", &PreprocessOptions::default());
+
+        assert!(result.markdown.contains("[@smith20??]"));
+        assert!(result.markdown.contains("[@jones...; @doe2001]"));
+        assert!(!result.markdown.contains("#cite(<smith20??>)"));
+        assert!(result.markdown.contains("#cite(<doe2001>)"));
+        assert_eq!(result.draft_citations, vec!["smith20??", "jones..."]);
+    }
+
+    #[test]
+    fn test_citations_in_code_left_literal() {
+        let md = "Use `[@einstein1905]` to cite, as in [@einstein1905].\n\n\
+                  ```markdown\nSee [@fenced].\n```\n\n\
+                  ````\n```\n[@nested]\n```\n````\n\n\
+                  Indented:\n\n    [@indented]\n\n\
+                  Wrapped [@knuth1984;\n@lamport1994] here.\n";
+        let result = convert_citations(md, &PreprocessOptions::default());
+
+        assert!(result.markdown.contains("`[@einstein1905]`"));
+        assert!(result.markdown.contains("as in <!--raw-typst #cite(<einstein1905>) -->."));
+        assert!(result.markdown.contains("See [@fenced]."));
+        assert!(result.markdown.contains("\n[@nested]\n"));
+        assert!(result.markdown.contains("    [@indented]"));
+        assert!(result.markdown.contains("<!--raw-typst #cite(<knuth1984>) #cite(<lamport1994>) -->"));
+        assert_eq!(result.cited_keys, vec!["einstein1905", "knuth1984", "lamport1994"]);
+
+        let counts = count_citations(md);
+        assert_eq!(counts.get("einstein1905"), Some(&1));
+        assert!(!counts.contains_key("fenced") && !counts.contains_key("nested") && !counts.contains_key("indented"));
+        assert_eq!(
+            citation_keys_by_line(md),
+            vec![
+                (0, "einstein1905".to_string()),
+                (16, "knuth1984".to_string()),
+                (16, "lamport1994".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_linked_citations() {
+        let md = "See [@knuth1984] and [@a; @b] or [@einstein1905, p. 42].";
+
+        let options = PreprocessOptions {
+            link_citations: true,
+            ..Default::default()
+        };
+        let linked = convert_citations(md, &options).markdown;
+        assert!(linked.contains("<!--raw-typst #underline(cite(<knuth1984>)) -->"));
+        assert!(linked.contains("#underline(cite(<a>)) #underline(cite(<b>))"));
+        assert!(linked.contains("#underline(cite(<einstein1905>, supplement: [p. 42]))"));
+
+        let plain = convert_citations(md, &PreprocessOptions::default()).markdown;
+        assert!(plain.contains("<!--raw-typst #cite(<knuth1984>) -->"));
+        assert!(plain.contains("#cite(<a>) #cite(<b>)"));
+        assert!(!plain.contains("underline"));
+    }
+
+    #[test]
+    fn test_citation_backrefs() {
+        let md = "See [@knuth1984] and [@a; @knuth1984].\n";
+        let options = PreprocessOptions {
+            citation_backrefs: true,
+            ..Default::default()
+        };
+        let result = convert_citations(md, &options).markdown;
+
+        assert!(result.starts_with(
+            "See <!--raw-typst #cite(<knuth1984>)#label(\"tf-cite-1\") --> and \
+             <!--raw-typst #cite(<a>)#label(\"tf-cite-2\") #cite(<knuth1984>)#label(\"tf-cite-3\") -->."
+        ));
+        assert!(result.ends_with(
+            "<!--raw-typst #list(\
+             [#cite(<knuth1984>, form: \"full\"): #link(<tf-cite-1>)[#context counter(page).at(<tf-cite-1>).first()], \
+             #link(<tf-cite-3>)[#context counter(page).at(<tf-cite-3>).first()]], \
+             [#cite(<a>, form: \"full\"): #link(<tf-cite-2>)[#context counter(page).at(<tf-cite-2>).first()]]) -->\n"
+        ));
+
+        let plain = convert_citations(md, &PreprocessOptions::default()).markdown;
+        assert!(!plain.contains("tf-cite-") && !plain.contains("#list("));
+    }
+
+    #[test]
+    fn test_adjacent_citations_are_separated() {
+        let result = convert_citations("Shown [@a][@b].", &PreprocessOptions::default());
+        assert_eq!(result.markdown, "Shown <!--raw-typst #cite(<a>) --> <!--raw-typst #cite(<b>) -->.");
+
+        // Citations already separated by text are left as they are
+        let result = convert_citations("[@a], [@b]", &PreprocessOptions::default());
+        assert_eq!(result.markdown, "<!--raw-typst #cite(<a>) -->, <!--raw-typst #cite(<b>) -->");
+    }
+
+    #[test]
+    fn test_citation_key_prefix_stripping() {
+        let options = PreprocessOptions {
+            citation_key_prefixes: vec!["zotero:".to_string()],
+            ..Default::default()
+        };
+        let result = convert_citations("See [@zotero:smith2020], [@zotero:a; @b] and [@zotero:c, p. 3].", &options);
+        assert!(result.markdown.contains("#cite(<smith2020>)"));
+        assert!(result.markdown.contains("#cite(<a>) #cite(<b>)"));
+        assert!(result.markdown.contains("#cite(<c>, supplement: [p. 3])"));
+        assert!(!result.markdown.contains("zotero:"));
+
+        // Without configured prefixes the key is kept as written
+        let result = convert_citations("[@zotero:smith2020]", &PreprocessOptions::default());
+        assert!(result.markdown.contains("#cite(<zotero:smith2020>)"));
+    }
+
+    #[test]
+    fn test_line_wrapped_citations() {
+        let md = "As shown [@key1;\n  @key2] and [@key3;\r\n@\nkey4] and [@key5,\np. 9].";
+        let result = convert_citations(md, &PreprocessOptions::default());
+        assert_eq!(
+            result.markdown,
+            "As shown <!--raw-typst #cite(<key1>) #cite(<key2>) --> and \
+             <!--raw-typst #cite(<key3>) #cite(<key4>) --> and \
+             <!--raw-typst #cite(<key5>, supplement: [p. 9]) -->."
+        );
+
+        let keys: Vec<String> = citation_keys_by_line(md).into_iter().map(|(_, key)| key).collect();
+        assert_eq!(keys, vec!["key1", "key2", "key3", "key4", "key5"]);
+    }
+
+    #[test]
+    fn test_multi_citation_modes() {
+        let md = "Both [@a; @b] agree.";
+
+        let separate = convert_citations(md, &PreprocessOptions::default()).markdown;
+        assert!(separate.contains("<!--raw-typst #cite(<a>) #cite(<b>) -->"));
+
+        let options = PreprocessOptions {
+            multi_citation_mode: MultiCitationMode::GroupedBracket,
+            ..Default::default()
+        };
+        let grouped = convert_citations(md, &options).markdown;
+        assert!(grouped.contains("<!--raw-typst #[#cite(<a>)#cite(<b>)] -->"));
+    }
+
+    #[test]
+    fn test_multiple_supplement_locators() {
+        let result = convert_citations("See [@key, p. 42, n. 7].", &PreprocessOptions::default());
+        assert!(result.markdown.contains("#cite(<key>, supplement: [p. 42, n. 7])"));
+
+        let result = convert_citations("See [@key,  page 42 ,note  7, ].", &PreprocessOptions::default());
+        assert!(result.markdown.contains("#cite(<key>, supplement: [p. 42, n. 7])"));
+
+        let result = convert_citations("See [@key, Chapter 3].", &PreprocessOptions::default());
+        assert!(result.markdown.contains("#cite(<key>, supplement: [chap. 3])"));
+    }
+
+    #[test]
+    fn test_adversarial_citation_input() {
+        let mut md = "[@".repeat(50_000);
+        md.push_str("[@unclosed");
+        md.push_str(&"x".repeat(200_000));
+        md.push_str(" then [@ok] and [@");
+        md.push_str(&"y".repeat(MAX_CITATION_LEN + 1));
+        md.push(']');
+
+        let start = std::time::Instant::now();
+        let result = convert_citations(&md, &PreprocessOptions::default());
+        let counts = count_citations(&md);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        assert!(result.markdown.contains("#cite(<ok>)"));
+        // An over-long body is left literal
+        assert_eq!(result.markdown.matches("#cite(").count(), 1);
+        assert_eq!(counts.len(), 1);
+    }
+
+    #[test]
+    fn test_citation_keys_by_line() {
+        let md = "Intro [@a].\n\nSee [@b; @c] and\n[@d, p. 4] but not [@e??].";
+        let keys = citation_keys_by_line(md);
+        let expected = [(0, "a"), (2, "b"), (2, "c"), (3, "d")];
+        assert_eq!(keys, expected.map(|(line, key)| (line, key.to_string())));
+    }
+
+    #[test]
+    fn test_count_citations() {
+        let md = "First [@a]. Then [@a; @b] and [@a, p. 42]. Draft [@c??].";
+        let counts = count_citations(md);
+
+        assert_eq!(counts.get("a"), Some(&3));
+        assert_eq!(counts.get("b"), Some(&1));
+        assert_eq!(counts.len(), 2);
+    }
+
+    #[test]
+    fn test_cited_keys_deduplicated_in_order() {
+        let md = "See [@b]. Then [@a; @zotero:b] and [@c, p. 4]. Again [@a]. Draft [@d??].";
+        let options = PreprocessOptions {
+            citation_key_prefixes: vec!["zotero:".to_string()],
+            ..Default::default()
+        };
+        let result = convert_citations(md, &options);
+
+        assert_eq!(result.cited_keys, vec!["b", "a", "c"]);
+    }
+}
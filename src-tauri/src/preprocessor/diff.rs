@@ -0,0 +1,206 @@
+//! Structural diffs between two versions of a document's anchor map.
+//!
+//! Anchors are matched by identity rather than position, so a heading that
+//! simply shifts down because text was inserted above it is unchanged, while
+//! one that changes place relative to its neighbours is reported as moved.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use super::types::{is_offset_anchor, AnchorMeta};
+
+/// One anchor that was added, removed or moved between two documents.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct AnchorChange {
+    pub id: String,
+    /// Line in the old document (`None` for added anchors)
+    pub old_line: Option<usize>,
+    /// Line in the new document (`None` for removed anchors)
+    pub new_line: Option<usize>,
+}
+
+/// Anchors that differ between two versions of a document.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AnchorDiff {
+    pub added: Vec<AnchorChange>,
+    pub removed: Vec<AnchorChange>,
+    pub moved: Vec<AnchorChange>,
+}
+
+/// Diff two anchor maps, matching anchors by id.
+pub fn diff_anchor_maps(old: &[AnchorMeta], new: &[AnchorMeta]) -> AnchorDiff {
+    let old_keys: Vec<String> = old.iter().map(|a| a.id.clone()).collect();
+    let new_keys: Vec<String> = new.iter().map(|a| a.id.clone()).collect();
+    diff_keyed(old, &old_keys, new, &new_keys)
+}
+
+/// Diff the anchor maps of two preprocessed documents.
+///
+/// Generic paragraph anchors (`tf-<offset>-<index>`) change id whenever text
+/// above them changes length, so they are matched by the text of their first
+/// source line instead. Headings, code blocks and other named anchors are
+/// matched by id.
+pub fn diff_document_anchors(
+    old_markdown: &str,
+    old: &[AnchorMeta],
+    new_markdown: &str,
+    new: &[AnchorMeta],
+) -> AnchorDiff {
+    let old_keys = anchor_keys(old_markdown, old);
+    let new_keys = anchor_keys(new_markdown, new);
+    diff_keyed(old, &old_keys, new, &new_keys)
+}
+
+/// Identity keys for anchors, with paragraph anchors keyed by their line's text.
+fn anchor_keys(markdown: &str, anchors: &[AnchorMeta]) -> Vec<String> {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    anchors
+        .iter()
+        .map(|anchor| {
+            let key = if is_offset_anchor(&anchor.id) {
+                let text = lines.get(anchor.line).map(|line| line.trim()).unwrap_or("");
+                format!("tf-paragraph:{}", text)
+            } else {
+                anchor.id.clone()
+            };
+            // Repeated paragraphs get an occurrence suffix so keys stay unique
+            let count = seen.entry(key.clone()).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                key
+            } else {
+                format!("{}#{}", key, count)
+            }
+        })
+        .collect()
+}
+
+/// Classify anchors given parallel identity keys for both sides.
+///
+/// Anchors present on both sides are moved when they fall outside the longest
+/// common subsequence of the shared keys, i.e. their relative order changed.
+fn diff_keyed(old: &[AnchorMeta], old_keys: &[String], new: &[AnchorMeta], new_keys: &[String]) -> AnchorDiff {
+    let old_set: HashSet<&String> = old_keys.iter().collect();
+    let new_set: HashSet<&String> = new_keys.iter().collect();
+    let mut diff = AnchorDiff::default();
+
+    for (anchor, key) in new.iter().zip(new_keys) {
+        if !old_set.contains(key) {
+            diff.added.push(AnchorChange {
+                id: anchor.id.clone(),
+                old_line: None,
+                new_line: Some(anchor.line),
+            });
+        }
+    }
+    for (anchor, key) in old.iter().zip(old_keys) {
+        if !new_set.contains(key) {
+            diff.removed.push(AnchorChange {
+                id: anchor.id.clone(),
+                old_line: Some(anchor.line),
+                new_line: None,
+            });
+        }
+    }
+
+    let shared_old: Vec<usize> = (0..old.len()).filter(|&i| new_set.contains(&old_keys[i])).collect();
+    let shared_new: Vec<usize> = (0..new.len()).filter(|&j| old_set.contains(&new_keys[j])).collect();
+    let stable = longest_common_keys(&shared_old, old_keys, &shared_new, new_keys);
+    let new_index: HashMap<&String, usize> = new_keys.iter().enumerate().map(|(j, key)| (key, j)).collect();
+
+    for &i in &shared_old {
+        if stable.contains(&old_keys[i]) {
+            continue;
+        }
+        let j = new_index[&old_keys[i]];
+        diff.moved.push(AnchorChange {
+            id: new[j].id.clone(),
+            old_line: Some(old[i].line),
+            new_line: Some(new[j].line),
+        });
+    }
+
+    diff
+}
+
+/// Keys on the longest common subsequence of the shared anchors of both sides.
+fn longest_common_keys<'a>(
+    old: &[usize],
+    old_keys: &'a [String],
+    new: &[usize],
+    new_keys: &[String],
+) -> HashSet<&'a String> {
+    let (n, m) = (old.len(), new.len());
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for a in (0..n).rev() {
+        for b in (0..m).rev() {
+            lengths[a][b] = if old_keys[old[a]] == new_keys[new[b]] {
+                lengths[a + 1][b + 1] + 1
+            } else {
+                lengths[a + 1][b].max(lengths[a][b + 1])
+            };
+        }
+    }
+
+    let mut stable = HashSet::new();
+    let (mut a, mut b) = (0, 0);
+    while a < n && b < m {
+        if old_keys[old[a]] == new_keys[new[b]] {
+            stable.insert(&old_keys[old[a]]);
+            a += 1;
+            b += 1;
+        } else if lengths[a + 1][b] >= lengths[a][b + 1] {
+            a += 1;
+        } else {
+            b += 1;
+        }
+    }
+    stable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocessor::preprocess_markdown;
+
+    fn diff(old_md: &str, new_md: &str) -> AnchorDiff {
+        let old = preprocess_markdown(old_md, false).unwrap();
+        let new = preprocess_markdown(new_md, false).unwrap();
+        diff_document_anchors(old_md, &old.anchors, new_md, &new.anchors)
+    }
+
+    #[test]
+    fn test_diff_added_heading_and_moved_paragraph() {
+        let old_md = "# Intro\n\nFirst point.\n\nSecond point.\n\n## Details\n\nClosing words.";
+        let new_md = "# Intro\n\nSecond point.\n\nFirst point.\n\n## Details\n\nClosing words.\n\n## Summary";
+        let result = diff(old_md, new_md);
+
+        assert_eq!(
+            result.added,
+            vec![AnchorChange {
+                id: "summary".to_string(),
+                old_line: None,
+                new_line: Some(10),
+            }]
+        );
+        assert!(result.removed.is_empty());
+        assert_eq!(result.moved.len(), 1);
+        let moved = &result.moved[0];
+        assert!(moved.id.starts_with("tf-"));
+        assert_eq!((moved.old_line, moved.new_line), (Some(2), Some(4)));
+    }
+
+    #[test]
+    fn test_diff_shifted_content_is_unchanged() {
+        let old_md = "# Intro\n\nText.\n\n## Details";
+        let new_md = "# Intro\n\nText that grew\nover two lines.\n\n## Details";
+        let result = diff(old_md, new_md);
+
+        // The paragraph's first line changed, so it is replaced; the heading only shifted
+        assert_eq!(result.added.len(), 1);
+        assert_eq!(result.removed.len(), 1);
+        assert!(result.moved.is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+//! YAML frontmatter parsing utilities.
+//!
+//! `split_frontmatter` only separates the frontmatter block from the body;
+//! these helpers read the fields inside it so other stages can react to
+//! document-level settings (e.g. a declared bibliography).
+
+use serde_yaml::{Mapping, Value};
+
+use super::normalize::split_frontmatter;
+use super::types::{offset_to_line_column, AnchorMeta};
+
+/// Frontmatter fields that get an editor-side `fm-<field>` anchor.
+const ANCHORED_FIELDS: [&str; 4] = ["title", "subtitle", "author", "date"];
+
+/// Parse a frontmatter block (including its `---` delimiters) into a YAML mapping.
+///
+/// Returns `None` if the block is empty, malformed, or not a mapping at the top level.
+pub fn parse_frontmatter(frontmatter: &str) -> Option<Mapping> {
+    let body = frontmatter_body(frontmatter)?;
+    match serde_yaml::from_str::<Value>(body) {
+        Ok(Value::Mapping(fields)) => Some(fields),
+        _ => None,
+    }
+}
+
+/// Look up a top-level string field in parsed frontmatter.
+///
+/// Blank values are treated as missing.
+pub fn frontmatter_str<'a>(fields: &'a Mapping, key: &str) -> Option<&'a str> {
+    fields
+        .get(key)
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+/// Look up a top-level non-negative integer field in parsed frontmatter.
+///
+/// Accepts both YAML numbers (`180`) and numeric strings (`"180"`).
+pub fn frontmatter_u64(fields: &Mapping, key: &str) -> Option<u64> {
+    match fields.get(key)? {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Editor-side anchors (`fm-title`, `fm-author`, ...) for recognized top-level fields.
+///
+/// These have no Typst label; they only let the editor map the frontmatter region.
+/// Offsets are relative to the start of `frontmatter`, which is the start of the document.
+pub fn frontmatter_field_anchors(frontmatter: &str) -> Vec<AnchorMeta> {
+    let mut anchors = Vec::new();
+    let mut offset = 0;
+
+    for line in frontmatter.split_inclusive('\n') {
+        // Only unindented keys are top-level fields
+        let key = line.split_once(':').map(|(key, _)| key);
+        if let Some(field) = key.filter(|key| ANCHORED_FIELDS.contains(key)) {
+            let id = format!("fm-{}", field);
+            if !anchors.iter().any(|a: &AnchorMeta| a.id == id) {
+                let (line, column) = offset_to_line_column(frontmatter, offset);
+                anchors.push(AnchorMeta {
+                    id,
+                    offset,
+                    line,
+                    column,
+                    preview: None,
+                    seq: 0,
+                });
+            }
+        }
+        offset += line.len();
+    }
+
+    anchors
+}
+
+/// Set a top-level frontmatter field, re-serializing the frontmatter as YAML.
+///
+/// The body is kept byte for byte. A document without frontmatter gets a new block.
+/// If the existing frontmatter is not a YAML mapping, the document is returned
+/// unchanged rather than overwriting what the user wrote.
+pub fn set_frontmatter_field(markdown: &str, key: &str, value: Value) -> String {
+    let (frontmatter, body) = split_frontmatter(markdown);
+    let is_blank = frontmatter.is_empty()
+        || frontmatter_body(frontmatter).is_some_and(|yaml| yaml.trim().is_empty());
+    let mut fields = if is_blank {
+        Mapping::new()
+    } else {
+        match parse_frontmatter(frontmatter) {
+            Some(fields) => fields,
+            None => return markdown.to_string(),
+        }
+    };
+
+    fields.insert(Value::String(key.to_string()), value);
+    let yaml = match serde_yaml::to_string(&fields) {
+        Ok(yaml) => yaml,
+        Err(_) => return markdown.to_string(),
+    };
+    format!("---\n{}---\n{}", yaml, body)
+}
+
+/// Strip the `---` delimiters from a frontmatter block, returning the YAML between them.
+fn frontmatter_body(frontmatter: &str) -> Option<&str> {
+    frontmatter
+        .trim()
+        .strip_prefix("---")?
+        .strip_suffix("---")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_frontmatter_fields() {
+        let fm = "---\ntitle: Test\nbibliography: refs.bib\n---\n";
+        let fields = parse_frontmatter(fm).unwrap();
+        assert_eq!(frontmatter_str(&fields, "title"), Some("Test"));
+        assert_eq!(frontmatter_str(&fields, "bibliography"), Some("refs.bib"));
+        assert_eq!(frontmatter_str(&fields, "author"), None);
+
+        let fields = parse_frontmatter("---\nreading_speed: 180\nwpm: \"220\"\nbad: fast\n---").unwrap();
+        assert_eq!(frontmatter_u64(&fields, "reading_speed"), Some(180));
+        assert_eq!(frontmatter_u64(&fields, "wpm"), Some(220));
+        assert_eq!(frontmatter_u64(&fields, "bad"), None);
+    }
+
+    #[test]
+    fn test_parse_frontmatter_invalid() {
+        assert!(parse_frontmatter("").is_none());
+        assert!(parse_frontmatter("---\n- just\n- a list\n---").is_none());
+        assert!(parse_frontmatter("---\ntitle: [unclosed\n---").is_none());
+    }
+
+    #[test]
+    fn test_set_frontmatter_field() {
+        let md = "---\ntitle: Report\npage:\n  size: a4\n---\n# Heading\n\nBody text.\n";
+        let result = set_frontmatter_field(md, "orientation", Value::String("landscape".to_string()));
+        let (frontmatter, body) = split_frontmatter(&result);
+        let fields = parse_frontmatter(frontmatter).unwrap();
+        assert_eq!(frontmatter_str(&fields, "orientation"), Some("landscape"));
+        assert_eq!(frontmatter_str(&fields, "title"), Some("Report"));
+        assert_eq!(body, "# Heading\n\nBody text.\n");
+
+        // Existing keys are updated in place
+        let result = set_frontmatter_field(&result, "title", Value::String("Final".to_string()));
+        let fields = parse_frontmatter(split_frontmatter(&result).0).unwrap();
+        assert_eq!(frontmatter_str(&fields, "title"), Some("Final"));
+        assert_eq!(fields.len(), 3);
+
+        // Malformed frontmatter is left alone
+        let broken = "---\ntitle: [unclosed\n---\nText";
+        assert_eq!(set_frontmatter_field(broken, "a", Value::Bool(true)), broken);
+    }
+
+    #[test]
+    fn test_set_frontmatter_field_creates_block() {
+        let md = "# Heading\n\nBody text.";
+        let result = set_frontmatter_field(md, "reading_speed", Value::Number(180.into()));
+        assert_eq!(result, "---\nreading_speed: 180\n---\n# Heading\n\nBody text.");
+
+        let (frontmatter, body) = split_frontmatter(&result);
+        assert_eq!(frontmatter_u64(&parse_frontmatter(frontmatter).unwrap(), "reading_speed"), Some(180));
+        assert_eq!(body, md);
+    }
+
+    #[test]
+    fn test_frontmatter_field_anchors() {
+        let fm = "---\ntitle: Report\nauthors:\n  - title: nested\nauthor: Ada\n---\n";
+        let anchors = frontmatter_field_anchors(fm);
+
+        let ids: Vec<&str> = anchors.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, vec!["fm-title", "fm-author"]);
+        assert_eq!((anchors[0].offset, anchors[0].line, anchors[0].column), (4, 1, 0));
+        assert_eq!(anchors[1].offset, fm.find("author: Ada").unwrap());
+        assert_eq!(anchors[1].line, 4);
+    }
+}
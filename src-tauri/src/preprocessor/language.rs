@@ -0,0 +1,78 @@
+//! Document language detection for Typst hyphenation.
+//!
+//! A frontmatter `lang:` key always wins; otherwise the body is scored against
+//! short lists of very common function words.
+
+use super::frontmatter::{frontmatter_str, parse_frontmatter};
+
+/// Fewest stopword hits before the heuristic commits to a language.
+const MIN_STOPWORD_HITS: usize = 3;
+
+/// ISO 639-1 code and the most frequent short words of each detectable language.
+const STOPWORDS: [(&str, &[&str]); 8] = [
+    ("en", &["the", "and", "of", "to", "is", "in", "that", "with", "for", "this"]),
+    ("de", &["der", "die", "das", "und", "ist", "nicht", "mit", "ein", "eine", "auch"]),
+    ("fr", &["le", "la", "les", "et", "est", "des", "une", "dans", "pour", "pas"]),
+    ("es", &["el", "los", "las", "y", "es", "del", "una", "por", "para", "con"]),
+    ("it", &["il", "di", "che", "è", "per", "gli", "una", "della", "sono", "non"]),
+    ("pt", &["o", "os", "e", "é", "do", "da", "uma", "não", "com", "para"]),
+    ("nl", &["de", "het", "een", "en", "van", "is", "niet", "met", "dat", "op"]),
+    ("tr", &["ve", "bir", "bu", "için", "ile", "da", "de", "olarak", "çok", "gibi"]),
+];
+
+/// Language code for Typst's `set text(lang: ..)`.
+///
+/// Prefers a non-empty frontmatter `lang:` value as written (e.g. `de`, `pt-BR`);
+/// otherwise guesses from `body`, returning `None` when the text is too short or
+/// too mixed to tell.
+pub fn detect_language(frontmatter: &str, body: &str) -> Option<String> {
+    let declared = parse_frontmatter(frontmatter)
+        .and_then(|fields| frontmatter_str(&fields, "lang").map(str::to_string));
+    declared.or_else(|| guess_language(body).map(str::to_string))
+}
+
+/// Pick the language whose stopwords occur most often in `text`.
+fn guess_language(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    let mut scores: Vec<(&str, usize)> = STOPWORDS
+        .iter()
+        .map(|(lang, stopwords)| {
+            let hits = words.iter().filter(|word| stopwords.contains(&word.as_str())).count();
+            (*lang, hits)
+        })
+        .collect();
+    scores.sort_by_key(|&(_, hits)| std::cmp::Reverse(hits));
+
+    match scores.as_slice() {
+        [(lang, best), (_, second), ..] if *best >= MIN_STOPWORD_HITS && best > second => Some(lang),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guess_language() {
+        assert_eq!(
+            guess_language("The cat sat on the mat and looked at the door of the house."),
+            Some("en")
+        );
+        assert_eq!(
+            guess_language("Das ist nicht der Weg, und die Katze ist auch nicht hier."),
+            Some("de")
+        );
+        assert_eq!(
+            guess_language("Bu proje için çok güzel bir örnek ve kolay bir başlangıç."),
+            Some("tr")
+        );
+        // Too little text to tell
+        assert_eq!(guess_language("Hello world"), None);
+    }
+}
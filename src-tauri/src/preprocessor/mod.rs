@@ -6,95 +6,75 @@
 //! # Module Structure
 //!
 //! - `types`: Core data structures for positions and anchors
-//! - `normalize`: Markdown normalization (frontmatter, tables)
+//! - `normalize`: Markdown normalization (frontmatter, tables, list indentation)
+//! - `frontmatter`: YAML frontmatter field parsing
 //! - `anchors`: Anchor injection logic
+//! - `citations`: Pandoc-style citation conversion
+//! - `diff`: Structural diffs between anchor maps
+//! - `language`: Document language detection
 //! - `source_map`: PDF position mapping utilities
+//!
+//! The module is public as `tideflow_lib::preprocessor`. Helpers the app's own
+//! commands do not call, such as incremental reprocessing, anchor map diffs, source
+//! map queries and merging, are library-only API for tooling built on the crate.
 
 mod anchors;
+mod citations;
+mod diff;
+mod frontmatter;
+mod language;
 mod normalize;
 mod source_map;
 mod types;
 
 // Re-export public API
-pub use source_map::{attach_pdf_positions, pdf_positions_from_query};
+pub use anchors::{decode_html_entities, inject_anchors, slugify};
+pub use citations::citation_keys_by_line;
+pub use diff::{diff_anchor_maps, diff_document_anchors, AnchorChange, AnchorDiff};
+pub use frontmatter::{frontmatter_str, frontmatter_u64, parse_frontmatter, set_frontmatter_field};
+pub use normalize::{malformed_table_rows, split_frontmatter};
+pub use source_map::{
+    attach_pdf_positions, first_anchor_on_page, interpolated_pdf_position, max_page, merge_source_maps,
+    pdf_positions_from_query, write_source_map_ndjson,
+};
 pub use types::{
-    offset_to_line_column, AnchorMeta, PdfPosition, PreprocessorOutput, SourceMapPayload,
+    anchor_category, AnchorMeta, CodeBlockInfo, PdfPosition, PreprocessOptions, PreprocessorOutput, SourceMapPayload,
 };
 // These are used by other modules but may not be used directly by lib.rs consumers
 #[allow(unused_imports)]
-pub use types::{anchors_to_lookup, AnchorEntry, EditorPosition};
+pub use types::{
+    anchors_by_line, anchors_to_lookup, inserted_bytes_before, offset_to_line_column,
+    refresh_anchor_positions, shift_anchor_offsets, structural_fingerprint, AnchorEntry, AnchorSyntax, EditorPosition, EmptyHeadingMode,
+    ExportMode, HeadingLabelPlacement, MultiCitationMode, PhaseTimings, RawHtmlMode, RuleAnchorMode, SlugSuffixStyle,
+    DEFAULT_MAX_INPUT_BYTES,
+};
 
 use anyhow::Result;
-use normalize::{ensure_blank_lines_before_tables, split_frontmatter};
-use anchors::inject_anchors;
-use regex::Regex;
-
-/// Convert Pandoc-style citations to Typst format.
-///
-/// Converts:
-/// - `[@key]` → `<!--raw-typst #cite(<key>) -->`
-/// - `[@key1; @key2]` → `<!--raw-typst #cite(<key1>) #cite(<key2>) -->`
-/// - `[@key, p. 42]` → `<!--raw-typst #cite(<key>, supplement: [p. 42]) -->`
-///
-/// This enables bibliography support using familiar Pandoc citation syntax.
-fn convert_citations(markdown: &str) -> String {
-    // Regex to match Pandoc citations: [@key] or [@key, supplement]
-    // Pattern matches: [@citation-key] or [@key1; @key2] or [@key, p. 42]
-    let re = Regex::new(r"\[@([^\]]+)\]").unwrap();
-
-    re.replace_all(markdown, |caps: &regex::Captures| {
-        let inner = &caps[1];
-
-        // Check if this is multiple citations (contains semicolon)
-        if inner.contains(';') {
-            // Multiple citations: [@key1; @key2] → #cite(<key1>) #cite(<key2>)
-            let citations: Vec<&str> = inner.split(';')
-                .map(|s| s.trim().trim_start_matches('@'))
-                .filter(|key| !key.is_empty()) // Skip empty keys
-                .collect();
-
-            // If no valid citations, return original text
-            if citations.is_empty() {
-                return caps[0].to_string();
-            }
-
-            let cite_calls = citations.iter()
-                .map(|key| format!("#cite(<{}>)", key))
-                .collect::<Vec<_>>()
-                .join(" ");
-            format!("<!--raw-typst {} -->", cite_calls)
-        } else if inner.contains(',') {
-            // Citation with supplement: [@key, p. 42] → #cite(<key>, supplement: [p. 42])
-            let parts: Vec<&str> = inner.splitn(2, ',').collect();
-            let key = parts[0].trim().trim_start_matches('@');
-
-            // If key is empty, return original text
-            if key.is_empty() {
-                return caps[0].to_string();
-            }
-
-            let supplement = parts[1].trim();
-            format!("<!--raw-typst #cite(<{}>, supplement: [{}]) -->", key, supplement)
-        } else {
-            // Simple citation: [@key] → #cite(<key>)
-            let key = inner.trim().trim_start_matches('@');
-
-            // If key is empty, return original text (incomplete citation)
-            if key.is_empty() {
-                return caps[0].to_string();
-            }
+use crate::error::PreprocessError;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Instant;
+use normalize::{
+    apply_draft_blocks, apply_image_width_hints, cap_image_widths, convert_footnotes, convert_latex_refs, convert_page_breaks, ensure_blank_lines_before_tables,
+    fix_atx_heading_spacing, normalize_line_separators, normalize_list_indentation, repair_unbalanced_emphasis,
+    soften_long_table_cells, strip_author_comments, transform_html_blocks, unbalanced_emphasis, COMMENT_RE,
+};
+use anchors::{inject_anchors_with_options, inject_anchors_with_previous, write_anchored};
+use citations::{convert_citations, count_citations, CitationConversion};
+use frontmatter::frontmatter_field_anchors;
+use language::detect_language;
+use types::number_anchors;
 
-            format!("<!--raw-typst #cite(<{}>) -->", key)
-        }
-    }).to_string()
-}
+/// UTF-8 byte order mark some editors write at the start of a file.
+const BOM: char = '\u{feff}';
 
 /// Transform user markdown by injecting invisible Typst anchors for scroll sync.
 ///
 /// This is the main entry point for the preprocessor. It:
-/// 1. Preserves YAML frontmatter if present
+/// 1. Preserves a leading byte order mark and YAML frontmatter if present
 /// 2. Converts Pandoc-style citations to Typst format (only if has_bibliography is true)
-/// 3. Normalizes markdown (ensures blank lines before tables)
+/// 3. Normalizes markdown (page breaks, list indentation, blank lines before tables)
 /// 4. Injects anchor labels for scroll synchronization
 /// 5. Generates heading labels for internal links
 ///
@@ -111,36 +91,383 @@ fn convert_citations(markdown: &str) -> String {
 /// // output.anchors contains metadata for each anchor
 /// ```
 pub fn preprocess_markdown(markdown: &str, has_bibliography: bool) -> Result<PreprocessorOutput> {
-    // Skip YAML frontmatter if present
+    let options = PreprocessOptions {
+        has_bibliography,
+        ..Default::default()
+    };
+    preprocess_markdown_with_options(markdown, &options)
+}
+
+/// Transform user markdown using explicit [`PreprocessOptions`].
+///
+/// See [`preprocess_markdown`] for the stages involved.
+pub fn preprocess_markdown_with_options(
+    markdown: &str,
+    options: &PreprocessOptions,
+) -> Result<PreprocessorOutput> {
+    preprocess_with_previous(markdown, options, None)
+}
+
+/// Transform user markdown, reusing `previous` anchors outside
+/// [`PreprocessOptions::focus_range`].
+///
+/// Meant for incremental preview while typing: `previous` must already describe the
+/// current text (see [`shift_anchor_offsets`] and [`refresh_anchor_positions`]).
+/// Without a focus range this is identical to [`preprocess_markdown_with_options`].
+pub fn preprocess_markdown_with_previous(
+    markdown: &str,
+    options: &PreprocessOptions,
+    previous: &PreprocessorOutput,
+) -> Result<PreprocessorOutput> {
+    preprocess_with_previous(markdown, options, Some(previous))
+}
+
+fn preprocess_with_previous(
+    markdown: &str,
+    options: &PreprocessOptions,
+    previous: Option<&PreprocessorOutput>,
+) -> Result<PreprocessorOutput> {
+    check_input_size(markdown, options)?;
+    let prepared = prepare_body(markdown, options);
+    let mut timings = prepared.timings;
+    let options = options_with_frontmatter(options, prepared.frontmatter);
+    let options = options.as_ref();
+
+    let started = Instant::now();
+    let mut result = match (previous, &options.focus_range) {
+        (Some(previous), Some(focus)) => {
+            // Cached anchors and the focus range are in editor coordinates
+            let (offset_adjustment, line_adjustment) = body_shift(prepared.bom, prepared.frontmatter);
+            let carried: Vec<AnchorMeta> = previous
+                .anchors
+                .iter()
+                .filter(|anchor| !anchor.id.starts_with("fm-") && anchor.offset >= offset_adjustment)
+                .map(|anchor| {
+                    let line = anchor.line.saturating_sub(line_adjustment);
+                    AnchorMeta {
+                        offset: (anchor.offset - offset_adjustment)
+                            .saturating_sub(separator_bytes_before(&prepared.separator_lines, line)),
+                        line,
+                        ..anchor.clone()
+                    }
+                })
+                .collect();
+            let focus = focus.start.saturating_sub(offset_adjustment)
+                ..focus.end.saturating_sub(offset_adjustment);
+            inject_anchors_with_previous(&prepared.body, options, focus, &carried)?
+        }
+        _ => inject_anchors_with_options(&prepared.body, options)?,
+    };
+    timings.anchor_injection = started.elapsed();
+
+    let started = Instant::now();
+    restore_separator_offsets(&mut result, &prepared.separator_lines);
+    result.draft_citations = prepared.draft_citations;
+    result.warnings.splice(0..0, prepared.warnings);
+    if let Some(known_keys) = &options.known_keys {
+        result.unknown_citations = prepared
+            .citation_counts
+            .keys()
+            .filter(|key| !known_keys.contains(*key))
+            .cloned()
+            .collect();
+        result.unknown_citations.sort();
+    }
+    result.citation_counts = prepared.citation_counts;
+    result.cited_keys = prepared.cited_keys;
+    result.is_empty = is_body_empty(&prepared.body);
+    result.detected_lang = detect_language(prepared.frontmatter, &prepared.body);
+    result.was_normalized = prepared.was_normalized;
+    
+    // Prepend the BOM and frontmatter back if they existed
+    let (offset_adjustment, line_adjustment) = body_shift(prepared.bom, prepared.frontmatter);
+    if offset_adjustment > 0 {
+        let separator = if prepared.frontmatter.is_empty() { "" } else { "\n" };
+        result.markdown = format!("{}{}{}{}", prepared.bom, prepared.frontmatter, separator, result.markdown);
+        shift_anchors(&mut result.anchors, (offset_adjustment, line_adjustment));
+        for position in result.footnotes.values_mut() {
+            position.offset += offset_adjustment;
+            position.line += line_adjustment;
+        }
+        for (offset, _) in &mut result.insertions {
+            *offset += offset_adjustment;
+        }
+        if !prepared.frontmatter.is_empty() {
+            // The separator newline between frontmatter and body
+            result.insertions.insert(0, (offset_adjustment, 1));
+            if options.frontmatter_anchors {
+                result.anchors.splice(0..0, frontmatter_anchors(prepared.bom, prepared.frontmatter));
+            }
+        }
+    }
+    number_anchors(&mut result.anchors);
+    timings.output_build = started.elapsed();
+
+    if options.record_timings {
+        result.timings = Some(timings);
+    }
+    Ok(result)
+}
+
+/// Stream preprocessed markdown into `out` instead of building it in memory.
+///
+/// Produces byte-identical output to [`preprocess_markdown_with_options`] but writes
+/// original spans interleaved with anchor snippets, which avoids the repeated
+/// in-place inserts on multi-megabyte documents. Returns the anchor metadata.
+pub fn write_preprocessed<W: Write>(
+    markdown: &str,
+    options: &PreprocessOptions,
+    mut out: W,
+) -> Result<Vec<AnchorMeta>> {
+    check_input_size(markdown, options)?;
+    let prepared = prepare_body(markdown, options);
+    let options = options_with_frontmatter(options, prepared.frontmatter);
+    let options = options.as_ref();
+
+    out.write_all(prepared.bom.as_bytes())?;
+    if !prepared.frontmatter.is_empty() {
+        out.write_all(prepared.frontmatter.as_bytes())?;
+        out.write_all(b"\n")?;
+    }
+
+    let mut anchors = write_anchored(&prepared.body, options, &mut out)?;
+    for anchor in &mut anchors {
+        anchor.offset += separator_bytes_before(&prepared.separator_lines, anchor.line);
+    }
+    shift_anchors(&mut anchors, body_shift(prepared.bom, prepared.frontmatter));
+    if !prepared.frontmatter.is_empty() && options.frontmatter_anchors {
+        anchors.splice(0..0, frontmatter_anchors(prepared.bom, prepared.frontmatter));
+    }
+    number_anchors(&mut anchors);
+    out.flush()?;
+
+    Ok(anchors)
+}
+
+/// Refuse inputs over [`PreprocessOptions::max_input_bytes`] before any work is done.
+fn check_input_size(markdown: &str, options: &PreprocessOptions) -> Result<()> {
+    let limit = options.max_input_bytes.unwrap_or(DEFAULT_MAX_INPUT_BYTES);
+    if markdown.len() > limit {
+        return Err(PreprocessError::TooLarge {
+            size: markdown.len(),
+            limit,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Apply per-document overrides from frontmatter keys (`default_code_lang:`).
+///
+/// Borrows `options` unchanged when the frontmatter sets none of them.
+fn options_with_frontmatter<'o>(options: &'o PreprocessOptions, frontmatter: &str) -> Cow<'o, PreprocessOptions> {
+    let fields = parse_frontmatter(frontmatter);
+    let default_code_lang = fields
+        .as_ref()
+        .and_then(|fields| frontmatter_str(fields, "default_code_lang"))
+        .map(str::trim)
+        .filter(|lang| !lang.is_empty() && !lang.contains(char::is_whitespace));
+    match default_code_lang {
+        Some(lang) => Cow::Owned(PreprocessOptions {
+            default_code_lang: Some(lang.to_string()),
+            ..options.clone()
+        }),
+        None => Cow::Borrowed(options),
+    }
+}
+
+/// Markdown body after frontmatter splitting, citation conversion and normalization.
+struct PreparedBody<'a> {
+    /// Leading UTF-8 byte order mark, kept ahead of the frontmatter and every anchor
+    bom: &'a str,
+    frontmatter: &'a str,
+    body: String,
+    draft_citations: Vec<String>,
+    citation_counts: HashMap<String, usize>,
+    cited_keys: Vec<String>,
+    /// Diagnostics about the source, with editor line numbers
+    warnings: Vec<String>,
+    /// Citation conversion or normalization changed the body
+    was_normalized: bool,
+    /// Lines ended by a U+2028/U+2029 separator, which the body shortens to `\n`
+    separator_lines: Vec<usize>,
+    /// Timings of the phases run so far
+    timings: PhaseTimings,
+}
+
+/// Run every stage that precedes anchor injection.
+fn prepare_body<'a>(markdown: &'a str, options: &PreprocessOptions) -> PreparedBody<'a> {
+    let mut timings = PhaseTimings::default();
+
+    // Skip a byte order mark and YAML frontmatter if present
+    let started = Instant::now();
+    let bom_len = if markdown.starts_with(BOM) { BOM.len_utf8() } else { 0 };
+    let (bom, markdown) = markdown.split_at(bom_len);
     let (frontmatter, content) = split_frontmatter(markdown);
+    timings.frontmatter_split = started.elapsed();
+
+    // Draft-only blocks go first so dropped text is never counted or anchored
+    let content = apply_draft_blocks(content, options.export_mode);
+    let content = content.as_str();
+
+    let started = Instant::now();
+
+    // Count citations before conversion so usage is known even without a bibliography
+    let citation_counts = count_citations(content);
+
+    let body_line = body_shift(bom, frontmatter).1;
+    let warnings = unbalanced_emphasis(content)
+        .into_iter()
+        .map(|(line, marker)| {
+            format!(
+                "Line {}: `{}` has no matching emphasis marker in its block",
+                body_line + line + 1,
+                marker
+            )
+        })
+        .collect();
 
     // Convert Pandoc citations to Typst format ONLY if bibliography is loaded
-    // This prevents "document does not contain a bibliography" errors
-    let with_citations = if has_bibliography {
-        convert_citations(content)
+    // This prevents "document does not contain a bibliography" errors.
+    // Preview mode skips the bibliography, so citations stay literal there too
+    let citations = if options.has_bibliography && !options.preview_mode {
+        convert_citations(content, options)
     } else {
-        content.to_string()
+        CitationConversion {
+            markdown: content.to_string(),
+            ..Default::default()
+        }
     };
+    timings.citation_conversion = started.elapsed();
 
-    // Normalize markdown: ensure blank line before tables
-    let normalized = ensure_blank_lines_before_tables(&with_citations);
-    let mut result = inject_anchors(&normalized)?;
-    
-    // Prepend frontmatter back if it existed
-    if !frontmatter.is_empty() {
-        result.markdown = format!("{}\n{}", frontmatter, result.markdown);
-        
-        // Adjust all anchor offsets to account for frontmatter
-        let offset_adjustment = frontmatter.len() + 1; // +1 for the newline we added
-        for anchor in &mut result.anchors {
-            anchor.offset += offset_adjustment;
-            let (line, column) = offset_to_line_column(&result.markdown, anchor.offset);
-            anchor.line = line;
-            anchor.column = column;
+    // Normalize markdown: line separators, page break sentinels, author comments,
+    // consistent list indentation, blank line before tables
+    let started = Instant::now();
+    let separator_lines = separator_line_ends(content);
+    let body = normalize_line_separators(&citations.markdown);
+    let body = if options.repair_emphasis {
+        repair_unbalanced_emphasis(&body)
+    } else {
+        body
+    };
+    let body = convert_page_breaks(&body);
+    let body = if options.latex_cross_refs {
+        convert_latex_refs(&body)
+    } else {
+        body
+    };
+    let body = if options.explicit_footnotes {
+        convert_footnotes(&body)
+    } else {
+        body
+    };
+    let body = if options.keep_author_comments {
+        body
+    } else {
+        strip_author_comments(&body)
+    };
+    let body = transform_html_blocks(&body, options.raw_html);
+    let body = if options.lenient_atx_headings {
+        fix_atx_heading_spacing(&body)
+    } else {
+        body
+    };
+    let body = normalize_list_indentation(&body);
+    let body = ensure_blank_lines_before_tables(&body);
+    let body = match options.table_cell_break_length {
+        Some(max_run) => soften_long_table_cells(&body, max_run),
+        None => body,
+    };
+    let body = apply_image_width_hints(&body);
+    let body = match &options.max_image_width {
+        Some(max_width) => cap_image_widths(&body, max_width),
+        None => body,
+    };
+    timings.normalization = started.elapsed();
+    let was_normalized = body != content;
+
+    PreparedBody {
+        bom,
+        frontmatter,
+        body,
+        draft_citations: citations.draft_citations,
+        citation_counts,
+        cited_keys: citations.cited_keys,
+        warnings,
+        was_normalized,
+        separator_lines,
+        timings,
+    }
+}
+
+/// 0-based lines of `content` that end in a U+2028/U+2029 separator.
+fn separator_line_ends(content: &str) -> Vec<usize> {
+    content
+        .chars()
+        .filter(|&c| matches!(c, '\n' | '\u{2028}' | '\u{2029}'))
+        .enumerate()
+        .filter(|&(_, c)| c != '\n')
+        .map(|(line, _)| line)
+        .collect()
+}
+
+/// Bytes the source has before `line` beyond the body: each 3-byte separator above it
+/// became a 1-byte `\n`.
+fn separator_bytes_before(separator_lines: &[usize], line: usize) -> usize {
+    2 * separator_lines.partition_point(|&end| end < line)
+}
+
+/// Move body offsets back onto the source text around U+2028/U+2029 separators.
+fn restore_separator_offsets(result: &mut PreprocessorOutput, separator_lines: &[usize]) {
+    if separator_lines.is_empty() {
+        return;
+    }
+    for anchor in &mut result.anchors {
+        anchor.offset += separator_bytes_before(separator_lines, anchor.line);
+    }
+    for position in result.footnotes.values_mut() {
+        position.offset += separator_bytes_before(separator_lines, position.line);
+    }
+}
+
+/// Check whether a markdown body would render nothing.
+///
+/// HTML comments are invisible in the PDF, except `<!--raw-typst ... -->`
+/// passthroughs which emit Typst content.
+fn is_body_empty(body: &str) -> bool {
+    let visible = COMMENT_RE.replace_all(body, |caps: &regex::Captures| {
+        if caps[0].starts_with("<!--raw-typst") {
+            caps[0].to_string()
+        } else {
+            String::new()
         }
+    });
+    visible.trim().is_empty()
+}
+
+/// Byte and line offsets of the body within the original markdown.
+///
+/// The separator newline added to the output after the frontmatter is not part of
+/// the source, so only the BOM and the frontmatter itself are accounted for.
+fn body_shift(bom: &str, frontmatter: &str) -> (usize, usize) {
+    (bom.len() + frontmatter.len(), frontmatter.matches('\n').count())
+}
+
+/// Editor-only frontmatter field anchors, in original markdown coordinates.
+fn frontmatter_anchors(bom: &str, frontmatter: &str) -> Vec<AnchorMeta> {
+    let mut anchors = frontmatter_field_anchors(frontmatter);
+    for anchor in &mut anchors {
+        anchor.offset += bom.len();
+    }
+    anchors
+}
+
+/// Adjust body-relative anchors so they point into the original (editor) markdown.
+fn shift_anchors(anchors: &mut [AnchorMeta], (offset_adjustment, line_adjustment): (usize, usize)) {
+    for anchor in anchors {
+        anchor.offset += offset_adjustment;
+        anchor.line += line_adjustment;
     }
-    
-    Ok(result)
 }
 
 #[cfg(test)]
@@ -166,6 +493,84 @@ mod tests {
         assert!(result.markdown.contains("#label(\"hello\")"));
     }
 
+    #[test]
+    fn test_frontmatter_followed_by_rule() {
+        let md = "---\ntitle: Test\n---\n---\n\nBody text.\n\n## Next\n";
+        let result = preprocess_markdown(md, false).unwrap();
+
+        // The leading rule is body content, anchored as the first rule
+        let hr = result.anchors.iter().find(|a| a.id == "tf-hr-1").expect("rule anchor");
+        assert_eq!(hr.offset, "---\ntitle: Test\n---\n".len());
+        assert_eq!((hr.line, hr.column), (3, 0));
+        assert!(result.markdown.contains("#label(\"tf-hr-1\") -->\n---\n"));
+
+        // Offsets and lines point into the original source, not the anchored output
+        for anchor in result.anchors.iter().filter(|a| a.id != "tf-doc-start") {
+            assert_eq!((anchor.line, anchor.column), offset_to_line_column(md, anchor.offset));
+        }
+        let body = result.anchors.iter().find(|a| a.id.starts_with("tf-") && a.offset > hr.offset).unwrap();
+        assert_eq!(body.offset, md.find("Body text.").unwrap());
+        let heading = result.anchors.iter().find(|a| a.id == "next").unwrap();
+        assert_eq!(heading.offset, md.find("## Next").unwrap());
+    }
+
+    #[test]
+    fn test_footnote_definition_positions() {
+        let md = "---\ntitle: Notes\n---\nClaim one[^src] and two[^2].\n\n[^src]: Source text.\n\n[^2]: Second note\n    continues here.\n";
+        let result = preprocess_markdown(md, false).unwrap();
+
+        assert_eq!(result.footnotes.len(), 2);
+        let src = &result.footnotes["src"];
+        assert_eq!(src.offset, md.find("[^src]:").unwrap());
+        assert_eq!((src.line, src.column), (5, 0));
+        let second = &result.footnotes["2"];
+        assert_eq!(second.offset, md.find("[^2]:").unwrap());
+        assert_eq!((second.line, second.column), (7, 0));
+    }
+
+    #[test]
+    fn test_bom_before_frontmatter() {
+        let md = "\u{feff}---\ntitle: Field Notes\n---\n# Hi\n";
+        let options = PreprocessOptions {
+            frontmatter_anchors: true,
+            ..Default::default()
+        };
+        let result = preprocess_markdown_with_options(md, &options).unwrap();
+
+        assert!(result.markdown.starts_with("\u{feff}---\ntitle: Field Notes\n---\n"));
+        let title = result.anchors.iter().find(|a| a.id == "fm-title").expect("frontmatter detected");
+        assert_eq!(title.offset, md.find("title:").unwrap());
+        let heading = result.anchors.iter().find(|a| a.id == "hi").unwrap();
+        assert_eq!((heading.offset, heading.line), (md.find("# Hi").unwrap(), 3));
+
+        // Without frontmatter the doc-start anchor still follows the BOM
+        let result = preprocess_markdown("\u{feff}Intro text.\n", false).unwrap();
+        assert!(result.markdown.starts_with("\u{feff}<!--raw-typst"));
+        assert_eq!(result.markdown.matches('\u{feff}').count(), 1);
+        let start = result.anchors.iter().find(|a| a.id == "tf-doc-start").unwrap();
+        assert_eq!(start.offset, BOM.len_utf8());
+    }
+
+    #[test]
+    fn test_frontmatter_field_anchors_are_editor_only() {
+        let md = "---\nlayout: paper\ntitle: Field Notes\nauthor: Ada\n---\n\n# Intro\n";
+        let options = PreprocessOptions {
+            frontmatter_anchors: true,
+            ..Default::default()
+        };
+        let result = preprocess_markdown_with_options(md, &options).unwrap();
+
+        let title = result.anchors.iter().find(|a| a.id == "fm-title").expect("title anchor");
+        assert_eq!(title.offset, md.find("title:").unwrap());
+        assert_eq!((title.line, title.column), (2, 0));
+        assert!(result.anchors.iter().any(|a| a.id == "fm-author"));
+        assert!(!result.markdown.contains("fm-title"));
+
+        // Off by default
+        let plain = preprocess_markdown(md, false).unwrap();
+        assert!(!plain.anchors.iter().any(|a| a.id.starts_with("fm-")));
+    }
+
     #[test]
     fn test_duplicate_headings() {
         let md = "# Intro\n\n# Intro\n\n# Intro";
@@ -195,6 +600,81 @@ mod tests {
         assert!(result3.markdown.contains("#cite(<einstein1905>, supplement: [p. 42])"));
     }
 
+    #[test]
+    fn test_detected_language() {
+        let md = "---\nlang: de\n---\n\nThe text is written in English, but the author says otherwise.\n";
+        let result = preprocess_markdown(md, false).unwrap();
+        assert_eq!(result.detected_lang.as_deref(), Some("de"));
+
+        let md = "# Résumé\n\nLe projet est simple et la documentation est dans le dossier pour les auteurs.\n";
+        let result = preprocess_markdown(md, false).unwrap();
+        assert_eq!(result.detected_lang.as_deref(), Some("fr"));
+
+        assert_eq!(preprocess_markdown("# Title\n", false).unwrap().detected_lang, None);
+    }
+
+    #[test]
+    fn test_anchor_seq_follows_document_order() {
+        let md = "---\ntitle: Report\nauthor: Ada\n---\n\
+            Intro paragraph.\n\n\
+            # First\n\n\
+            Some text here.\n\n\
+            ![Chart](chart.png)\n\n\
+            ## Second\n\n\
+            - item one\n- item two\n";
+        let options = PreprocessOptions {
+            frontmatter_anchors: true,
+            ..Default::default()
+        };
+        let result = preprocess_markdown_with_options(md, &options).unwrap();
+
+        assert!(result.anchors.len() > 4);
+        assert!(result.anchors.iter().any(|anchor| anchor.id.starts_with("fm-")));
+        for (index, pair) in result.anchors.windows(2).enumerate() {
+            assert!(pair[0].seq < pair[1].seq, "seq not increasing at {index}: {:?}", pair);
+            assert!(pair[0].offset <= pair[1].offset);
+        }
+        assert_eq!(result.anchors[0].seq, 0);
+
+        let payload = attach_pdf_positions(&result.anchors, &HashMap::new());
+        let seqs: Vec<usize> = payload.anchors.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, (0..result.anchors.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cited_keys_follow_document_order() {
+        let md = "---\nbibliography: refs.bib\n---\n\
+            # Intro [@heading]\n\n\
+            First [@zeta] then [@alpha; @mid].\n\n\
+            | Source | Note |\n|---|---|\n| [@table] | [@alpha] |\n\n\
+            ![Chart from [@caption]](chart.png)\n\n\
+            Last [@zeta, p. 2] and [@omega].\n";
+        let result = preprocess_markdown(md, true).unwrap();
+
+        assert_eq!(
+            result.cited_keys,
+            vec!["heading", "zeta", "alpha", "mid", "table", "caption", "omega"]
+        );
+    }
+
+    #[test]
+    fn test_preview_mode_leaves_citations_literal() {
+        let md = "As shown in [@smith2020] and [@a; @b].";
+        let options = PreprocessOptions {
+            has_bibliography: true,
+            preview_mode: true,
+            ..Default::default()
+        };
+        let result = preprocess_markdown_with_options(md, &options).unwrap();
+
+        assert!(result.markdown.contains("[@smith2020]"));
+        assert!(result.markdown.contains("[@a; @b]"));
+        assert!(!result.markdown.contains("#cite"));
+        assert!(result.cited_keys.is_empty());
+        // Usage is still counted
+        assert_eq!(result.citation_counts.get("smith2020"), Some(&1));
+    }
+
     #[test]
     fn test_citation_no_conversion_without_bibliography() {
         // Without bibliography, citations should remain as plain text to prevent crashes
@@ -207,4 +687,442 @@ mod tests {
         assert!(result.markdown.contains("[@einstein1905]"));
         assert!(result.markdown.contains("[@knuth1984; @lamport1986]"));
     }
+
+    #[test]
+    fn test_streamed_output_matches_buffered() {
+        let md = "---\ntitle: Test\n---\n\n# Intro\n\nText with [@key].\n| A | B |\n|---|---|\n| 1 | 2 |\n\n```rust\nfn main() {}\n```\n\n---\n\n## Intro\n\nEnd.";
+        let options = PreprocessOptions {
+            has_bibliography: true,
+            ..Default::default()
+        };
+
+        let buffered = preprocess_markdown_with_options(md, &options).unwrap();
+        let mut streamed = Vec::new();
+        let anchors = write_preprocessed(md, &options, &mut streamed).unwrap();
+
+        assert_eq!(String::from_utf8(streamed).unwrap(), buffered.markdown);
+        assert_eq!(anchors.len(), buffered.anchors.len());
+        for (a, b) in anchors.iter().zip(&buffered.anchors) {
+            assert_eq!((&a.id, a.offset, a.line, a.column), (&b.id, b.offset, b.line, b.column));
+        }
+    }
+
+    #[test]
+    fn test_manual_page_breaks() {
+        let md = "First page.\n\n<!-- pagebreak -->\n\nSecond page.\n\n```\n<!-- pagebreak -->\n\\pagebreak\n```\n";
+        let result = preprocess_markdown(md, false).unwrap();
+
+        assert_eq!(result.markdown.matches("<!--raw-typst #pagebreak() -->").count(), 1);
+        assert!(result.markdown.contains("```\n<!-- pagebreak -->\n\\pagebreak\n```"));
+        assert!(result.markdown.contains("#label(\"tf-pagebreak-1\")"));
+        let anchor = result.anchors.iter().find(|a| a.id == "tf-pagebreak-1").unwrap();
+        assert_eq!(anchor.line, 2);
+
+        // A lone `\pagebreak` line works too
+        let result = preprocess_markdown("One.\n\\pagebreak\nTwo.", false).unwrap();
+        assert!(result.markdown.contains("<!--raw-typst #pagebreak() -->"));
+        assert!(result.anchors.iter().any(|a| a.id == "tf-pagebreak-1"));
+
+        // Indented code is code too
+        let result = preprocess_markdown("Para one.\n\n    \\pagebreak\n\nPara two.", false).unwrap();
+        assert!(!result.markdown.contains("#pagebreak()"));
+        assert!(!result.anchors.iter().any(|a| a.id.starts_with("tf-pagebreak-")));
+    }
+
+    #[test]
+    fn test_structural_fingerprint() {
+        let original = preprocess_markdown("# Intro\n\nSome text.\n\n## Details\n\nMore text.", false).unwrap();
+        let reworded = preprocess_markdown("# Intro\n\nSome much longer text here.\n\n## Details\n\nOther words.", false).unwrap();
+        let extended = preprocess_markdown("# Intro\n\nSome text.\n\n## Details\n\nMore text.\n\n## Summary", false).unwrap();
+
+        assert_eq!(structural_fingerprint(&original), structural_fingerprint(&reworded));
+        assert_ne!(structural_fingerprint(&original), structural_fingerprint(&extended));
+    }
+
+    #[test]
+    fn test_input_size_limit() {
+        let options = PreprocessOptions {
+            max_input_bytes: Some(16),
+            ..Default::default()
+        };
+        let md = "# Heading\n\nA paragraph longer than the limit.";
+        let err = preprocess_markdown_with_options(md, &options).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PreprocessError>(),
+            Some(&PreprocessError::TooLarge { size: md.len(), limit: 16 })
+        );
+        assert!(write_preprocessed(md, &options, Vec::new()).is_err());
+
+        // At the limit is fine, and the default limit is generous
+        assert!(preprocess_markdown_with_options(&md[..16], &options).is_ok());
+        assert!(preprocess_markdown(md, false).is_ok());
+    }
+
+    #[test]
+    fn test_stripped_comments_keep_anchor_positions() {
+        let md = "# A\n\n<!-- TODO -->\n\nPara [@k] more text.\n\n<!-- spans\ntwo lines -->\n\n## B\n";
+        let result = preprocess_markdown(md, false).unwrap();
+        assert!(!result.markdown.contains("TODO"));
+
+        let para = result.anchors.iter().find(|a| a.id.starts_with("tf-") && a.line == 4).expect("paragraph anchor");
+        assert_eq!(para.offset, md.find("Para").unwrap());
+        let b = result.anchors.iter().find(|a| a.id == "b").unwrap();
+        assert_eq!(b.line, 9);
+        assert_eq!(b.offset, md.find("## B").unwrap());
+    }
+
+    #[test]
+    fn test_author_comments() {
+        let md = "Intro.\n\n<!-- TODO: fix this -->\n\nText <!-- aside --> here.\n\n`<!-- span -->`\n\n```\n<!-- fenced -->\n```\n";
+        let result = preprocess_markdown(md, false).unwrap();
+        assert!(!result.markdown.contains("TODO"));
+        assert!(result.markdown.contains("Text  here."));
+        assert!(result.markdown.contains("`<!-- span -->`"));
+        assert!(result.markdown.contains("```\n<!-- fenced -->\n```"));
+        assert!(result.markdown.contains("<!--raw-typst"));
+
+        let options = PreprocessOptions {
+            keep_author_comments: true,
+            ..Default::default()
+        };
+        let kept = preprocess_markdown_with_options(md, &options).unwrap();
+        assert!(kept.markdown.contains("\n<!-- TODO: fix this -->\n"));
+        assert!(kept.markdown.contains("Text <!-- aside --> here."));
+    }
+
+    #[test]
+    fn test_draft_blocks_follow_export_mode() {
+        let md = "Intro.\n\n:::draft\nCheck these numbers [@todo].\n:::\n\n# Results\n\nFinal text.\n\n\
+            ```\n:::draft\nliteral\n:::\n```\n";
+
+        let draft = preprocess_markdown(md, false).unwrap();
+        assert!(draft.markdown.contains("Check these numbers"));
+        assert!(!draft.markdown.contains("\n:::draft\nCheck"));
+
+        let options = PreprocessOptions {
+            export_mode: ExportMode::Final,
+            ..Default::default()
+        };
+        let fin = preprocess_markdown_with_options(md, &options).unwrap();
+        assert!(!fin.markdown.contains("Check these numbers"));
+        assert!(!fin.citation_counts.contains_key("todo"));
+        assert!(fin.markdown.contains("Final text."));
+        assert!(fin.markdown.contains("```\n:::draft\nliteral\n:::\n```"));
+        // Blanked lines keep later anchors on their source lines
+        let results = fin.anchors.iter().find(|anchor| anchor.id == "results").unwrap();
+        assert_eq!(results.line, 6);
+    }
+
+    #[test]
+    fn test_empty_document_detection() {
+        let result = preprocess_markdown("---\ntitle: Draft\n---\n\n<!-- todo -->\n   \n", false).unwrap();
+        assert!(result.is_empty);
+
+        let result = preprocess_markdown("---\ntitle: Draft\n---\n\n# Hello\n\nWorld.", false).unwrap();
+        assert!(!result.is_empty);
+
+        // Raw Typst passthroughs render, so they count as content
+        let result = preprocess_markdown("<!-- pagebreak -->", false).unwrap();
+        assert!(!result.is_empty);
+    }
+
+    #[test]
+    fn test_anchors_by_line() {
+        let md = "---\ntitle: Doc\n---\n# Intro\n\nText.\n\n## Next";
+        let options = PreprocessOptions {
+            frontmatter_anchors: true,
+            ..Default::default()
+        };
+        let result = preprocess_markdown_with_options(md, &options).unwrap();
+        let by_line = anchors_by_line(&result);
+
+        assert_eq!(by_line.get(&1), Some(&vec!["fm-title".to_string()]));
+        // tf-doc-start and the heading share the first body line
+        assert_eq!(by_line.get(&3), Some(&vec!["intro".to_string(), "tf-doc-start".to_string()]));
+        assert!(by_line.get(&5).is_some_and(|ids| ids.len() == 1 && ids[0].starts_with("tf-")));
+        assert_eq!(by_line.get(&7), Some(&vec!["next".to_string()]));
+        assert_eq!(by_line.keys().copied().collect::<Vec<_>>(), vec![1, 3, 5, 7]);
+    }
+
+    #[test]
+    fn test_inserted_bytes_before() {
+        let md = "---\ntitle: Doc\n---\n# Intro\n\nSome text.\n\n```\ncode\n```\n\n## Next";
+        let result = preprocess_markdown(md, false).unwrap();
+
+        assert_eq!(inserted_bytes_before(&result, 0), 0);
+        for needle in ["title", "# Intro", "Some text.", "code", "## Next"] {
+            let source_offset = md.find(needle).unwrap();
+            let processed_offset = source_offset + inserted_bytes_before(&result, source_offset);
+            assert!(
+                result.markdown[processed_offset..].starts_with(needle),
+                "{:?} not at mapped offset",
+                needle
+            );
+        }
+
+        let total: usize = result.insertions.iter().map(|(_, len)| len).sum();
+        assert_eq!(inserted_bytes_before(&result, md.len()), total);
+        assert_eq!(result.markdown.len(), md.len() + total);
+    }
+
+    #[test]
+    fn test_trailing_newline_is_preserved() {
+        let documents = [
+            "# Title",
+            "Intro.\n\n| A | B |\n|---|---|\n| 1 | 2 |",
+            "---\ntitle: Doc\n---\nText.\n\n---",
+            "```rust\nfn main() {}\n```",
+            "Para.\n\n![Figure](assets/fig.png)",
+        ];
+        let options = PreprocessOptions {
+            heading_label_placement: HeadingLabelPlacement::NextLine,
+            ..Default::default()
+        };
+
+        for doc in documents {
+            for input in [doc.to_string(), format!("{}\n", doc)] {
+                for result in [
+                    preprocess_markdown(&input, false).unwrap(),
+                    preprocess_markdown_with_options(&input, &options).unwrap(),
+                ] {
+                    assert_eq!(
+                        result.markdown.ends_with('\n'),
+                        input.ends_with('\n'),
+                        "{:?} -> {:?}",
+                        input,
+                        result.markdown
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_lenient_atx_headings() {
+        let md = "##Heading\n\nSee #hashtag.";
+
+        let strict = preprocess_markdown(md, false).unwrap();
+        assert!(strict.markdown.contains("##Heading"));
+        assert!(!strict.anchors.iter().any(|a| a.id == "heading"));
+
+        let options = PreprocessOptions {
+            lenient_atx_headings: true,
+            ..Default::default()
+        };
+        let lenient = preprocess_markdown_with_options(md, &options).unwrap();
+        assert!(lenient.markdown.contains("## Heading"));
+        assert!(lenient.markdown.contains("See #hashtag."));
+        assert!(lenient.anchors.iter().any(|a| a.id == "heading"));
+    }
+
+    #[test]
+    fn test_shift_anchor_offsets_after_edit() {
+        let md = "# Intro\n\nFirst paragraph.\n\n## Details\n\nMore text.";
+        let mut result = preprocess_markdown(md, false).unwrap();
+        let before = result.anchors.clone();
+
+        // Insert a line of prose inside the first paragraph
+        let edit_at = md.find("paragraph").unwrap();
+        let inserted = "long\nwrapped ";
+        let edited = format!("{}{}{}", &md[..edit_at], inserted, &md[edit_at..]);
+        shift_anchor_offsets(&mut result, edit_at, inserted.len() as isize);
+        refresh_anchor_positions(&mut result, &edited);
+
+        for (old, new) in before.iter().zip(&result.anchors) {
+            if old.offset < edit_at {
+                assert_eq!((new.offset, new.line, new.column), (old.offset, old.line, old.column));
+            } else {
+                assert_eq!(new.offset, old.offset + inserted.len());
+                assert_eq!(new.line, old.line + 1);
+            }
+        }
+        let details = result.anchors.iter().find(|a| a.id == "details").unwrap();
+        assert!(edited[details.offset..].starts_with("## Details"));
+        assert!(before.iter().any(|a| a.offset >= edit_at));
+    }
+
+    #[test]
+    fn test_focus_range_carries_anchors_outside_window() {
+        use types::is_offset_anchor;
+
+        let md: String = (0..40)
+            .map(|i| format!("Paragraph {} with a little filler text.\n\n", i))
+            .collect();
+        let mut previous = preprocess_markdown(&md, false).unwrap();
+        for anchor in &mut previous.anchors {
+            if is_offset_anchor(&anchor.id) {
+                anchor.id = format!("tf-{}-900", anchor.offset);
+            }
+        }
+
+        let edit_at = md.find("Paragraph 20 ").unwrap();
+        let options = PreprocessOptions {
+            focus_range: Some(edit_at..edit_at + 10),
+            ..Default::default()
+        };
+        let result = preprocess_markdown_with_previous(&md, &options, &previous).unwrap();
+        let window = edit_at.saturating_sub(anchors::FOCUS_PADDING)..edit_at + 10 + anchors::FOCUS_PADDING;
+
+        let paragraphs: Vec<_> = result.anchors.iter().filter(|a| is_offset_anchor(&a.id)).collect();
+        assert!(paragraphs.iter().any(|a| window.contains(&a.offset)));
+        for anchor in paragraphs {
+            let carried = anchor.id.ends_with("-900");
+            assert_eq!(carried, !window.contains(&anchor.offset), "{:?}", anchor);
+            assert!(result.markdown.contains(&format!("#label(\"{}\")", anchor.id)));
+        }
+        assert_eq!(result.anchors.len(), previous.anchors.len());
+    }
+
+    #[test]
+    fn test_unicode_line_separators() {
+        let md = "Intro text.\u{2029}# Heading\u{2028}Body line.";
+        let result = preprocess_markdown(md, false).unwrap();
+
+        let heading = result.anchors.iter().find(|a| a.id == "heading").unwrap();
+        assert_eq!((heading.line, heading.column), (1, 0));
+        assert!(result.anchors.iter().any(|a| a.id.starts_with("tf-") && a.line == 2));
+        assert!(!result.markdown.contains('\u{2028}'));
+
+        // Offsets point into the source, where each separator is 3 bytes
+        let md = "# A\u{2028}\u{2028}Para two.\u{2028}\u{2028}## B";
+        let result = preprocess_markdown(md, false).unwrap();
+        let b = result.anchors.iter().find(|a| a.id == "b").unwrap();
+        assert_eq!((b.offset, b.line, b.column), (md.find("## B").unwrap(), 4, 0));
+        let para = result.anchors.iter().find(|a| a.id.starts_with("tf-") && a.line == 2).unwrap();
+        assert_eq!(para.offset, md.find("Para").unwrap());
+
+        let written = write_preprocessed(md, &PreprocessOptions::default(), Vec::new()).unwrap();
+        assert_eq!(written.iter().find(|a| a.id == "b").unwrap().offset, md.find("## B").unwrap());
+    }
+
+    #[test]
+    fn test_unbalanced_emphasis_warning_and_repair() {
+        let md = "---\ntitle: Test\n---\nIntro.\n\nSome **bold text\n\nAfter.";
+        let result = preprocess_markdown(md, false).unwrap();
+        assert_eq!(
+            result.warnings,
+            vec!["Line 6: `**` has no matching emphasis marker in its block".to_string()]
+        );
+        assert!(result.markdown.contains("Some **bold text\n"));
+
+        let options = PreprocessOptions {
+            repair_emphasis: true,
+            ..Default::default()
+        };
+        let repaired = preprocess_markdown_with_options(md, &options).unwrap();
+        assert!(repaired.markdown.contains("Some **bold text**"));
+    }
+
+    #[test]
+    fn test_default_code_lang_from_frontmatter() {
+        let md = "---\ndefault_code_lang: python\n---\nIntro.\n\n```\nprint(1)\n```\n\n```rust\nfn main() {}\n```\n\n    indented";
+        let result = preprocess_markdown(md, false).unwrap();
+
+        assert!(result.anchors.iter().any(|a| a.id == "tf-code-python1"));
+        assert!(result.anchors.iter().any(|a| a.id == "tf-code-rust2"));
+        assert_eq!(result.code_blocks["tf-code-python1"].language, "python");
+        assert!(result.markdown.contains("```python\nprint(1)"));
+
+        let plain = preprocess_markdown("Intro.\n\n```\nprint(1)\n```", false).unwrap();
+        assert!(plain.anchors.iter().any(|a| a.id == "tf-code1"));
+    }
+
+    #[test]
+    fn test_was_normalized() {
+        let tight_table = "Intro text\n| A | B |\n|---|---|\n| 1 | 2 |";
+        assert!(preprocess_markdown(tight_table, false).unwrap().was_normalized);
+
+        let clean = "---\ntitle: Clean\n---\n# Title\n\nIntro text.\n\n| A | B |\n|---|---|\n| 1 | 2 |";
+        let result = preprocess_markdown(clean, false).unwrap();
+        assert!(!result.was_normalized);
+        assert_ne!(result.markdown, clean);
+    }
+
+    #[test]
+    fn test_explicit_footnotes() {
+        let md = "Intro.\n\nA claim[^1] with a source.\n\n[^1]: Backed by [@smith2020].\n";
+        let options = PreprocessOptions {
+            explicit_footnotes: true,
+            has_bibliography: true,
+            ..Default::default()
+        };
+        let result = preprocess_markdown_with_options(md, &options).unwrap();
+
+        assert!(result
+            .markdown
+            .contains("A claim<!--raw-typst #footnote[Backed by #cite(<smith2020>).] --> with a source."));
+        assert!(!result.markdown.contains("[^1]"));
+        assert!(preprocess_markdown(md, false).unwrap().markdown.contains("[^1]: Backed by"));
+    }
+
+    #[test]
+    fn test_latex_cross_refs_opt_in() {
+        let md = "## Intro \\label{sec:intro}\n\nSee \\ref{sec:intro}.";
+        let plain = preprocess_markdown(md, false).unwrap();
+        assert!(plain.markdown.contains("\\ref{sec:intro}"));
+
+        let options = PreprocessOptions {
+            latex_cross_refs: true,
+            ..Default::default()
+        };
+        let converted = preprocess_markdown_with_options(md, &options).unwrap();
+        assert!(converted.markdown.contains("#ref(label(\"sec:intro\"))"));
+        assert!(!converted.markdown.contains("\\label{"));
+    }
+
+    #[test]
+    fn test_unknown_citations_from_known_keys() {
+        let md = "See [@knuth1984; @lamport1994] and [@ghost2020, p. 3] and [@knuth1984].";
+        let options = PreprocessOptions {
+            has_bibliography: true,
+            known_keys: Some(["knuth1984".to_string()].into_iter().collect()),
+            ..Default::default()
+        };
+        let result = preprocess_markdown_with_options(md, &options).unwrap();
+        assert_eq!(result.unknown_citations, vec!["ghost2020", "lamport1994"]);
+
+        // Without a key set nothing is reported
+        assert!(preprocess_markdown(md, true).unwrap().unknown_citations.is_empty());
+    }
+
+    #[test]
+    fn test_max_image_width_option() {
+        let md = "# Figures\n\n![Plot](assets/plot.png)\n";
+
+        let plain = preprocess_markdown(md, false).unwrap();
+        assert!(plain.markdown.contains("![Plot](assets/plot.png)"));
+        assert!(!plain.markdown.contains("#image("));
+
+        let options = PreprocessOptions {
+            max_image_width: Some("100%".to_string()),
+            ..Default::default()
+        };
+        let capped = preprocess_markdown_with_options(md, &options).unwrap();
+        assert!(capped.markdown.contains("<!--raw-typst #image(\"/assets/plot.png\", width: 100%, alt: \"Plot\") -->"));
+        assert!(!capped.markdown.contains("![Plot]"));
+    }
+
+    #[test]
+    fn test_image_width_hint_keeps_anchor() {
+        let md = "# Figures\n\n![Diagram|300px](assets/x.png)\n";
+        let result = preprocess_markdown(md, false).unwrap();
+
+        assert!(result.markdown.contains("#figure(image(\"/assets/x.png\", width: 300pt, alt: \"Diagram\"), caption: [Diagram])"));
+        assert!(result.anchors.iter().any(|anchor| anchor.id == "tf-img-x-1"));
+    }
+
+    #[test]
+    fn test_phase_timings_only_when_requested() {
+        let md = "---\ntitle: T\n---\n# Heading\n\nText [@key].";
+
+        assert!(preprocess_markdown(md, true).unwrap().timings.is_none());
+
+        let options = PreprocessOptions {
+            has_bibliography: true,
+            record_timings: true,
+            ..Default::default()
+        };
+        let result = preprocess_markdown_with_options(md, &options).unwrap();
+        assert!(result.timings.is_some());
+    }
 }
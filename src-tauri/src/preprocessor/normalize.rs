@@ -3,6 +3,23 @@
 //! These functions prepare markdown for processing by fixing common formatting
 //! issues that could cause problems during anchor injection or rendering.
 
+use lazy_static::lazy_static;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use super::types::{offset_to_line_column, ExportMode, RawHtmlMode};
+
+lazy_static! {
+    /// `\label{key}` / `\ref{key}` as written in LaTeX sources.
+    static ref LATEX_REF_RE: Regex = Regex::new(r"\\(label|ref)\{([^{}\s]+)\}").unwrap();
+    /// Any HTML comment, possibly spanning lines.
+    pub(super) static ref COMMENT_RE: Regex = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    /// Alt text ending in a width hint: `Diagram|300px`, `Photo | 50%`.
+    static ref WIDTH_HINT_RE: Regex = Regex::new(r"^(.*?)\s*\|\s*(\d+(?:\.\d+)?)\s*(px|%)\s*$").unwrap();
+}
+
 /// Split YAML frontmatter from markdown content.
 /// 
 /// Returns (frontmatter, content) where frontmatter includes the `---` delimiters.
@@ -41,17 +58,24 @@ pub fn split_frontmatter(markdown: &str) -> (&str, &str) {
 /// 
 /// This fixes a common issue where tables immediately following paragraphs
 /// aren't parsed correctly by some markdown processors.
+///
+/// Tables are recognized either by a leading `|` or, for GFM tables written
+/// without outer pipes, by a header row followed by a matching delimiter row
+/// (`--- | ---`).
 pub fn ensure_blank_lines_before_tables(markdown: &str) -> String {
     let lines: Vec<&str> = markdown.lines().collect();
     let mut result = Vec::with_capacity(lines.len() + 10);
+    let mut in_table = false;
 
     for i in 0..lines.len() {
         let line = lines[i];
-        let is_table_line = line.trim_start().starts_with('|');
+        let is_pipe_row = line.trim_start().starts_with('|');
+        let is_header_row = lines
+            .get(i + 1)
+            .is_some_and(|next| is_table_header(line, next));
 
         // Check if this is the start of a table (first table row)
-        let is_table_start = is_table_line 
-            && (i == 0 || !lines[i - 1].trim_start().starts_with('|'));
+        let is_table_start = !in_table && (is_pipe_row || is_header_row);
 
         // If starting a table and previous line isn't blank, add blank line
         if is_table_start && i > 0 && !lines[i - 1].trim().is_empty() {
@@ -59,11 +83,902 @@ pub fn ensure_blank_lines_before_tables(markdown: &str) -> String {
         }
 
         result.push(line);
+
+        // A table continues until a blank line or a line without any pipes
+        in_table = if in_table {
+            !line.trim().is_empty() && has_unescaped_pipe(line)
+        } else {
+            is_table_start
+        };
+    }
+
+    let mut output = result.join("\n");
+    // `lines()` drops the final newline; keep it so the document end is unchanged
+    if markdown.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+/// Remove author comments (`<!-- TODO: fix this -->`) so only raw Typst passthroughs
+/// remain as comments in the output.
+///
+/// Newlines inside a comment are always kept so later anchors stay on their source
+/// lines. A comment alone on its line(s) is blanked to spaces, which also keeps byte
+/// offsets; one sharing a line with text is removed, since padding there could turn
+/// the text into an indented code block or a hard line break. Comments inside code
+/// blocks and code spans are literal text and are kept.
+pub fn strip_author_comments(markdown: &str) -> String {
+    let code = code_ranges(markdown);
+
+    let mut result = String::with_capacity(markdown.len());
+    let mut last = 0;
+    for comment in COMMENT_RE.find_iter(markdown) {
+        let in_code = code.iter().any(|range| range.contains(&comment.start()));
+        if in_code || comment.as_str().starts_with("<!--raw-typst") {
+            continue;
+        }
+        let line_start = markdown[..comment.start()].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = markdown[comment.end()..]
+            .find('\n')
+            .map_or(markdown.len(), |i| comment.end() + i);
+        let own_line = markdown[line_start..comment.start()].trim().is_empty()
+            && markdown[comment.end()..line_end].trim().is_empty();
+
+        result.push_str(&markdown[last..comment.start()]);
+        for c in comment.as_str().chars() {
+            if c == '\n' {
+                result.push('\n');
+            } else if own_line {
+                result.extend(std::iter::repeat(' ').take(c.len_utf8()));
+            }
+        }
+        last = comment.end();
+    }
+    result.push_str(&markdown[last..]);
+    result
+}
+
+/// Keep or drop `:::draft` ... `:::` blocks (also `::: draft` and `::: {.draft}`)
+/// according to `mode`.
+///
+/// Fence lines are always blanked, and in [`ExportMode::Final`] so is the content,
+/// which keeps line numbers stable for warnings. An opener without a closing `:::`
+/// and fences inside code blocks stay literal.
+pub fn apply_draft_blocks(markdown: &str, mode: ExportMode) -> String {
+    let mut lines: Vec<&str> = markdown.split('\n').collect();
+    let in_code = fenced_code_lines(&lines);
+
+    let mut i = 0;
+    while i < lines.len() {
+        if in_code[i] || !is_draft_open(lines[i]) {
+            i += 1;
+            continue;
+        }
+        let close = (i + 1..lines.len()).find(|&j| !in_code[j] && lines[j].trim() == ":::");
+        let Some(close) = close else {
+            break;
+        };
+        lines[i] = "";
+        lines[close] = "";
+        if mode == ExportMode::Final {
+            lines[i + 1..close].fill("");
+        }
+        i = close + 1;
+    }
+    lines.join("\n")
+}
+
+/// `:::draft`, `::: draft` or `::: {.draft}`
+fn is_draft_open(line: &str) -> bool {
+    line.trim()
+        .strip_prefix(":::")
+        .is_some_and(|class| matches!(class.trim(), "draft" | "{.draft}"))
+}
+
+/// Source ranges of code blocks (fenced and indented) and code spans, in document order.
+/// Text inside them is literal and must not be rewritten.
+pub fn code_ranges(markdown: &str) -> Vec<Range<usize>> {
+    Parser::new_ext(markdown, Options::ENABLE_TABLES)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Strip or escape raw HTML blocks according to `mode`.
+///
+/// HTML comments, including `<!--raw-typst ... -->` passthroughs, are kept as is,
+/// as is inline HTML inside paragraphs. Escaped blocks become literal paragraph text.
+pub fn transform_html_blocks(markdown: &str, mode: RawHtmlMode) -> String {
+    if mode == RawHtmlMode::PassThrough {
+        return markdown.to_string();
+    }
+
+    // Adjacent block-level HTML events (one per line) form one block
+    let mut blocks: Vec<Range<usize>> = Vec::new();
+    let mut current: Option<Range<usize>> = None;
+    let mut inline_depth = 0usize;
+    for (event, range) in Parser::new_ext(markdown, Options::ENABLE_TABLES).into_offset_iter() {
+        match event {
+            Event::Html(_) if inline_depth == 0 => {
+                current = match current {
+                    Some(block) if block.end == range.start => Some(block.start..range.end),
+                    previous => {
+                        blocks.extend(previous);
+                        Some(range)
+                    }
+                };
+                continue;
+            }
+            Event::Start(Tag::Paragraph | Tag::Heading(..) | Tag::TableCell) => inline_depth += 1,
+            Event::End(Tag::Paragraph | Tag::Heading(..) | Tag::TableCell) => {
+                inline_depth = inline_depth.saturating_sub(1)
+            }
+            _ => {}
+        }
+        blocks.extend(current.take());
+    }
+    blocks.extend(current.take());
+
+    let mut result = String::with_capacity(markdown.len());
+    let mut last = 0;
+    for block in blocks {
+        let html = &markdown[block.clone()];
+        if html.trim_start().starts_with("<!--") {
+            continue;
+        }
+        result.push_str(&markdown[last..block.start]);
+        if mode == RawHtmlMode::Escape {
+            result.push_str(&html.replace('<', "\\<"));
+        }
+        last = block.end;
+    }
+    result.push_str(&markdown[last..]);
+    result
+}
+
+/// An emphasis delimiter run that CommonMark left as literal text.
+struct UnbalancedRun {
+    offset: usize,
+    marker: String,
+    /// Could open emphasis (the run is followed by text)
+    opener: bool,
+    /// End of the block's content, where a repair closes the run
+    block_end: usize,
+}
+
+/// 0-based lines and markers of `**`/`*`/`_` emphasis runs with no partner in their block.
+///
+/// A stray `**` makes the rest of the paragraph bold in some renderers and breaks the
+/// Typst conversion in others. Escaped markers and intraword `_` (`snake_case`) are ignored.
+pub fn unbalanced_emphasis(markdown: &str) -> Vec<(usize, String)> {
+    unbalanced_runs(markdown)
+        .into_iter()
+        .map(|run| (offset_to_line_column(markdown, run.offset).0, run.marker))
+        .collect()
+}
+
+/// Close unmatched opening emphasis markers at the end of their block.
+///
+/// Stray closing markers are reported by [`unbalanced_emphasis`] but left alone.
+pub fn repair_unbalanced_emphasis(markdown: &str) -> String {
+    let mut closers: Vec<(usize, String)> = Vec::new();
+    for run in unbalanced_runs(markdown).into_iter().filter(|run| run.opener) {
+        match closers.last_mut() {
+            // Later openers close first
+            Some((end, markers)) if *end == run.block_end => markers.insert_str(0, &run.marker),
+            _ => closers.push((run.block_end, run.marker)),
+        }
+    }
+
+    let mut result = String::with_capacity(markdown.len() + closers.len() * 2);
+    let mut last = 0;
+    for (end, markers) in closers {
+        result.push_str(&markdown[last..end]);
+        result.push_str(&markers);
+        last = end;
+    }
+    result.push_str(&markdown[last..]);
+    result
+}
+
+fn unbalanced_runs(markdown: &str) -> Vec<UnbalancedRun> {
+    let mut runs: Vec<UnbalancedRun> = Vec::new();
+    let mut block: Vec<UnbalancedRun> = Vec::new();
+    let mut content_end = 0;
+    let mut in_code_block = false;
+    let options = Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES;
+    for (event, range) in Parser::new_ext(markdown, options).into_offset_iter() {
+        let is_start = matches!(event, Event::Start(_));
+        match event {
+            Event::Start(Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)) => {}
+            Event::End(Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)) => {
+                content_end = range.end;
+            }
+            // Any other tag is a block boundary, closing the current run of inline content
+            Event::Start(tag) | Event::End(tag) => {
+                in_code_block = is_start && matches!(tag, Tag::CodeBlock(_));
+                let block_end = markdown[..content_end].trim_end().len();
+                runs.extend(block.drain(..).map(|run| UnbalancedRun { block_end, ..run }));
+            }
+            Event::Text(_) if !in_code_block => {
+                for run in literal_emphasis_runs(markdown, range.clone()) {
+                    // pulldown-cmark may split one delimiter run across text events
+                    if block.last().map(|last| last.offset) != Some(run.offset) {
+                        block.push(run);
+                    }
+                }
+                content_end = range.end;
+            }
+            _ => content_end = range.end,
+        }
+    }
+    let block_end = markdown[..content_end].trim_end().len();
+    runs.extend(block.drain(..).map(|run| UnbalancedRun { block_end, ..run }));
+    runs
+}
+
+/// Flanking `*`/`_` runs touching a literal text range of `markdown`.
+fn literal_emphasis_runs(markdown: &str, range: Range<usize>) -> Vec<UnbalancedRun> {
+    let mut runs = Vec::new();
+    let mut i = range.start;
+    while i < range.end {
+        let c = markdown.as_bytes()[i];
+        if c != b'*' && c != b'_' {
+            i += 1;
+            continue;
+        }
+        // Runs extend past the text event when the parser split them
+        let start = i - markdown.as_bytes()[..i].iter().rev().take_while(|&&b| b == c).count();
+        let end = i + markdown.as_bytes()[i..].iter().take_while(|&&b| b == c).count();
+        i = end;
+
+        let before = markdown[..start].chars().next_back();
+        let after = markdown[end..].chars().next();
+        if before == Some('\\') {
+            continue;
+        }
+        let opener = after.is_some_and(|ch| !ch.is_whitespace());
+        let closer = before.is_some_and(|ch| !ch.is_whitespace());
+        let intraword = before.is_some_and(char::is_alphanumeric) && after.is_some_and(char::is_alphanumeric);
+        if (opener || closer) && !(c == b'_' && intraword) {
+            runs.push(UnbalancedRun {
+                offset: start,
+                marker: markdown[start..end].to_string(),
+                opener: opener && !closer,
+                block_end: 0,
+            });
+        }
+    }
+    runs
+}
+
+/// Replace local image references with a raw Typst `#image` of width `max_width`
+/// (any Typst length, e.g. `100%`), so hand-placed large images cannot overflow the page.
+///
+/// Paths are made root-relative (`/assets/fig.png`) because the template compiles with
+/// the content directory as its root. Remote and absolute paths are left as written.
+pub fn cap_image_widths(markdown: &str, max_width: &str) -> String {
+    let mut result = String::with_capacity(markdown.len());
+    let mut last = 0;
+    for (range, path, alt) in local_images(markdown) {
+        result.push_str(&markdown[last..range.start]);
+        result.push_str(&format!(
+            "<!--raw-typst #image(\"/{}\", width: {}, alt: \"{}\") -->",
+            typst_string_escape(&path),
+            max_width,
+            typst_string_escape(&alt)
+        ));
+        last = range.end;
+    }
+    result.push_str(&markdown[last..]);
+    result
+}
+
+/// Turn a width hint at the end of a local image's alt text (`![Diagram|300px](x.png)`,
+/// `![Photo|50%](y.jpg)`) into a raw Typst figure of that width, captioned with the
+/// rest of the alt text. Typst has no pixel unit, so `px` is taken as `pt`.
+///
+/// Images without a hint are left alone.
+pub fn apply_image_width_hints(markdown: &str) -> String {
+    let mut result = String::with_capacity(markdown.len());
+    let mut last = 0;
+    for (range, path, alt) in local_images(markdown) {
+        let Some(caps) = WIDTH_HINT_RE.captures(&alt) else {
+            continue;
+        };
+        let width = format!("{}{}", &caps[2], if &caps[3] == "px" { "pt" } else { "%" });
+        let alt = &caps[1];
+        let image = format!(
+            "image(\"/{}\", width: {}, alt: \"{}\")",
+            typst_string_escape(&path),
+            width,
+            typst_string_escape(alt)
+        );
+        result.push_str(&markdown[last..range.start]);
+        if alt.is_empty() {
+            result.push_str(&format!("<!--raw-typst #{} -->", image));
+        } else {
+            result.push_str(&format!(
+                "<!--raw-typst #figure({}, caption: [{}]) -->",
+                image,
+                typst_markup_escape(alt)
+            ));
+        }
+        last = range.end;
+    }
+    result.push_str(&markdown[last..]);
+    result
+}
+
+/// Source range, `/`-separated path and alt text of every image with a relative path.
+/// Remote, data and absolute paths are skipped.
+fn local_images(markdown: &str) -> Vec<(Range<usize>, String, String)> {
+    let mut images: Vec<(Range<usize>, String, String)> = Vec::new();
+    let mut current: Option<(Range<usize>, String, String)> = None;
+    for (event, range) in Parser::new_ext(markdown, Options::ENABLE_TABLES).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Image(_, dest, _)) if current.is_none() => {
+                current = Some((range, dest.replace('\\', "/"), String::new()));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, _, alt)) = current.as_mut() {
+                    alt.push_str(&text);
+                }
+            }
+            Event::End(Tag::Image(..)) => images.extend(current.take()),
+            _ => {}
+        }
+    }
+
+    images.retain(|(_, path, _)| {
+        !path.contains("://") && !path.starts_with("data:") && !path.starts_with('/') && path.chars().nth(1) != Some(':')
+    });
+    images
+}
+
+/// Escape text for use inside a Typst string literal.
+///
+/// `>` is written as `\u{3e}` because the literal sits in a `<!--raw-typst ... -->`
+/// comment, which a `-->` in the text would otherwise close early.
+fn typst_string_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('>', "\\u{3e}")
+}
+
+/// Replace Unicode line (U+2028) and paragraph (U+2029) separators with `\n`.
+///
+/// Pasted content sometimes uses them instead of newlines; every later pass splits
+/// on `\n` only and would otherwise see the document as one long line.
+pub fn normalize_line_separators(markdown: &str) -> String {
+    markdown.replace(['\u{2028}', '\u{2029}'], "\n")
+}
+
+/// Convert `[^label]` references into explicit `<!--raw-typst #footnote[...] -->` calls
+/// and drop their definitions, so footnotes do not depend on the markdown-to-Typst bridge.
+///
+/// Definitions keep emphasis, strong text, inline code and raw Typst (converted
+/// citations); other formatting is reduced to its text. A label referenced several times
+/// becomes one footnote that later references point back to. References without a
+/// definition and unreferenced definitions are left as written.
+pub fn convert_footnotes(markdown: &str) -> String {
+    let options = Options::ENABLE_FOOTNOTES | Options::ENABLE_TABLES;
+    let mut definitions: HashMap<String, (Range<usize>, String)> = HashMap::new();
+    let mut references: Vec<(Range<usize>, String)> = Vec::new();
+    let mut current: Option<(String, Range<usize>, String)> = None;
+    for (event, range) in Parser::new_ext(markdown, options).into_offset_iter() {
+        match (current.as_mut(), event) {
+            (None, Event::Start(Tag::FootnoteDefinition(label))) => {
+                current = Some((label.to_string(), range, String::new()));
+            }
+            (Some(_), Event::End(Tag::FootnoteDefinition(_))) => {
+                if let Some((label, range, content)) = current.take() {
+                    definitions.insert(label, (range, content.trim().to_string()));
+                }
+            }
+            (Some((_, _, content)), event) => push_footnote_markup(content, event),
+            (None, Event::FootnoteReference(label)) => references.push((range, label.to_string())),
+            _ => {}
+        }
+    }
+
+    let mut reference_counts: HashMap<&str, usize> = HashMap::new();
+    for (_, label) in &references {
+        *reference_counts.entry(label.as_str()).or_insert(0) += 1;
+    }
+
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+    let mut emitted: HashSet<&str> = HashSet::new();
+    for (range, label) in &references {
+        let Some((_, content)) = definitions.get(label) else {
+            continue;
+        };
+        let typst_label = typst_string_escape(&format!("tf-fn-{}", label));
+        let call = if !emitted.insert(label.as_str()) {
+            format!("#footnote(label(\"{}\"))", typst_label)
+        } else if reference_counts[label.as_str()] > 1 {
+            format!("#footnote[{}]#label(\"{}\")", content, typst_label)
+        } else {
+            format!("#footnote[{}]", content)
+        };
+        edits.push((range.clone(), format!("<!--raw-typst {} -->", call)));
+    }
+    for (label, (range, _)) in &definitions {
+        if emitted.contains(label.as_str()) {
+            edits.push((range.clone(), String::new()));
+        }
+    }
+    edits.sort_by_key(|(range, _)| range.start);
+
+    let mut result = String::with_capacity(markdown.len());
+    let mut last = 0;
+    for (range, replacement) in edits {
+        result.push_str(&markdown[last..range.start]);
+        result.push_str(&replacement);
+        last = range.end;
+    }
+    result.push_str(&markdown[last..]);
+    result
+}
+
+/// Append the Typst markup for one event of a footnote definition.
+fn push_footnote_markup(content: &mut String, event: Event) {
+    match event {
+        Event::Text(text) => content.push_str(&typst_markup_escape(&text)),
+        Event::Code(code) => content.push_str(&format!("#raw(\"{}\")", typst_string_escape(&code))),
+        Event::Start(Tag::Emphasis) | Event::End(Tag::Emphasis) => content.push('_'),
+        Event::Start(Tag::Strong) | Event::End(Tag::Strong) => content.push('*'),
+        Event::SoftBreak | Event::HardBreak | Event::End(Tag::Paragraph) => content.push(' '),
+        Event::Html(html) => {
+            let raw_typst = html
+                .trim()
+                .strip_prefix("<!--raw-typst")
+                .and_then(|rest| rest.strip_suffix("-->"));
+            if let Some(code) = raw_typst {
+                content.push_str(code.trim());
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Escape characters with meaning in Typst markup, including `>` so the text cannot
+/// close the surrounding HTML comment.
+fn typst_markup_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '#' | '[' | ']' | '*' | '_' | '$' | '@' | '<' | '>' | '`' | '~' | '/') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Raw Typst emitted for a manual page break.
+pub const PAGEBREAK_MARKUP: &str = "<!--raw-typst #pagebreak() -->";
+
+/// Convert manual page break sentinels into raw Typst page breaks.
+///
+/// A line containing only `\pagebreak` or `<!-- pagebreak -->` becomes
+/// [`PAGEBREAK_MARKUP`]. Sentinels inside fenced or indented code blocks are left
+/// literal.
+pub fn convert_page_breaks(markdown: &str) -> String {
+    let code = code_ranges(markdown);
+    let mut line_start = 0;
+
+    markdown
+        .split('\n')
+        .map(|line| {
+            let text_start = line_start + line.len() - line.trim_start().len();
+            line_start += line.len() + 1;
+            let in_code = code.iter().any(|range| range.contains(&text_start));
+            if !in_code && is_page_break_sentinel(line) {
+                PAGEBREAK_MARKUP
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Insert the missing space in line-leading ATX heading markers (`##Title` → `## Title`).
+///
+/// Only runs of one to six `#` at the start of a line (after at most three spaces of
+/// indentation) are touched, so `#hashtag` inside prose and fenced code stay literal.
+pub fn fix_atx_heading_spacing(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.split('\n').collect();
+    let in_code = fenced_code_lines(&lines);
+
+    lines
+        .iter()
+        .zip(&in_code)
+        .map(|(line, &is_code)| {
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            let rest = &line[indent..];
+            let hashes = rest.len() - rest.trim_start_matches('#').len();
+            let needs_space = !is_code
+                && indent <= 3
+                && (1..=6).contains(&hashes)
+                && rest[hashes..].chars().next().is_some_and(|c| !c.is_whitespace());
+            if needs_space {
+                format!("{}{} {}", &line[..indent], &rest[..hashes], &rest[hashes..])
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Convert LaTeX-style `\label{x}` and `\ref{x}` into raw Typst labels and references.
+///
+/// Commands inside fenced code blocks are left literal.
+pub fn convert_latex_refs(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.split('\n').collect();
+    let in_code = fenced_code_lines(&lines);
+
+    lines
+        .iter()
+        .zip(&in_code)
+        .map(|(line, &is_code)| {
+            if is_code {
+                return line.to_string();
+            }
+            LATEX_REF_RE
+                .replace_all(line, |caps: &Captures| {
+                    let key = typst_string_escape(&caps[2]);
+                    if &caps[1] == "label" {
+                        format!("<!--raw-typst #label(\"{}\") -->", key)
+                    } else {
+                        format!("<!--raw-typst #ref(label(\"{}\")) -->", key)
+                    }
+                })
+                .into_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Check whether a line is a manual page break sentinel.
+fn is_page_break_sentinel(line: &str) -> bool {
+    let trimmed = line.trim();
+    if trimmed == "\\pagebreak" {
+        return true;
+    }
+    trimmed
+        .strip_prefix("<!--")
+        .and_then(|rest| rest.strip_suffix("-->"))
+        .is_some_and(|inner| inner.trim() == "pagebreak")
+}
+
+/// 0-based lines of table rows whose cell count differs from their header row.
+///
+/// Markdown renderers silently pad or drop cells in such rows, so the table in the
+/// PDF does not match what was written. Tables in fenced code are ignored.
+pub fn malformed_table_rows(markdown: &str) -> Vec<usize> {
+    let lines: Vec<&str> = markdown.split('\n').collect();
+    let in_code = fenced_code_lines(&lines);
+    let mut malformed = Vec::new();
+
+    let mut i = 0;
+    while i + 1 < lines.len() {
+        if in_code[i] || !is_table_header(lines[i], lines[i + 1]) {
+            i += 1;
+            continue;
+        }
+
+        let columns = table_cells(lines[i]).len();
+        // Body rows continue until a blank line or a line without any pipes
+        let mut row = i + 2;
+        while row < lines.len() && !lines[row].trim().is_empty() && has_unescaped_pipe(lines[row]) {
+            if table_cells(lines[row]).len() != columns {
+                malformed.push(row);
+            }
+            row += 1;
+        }
+        i = row;
+    }
+
+    malformed
+}
+
+/// Zero-width space: an invisible line break opportunity for Typst.
+const BREAK_OPPORTUNITY: char = '\u{200B}';
+
+/// Let long unbroken words in table cells wrap.
+///
+/// Inside table rows, every run of more than `max_run` non-whitespace characters
+/// gets a zero-width space after each `max_run` characters, so a long identifier or
+/// hash no longer pushes the table past the page edge. Code spans and URLs are
+/// left intact (breaking them would change the code or the link target), as are
+/// delimiter rows and fenced code blocks.
+pub fn soften_long_table_cells(markdown: &str, max_run: usize) -> String {
+    if max_run == 0 {
+        return markdown.to_string();
+    }
+
+    let lines: Vec<&str> = markdown.split('\n').collect();
+    let in_code = fenced_code_lines(&lines);
+    let mut in_table = false;
+
+    let result: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, &line)| {
+            let is_delimiter = i > 0 && is_table_header(lines[i - 1], line);
+            let is_header = lines.get(i + 1).is_some_and(|next| is_table_header(line, next));
+            in_table = !in_code[i]
+                && if in_table {
+                    !line.trim().is_empty() && has_unescaped_pipe(line)
+                } else {
+                    is_header
+                };
+
+            if in_table && !is_delimiter {
+                soften_line(line, max_run)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+
+    result.join("\n")
+}
+
+/// Insert break opportunities into the long words of one table row.
+fn soften_line(line: &str, max_run: usize) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut in_code_span = false;
+
+    for word in line.split_inclusive(char::is_whitespace) {
+        let is_url = word.contains("://") || word.starts_with("www.") || word.starts_with('<');
+        let mut run = 0;
+        for c in word.chars() {
+            if c == '`' {
+                in_code_span = !in_code_span;
+            }
+            if c == '|' || c.is_whitespace() || c == '`' || in_code_span || is_url {
+                run = 0;
+            } else {
+                if run == max_run {
+                    result.push(BREAK_OPPORTUNITY);
+                    run = 0;
+                }
+                run += 1;
+            }
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+/// Rewrite nested list indentation that mixes tabs and spaces into spaces.
+///
+/// Renderers disagree on how wide a tab is, so a list indented partly with tabs
+/// can flatten or nest differently in the PDF than in the editor. For each list
+/// that uses tabs in its indentation, items are re-indented so every child sits
+/// at its parent's content column (tabs counted as 4 columns when determining
+/// depth). Lists without tabs and fenced code blocks are left untouched.
+pub fn normalize_list_indentation(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.split('\n').collect();
+    let in_code = fenced_code_lines(&lines);
+    let mut result: Vec<String> = Vec::with_capacity(lines.len());
+
+    let mut i = 0;
+    while i < lines.len() {
+        let is_list_start = !in_code[i] && list_marker_len(lines[i]).is_some();
+        if !is_list_start {
+            result.push(lines[i].to_string());
+            i += 1;
+            continue;
+        }
+
+        let end = list_block_end(&lines, &in_code, i);
+        let block = &lines[i..end];
+        let has_mixed_indent = block
+            .iter()
+            .enumerate()
+            .any(|(j, line)| !in_code[i + j] && leading_whitespace(line).contains('\t'));
+
+        if has_mixed_indent {
+            result.extend(reindent_list_block(block, &in_code[i..end]));
+        } else {
+            result.extend(block.iter().map(|line| line.to_string()));
+        }
+        i = end;
     }
 
     result.join("\n")
 }
 
+/// Mark lines that belong to fenced code blocks (including the fences).
+///
+/// A block closes only on a bare fence of the same character at least as long as
+/// the opening one, so a ```` ```` ```` block may contain ```` ``` ```` lines.
+fn fenced_code_lines(lines: &[&str]) -> Vec<bool> {
+    let mut fence: Option<(char, usize)> = None;
+    lines
+        .iter()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'));
+            let run = marker.map_or(0, |c| trimmed.len() - trimmed.trim_start_matches(c).len());
+            match fence {
+                Some((fence_char, fence_len)) => {
+                    if marker == Some(fence_char) && run >= fence_len && trimmed[run..].trim().is_empty() {
+                        fence = None;
+                    }
+                    true
+                }
+                None if run >= 3 => {
+                    fence = marker.map(|c| (c, run));
+                    true
+                }
+                None => false,
+            }
+        })
+        .collect()
+}
+
+/// Find the index one past the last line of the list starting at `start`.
+fn list_block_end(lines: &[&str], in_code: &[bool], start: usize) -> usize {
+    let mut end = start + 1;
+    while end < lines.len() {
+        let line = lines[end];
+        if in_code[end] || list_marker_len(line).is_some() || (indent_width(line) > 0 && !line.trim().is_empty()) {
+            end += 1;
+            continue;
+        }
+        if line.trim().is_empty() {
+            // A blank line only continues the list if more list content follows
+            let next = lines[end..].iter().position(|l| !l.trim().is_empty()).map(|p| end + p);
+            match next {
+                Some(n) if list_marker_len(lines[n]).is_some() || indent_width(lines[n]) > 0 => {
+                    end = n;
+                    continue;
+                }
+                _ => break,
+            }
+        }
+        break;
+    }
+    end
+}
+
+/// Re-indent one list so that each level sits at its parent's content column.
+fn reindent_list_block(block: &[&str], in_code: &[bool]) -> Vec<String> {
+    // (original visual width, new indent, marker length) for each open level
+    let mut levels: Vec<(usize, usize, usize)> = Vec::new();
+
+    block
+        .iter()
+        .zip(in_code)
+        .map(|(line, &is_code)| {
+            let content = line.trim_start();
+            if is_code || content.is_empty() {
+                return line.to_string();
+            }
+
+            let width = indent_width(line);
+            let Some(marker_len) = list_marker_len(line) else {
+                // Continuation text aligns with the innermost item's content
+                let column = levels.last().map(|&(_, indent, marker)| indent + marker + 1).unwrap_or(0);
+                return format!("{}{}", " ".repeat(column), content);
+            };
+
+            while levels.last().is_some_and(|&(orig, _, _)| orig > width) {
+                levels.pop();
+            }
+            let indent = match levels.last() {
+                Some(&(orig, indent, _)) if orig == width => {
+                    levels.pop();
+                    indent
+                }
+                Some(&(_, indent, marker)) => indent + marker + 1,
+                None => 0,
+            };
+            levels.push((width, indent, marker_len));
+
+            format!("{}{}", " ".repeat(indent), content)
+        })
+        .collect()
+}
+
+/// Length of the list marker (`-`, `*`, `+`, `1.`, `1)`) starting the line, if any.
+fn list_marker_len(line: &str) -> Option<usize> {
+    let content = line.trim_start();
+    // `---` / `* * *` are thematic breaks, not list items
+    let is_rule = content.chars().filter(|c| !c.is_whitespace()).count() >= 3
+        && content.chars().all(|c| c == '-' || c == '*' || c == '_' || c.is_whitespace());
+    if is_rule {
+        return None;
+    }
+
+    let digits = content.chars().take_while(|c| c.is_ascii_digit()).count();
+    let marker_len = if digits == 0 {
+        content.starts_with(['-', '*', '+']).then_some(1)?
+    } else if digits <= 9 && content[digits..].starts_with(['.', ')']) {
+        digits + 1
+    } else {
+        return None;
+    };
+
+    let after = &content[marker_len..];
+    (after.is_empty() || after.starts_with([' ', '\t'])).then_some(marker_len)
+}
+
+/// Leading whitespace of a line.
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Visual width of a line's indentation, expanding tabs to 4-column stops.
+fn indent_width(line: &str) -> usize {
+    leading_whitespace(line).chars().fold(0, |width, c| match c {
+        '\t' => width + 4 - width % 4,
+        _ => width + 1,
+    })
+}
+
+/// Check whether `line` is a table header row followed by the delimiter row `next`.
+///
+/// Both rows must contain a pipe and agree on the number of columns, which keeps
+/// prose containing `|` (or a paragraph followed by a `---` rule) from matching.
+fn is_table_header(line: &str, next: &str) -> bool {
+    if !has_unescaped_pipe(line) || !has_unescaped_pipe(next) {
+        return false;
+    }
+
+    let delimiter_cells = table_cells(next);
+    let is_delimiter_row = delimiter_cells.iter().all(|cell| {
+        let cell = cell.trim().trim_start_matches(':').trim_end_matches(':');
+        !cell.is_empty() && cell.chars().all(|c| c == '-')
+    });
+
+    is_delimiter_row && table_cells(line).len() == delimiter_cells.len()
+}
+
+/// Split a table row into its cells, ignoring optional leading/trailing pipes.
+///
+/// An escaped pipe (`\|`) is cell content, not a column separator.
+fn table_cells(line: &str) -> Vec<&str> {
+    let trimmed = line.trim();
+    let trimmed = trimmed.strip_prefix('|').unwrap_or(trimmed);
+    let trimmed = match trimmed.strip_suffix('|') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => trimmed,
+    };
+
+    let mut cells = Vec::new();
+    let mut cell_start = 0;
+    for pos in unescaped_pipes(trimmed) {
+        cells.push(&trimmed[cell_start..pos]);
+        cell_start = pos + 1;
+    }
+    cells.push(&trimmed[cell_start..]);
+    cells
+}
+
+/// Byte positions of the pipes in `line` that are not escaped with a backslash.
+fn unescaped_pipes(line: &str) -> impl Iterator<Item = usize> + '_ {
+    let bytes = line.as_bytes();
+    line.match_indices('|').map(|(pos, _)| pos).filter(move |&pos| {
+        // A pipe is escaped by an odd number of preceding backslashes
+        let backslashes = bytes[..pos].iter().rev().take_while(|&&b| b == b'\\').count();
+        backslashes % 2 == 0
+    })
+}
+
+/// Check whether `line` contains a pipe that acts as a column separator.
+fn has_unescaped_pipe(line: &str) -> bool {
+    unescaped_pipes(line).next().is_some()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -91,4 +1006,221 @@ mod tests {
         let result = ensure_blank_lines_before_tables(md);
         assert!(result.contains("Some text\n\n| A | B |"));
     }
+
+    #[test]
+    fn test_table_blank_line_without_leading_pipes() {
+        let md = "Some text\nName | Age\n--- | :---:\nAda | 36";
+        let result = ensure_blank_lines_before_tables(md);
+        assert_eq!(result, "Some text\n\nName | Age\n--- | :---:\nAda | 36");
+
+        // A header without leading pipe followed by a piped delimiter is one table
+        let md = "Some text\nName | Age\n|---|---|\n| Ada | 36 |";
+        let result = ensure_blank_lines_before_tables(md);
+        assert_eq!(result, "Some text\n\nName | Age\n|---|---|\n| Ada | 36 |");
+    }
+
+    #[test]
+    fn test_escaped_pipes_in_table_cells() {
+        assert_eq!(table_cells("| a \\| b | c |"), vec![" a \\| b ", " c "]);
+        assert_eq!(table_cells("a \\| b | c \\|"), vec!["a \\| b ", " c \\|"]);
+
+        // The escaped pipe does not add a column, so the header still matches its delimiter
+        let md = "Some text\na \\| b | c\n--- | ---\nx | y";
+        let result = ensure_blank_lines_before_tables(md);
+        assert_eq!(result, "Some text\n\na \\| b | c\n--- | ---\nx | y");
+
+        // A line whose only pipe is escaped is not a table header or row
+        let md = "Intro\nUse x \\| y here.\n---\n\n| A | B |\n|---|---|\n| a \\| b | c |\nAfter \\| text";
+        let result = ensure_blank_lines_before_tables(md);
+        assert_eq!(result, md);
+    }
+
+    #[test]
+    fn test_prose_with_pipes_is_not_a_table() {
+        let md = "Use a | b for alternatives.\nThen continue.\n\nTitle | Sub\n---";
+        let result = ensure_blank_lines_before_tables(md);
+        assert_eq!(result, md);
+    }
+
+    #[test]
+    fn test_malformed_table_rows() {
+        let md = "| A | B |\n|---|---|\n| 1 | 2 |\n| 3 |\n| 4 | 5 | 6 |\n\n```\n| x | y |\n|---|---|\n| z |\n```";
+        assert_eq!(malformed_table_rows(md), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_page_breaks_in_code_left_literal() {
+        let indented = "Para one.\n\n    \\pagebreak\n\nPara two.";
+        assert_eq!(convert_page_breaks(indented), indented);
+
+        let nested = "````\n```\n<!-- pagebreak -->\n```\n<!-- pagebreak -->\n````\n\n<!-- pagebreak -->";
+        assert_eq!(
+            convert_page_breaks(nested),
+            format!("````\n```\n<!-- pagebreak -->\n```\n<!-- pagebreak -->\n````\n\n{}", PAGEBREAK_MARKUP)
+        );
+    }
+
+    #[test]
+    fn test_fenced_code_lines_track_fence_length() {
+        let lines = ["````", "```", "inner", "```", "still code", "````", "text", "~~~", "```", "~~~~ x", "~~~~", "after"];
+        assert_eq!(
+            fenced_code_lines(&lines),
+            vec![true, true, true, true, true, true, false, true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn test_cap_image_widths() {
+        let md = "Intro.\n\n![Big \"chart\"](assets\\chart.png)\n\n![Remote](https://example.com/a.png)\n";
+        let result = cap_image_widths(md, "100%");
+
+        assert_eq!(
+            result,
+            "Intro.\n\n<!--raw-typst #image(\"/assets/chart.png\", width: 100%, alt: \"Big \\\"chart\\\"\") -->\n\n\
+             ![Remote](https://example.com/a.png)\n"
+        );
+    }
+
+    #[test]
+    fn test_cap_image_widths_keeps_comment_closed() {
+        let result = cap_image_widths("![a --> b](x.png)\n", "100%");
+
+        assert_eq!(
+            result,
+            "<!--raw-typst #image(\"/x.png\", width: 100%, alt: \"a --\\u{3e} b\") -->\n"
+        );
+        assert_eq!(result.matches("-->").count(), 1);
+    }
+
+    #[test]
+    fn test_apply_image_width_hints() {
+        let md = "![Diagram|300px](assets/x.png)\n\n![Photo | 50%](assets/y.jpg)\n\n![|25%](z.png)\n\n\
+                  ![Plain](assets/p.png)\n\n![Remote|50%](https://example.com/r.png)\n";
+        let result = apply_image_width_hints(md);
+
+        assert!(result.contains(
+            "<!--raw-typst #figure(image(\"/assets/x.png\", width: 300pt, alt: \"Diagram\"), caption: [Diagram]) -->"
+        ));
+        assert!(result.contains("#figure(image(\"/assets/y.jpg\", width: 50%, alt: \"Photo\"), caption: [Photo])"));
+        assert!(result.contains("<!--raw-typst #image(\"/z.png\", width: 25%, alt: \"\") -->"));
+        assert!(result.contains("![Plain](assets/p.png)"));
+        assert!(result.contains("![Remote|50%](https://example.com/r.png)"));
+        assert!(!result.contains("|300px"));
+
+        // The alt string is escaped; the caption is markup and escaped separately
+        let arrow = apply_image_width_hints("![a --> b|50%](x.png)\n");
+        assert!(arrow.contains("alt: \"a --\\u{3e} b\""));
+        assert_eq!(arrow.matches("-->").count(), 1);
+    }
+
+    #[test]
+    fn test_transform_html_blocks() {
+        let md = "Intro with <br> inline.\n\n<div class=\"note\">\n<b>Hi</b>\n</div>\n\n<!--raw-typst #pagebreak() -->\n\nAfter.";
+
+        assert_eq!(transform_html_blocks(md, RawHtmlMode::PassThrough), md);
+        assert_eq!(
+            transform_html_blocks(md, RawHtmlMode::Strip),
+            "Intro with <br> inline.\n\n\n<!--raw-typst #pagebreak() -->\n\nAfter."
+        );
+        assert_eq!(
+            transform_html_blocks(md, RawHtmlMode::Escape),
+            "Intro with <br> inline.\n\n\\<div class=\"note\">\n\\<b>Hi\\</b>\n\\</div>\n\n<!--raw-typst #pagebreak() -->\n\nAfter."
+        );
+    }
+
+    #[test]
+    fn test_fix_atx_heading_spacing() {
+        let md = "#Title\n  ##Section\n####### Seven\nA #hashtag here\n    #indented\n```\n#comment\n```\n## Fine";
+        let result = fix_atx_heading_spacing(md);
+
+        assert_eq!(
+            result,
+            "# Title\n  ## Section\n####### Seven\nA #hashtag here\n    #indented\n```\n#comment\n```\n## Fine"
+        );
+    }
+
+    #[test]
+    fn test_normalize_line_separators() {
+        let md = "Intro\u{2028}| A | B |\n|---|---|\u{2029}| 1 | 2 |";
+        let result = ensure_blank_lines_before_tables(&normalize_line_separators(md));
+
+        assert_eq!(result, "Intro\n\n| A | B |\n|---|---|\n| 1 | 2 |");
+    }
+
+    #[test]
+    fn test_unbalanced_emphasis() {
+        let md = "Some **bold text\n\nFine *emphasis*, snake_case and \\*escaped.\n\n| A |\n|---|\n| x_ |";
+        assert_eq!(unbalanced_emphasis(md), vec![(0, "**".to_string()), (6, "_".to_string())]);
+    }
+
+    #[test]
+    fn test_repair_unbalanced_emphasis() {
+        let md = "Some **bold and _more\n\n- item **open\n\nstray** closer";
+        let result = repair_unbalanced_emphasis(md);
+
+        assert_eq!(result, "Some **bold and _more_**\n\n- item **open**\n\nstray** closer");
+        assert_eq!(unbalanced_emphasis(&result), vec![(4, "**".to_string())]);
+    }
+
+    #[test]
+    fn test_convert_footnotes() {
+        let md = "Claim[^src] and again[^src], plus[^none].\n\n[^src]: See *Smith*, p. [12] `x_y`.\n\n[^unused]: Kept.\n";
+        let result = convert_footnotes(md);
+
+        assert_eq!(
+            result,
+            "Claim<!--raw-typst #footnote[See _Smith_, p. \\[12\\] #raw(\"x_y\").]#label(\"tf-fn-src\") --> \
+             and again<!--raw-typst #footnote(label(\"tf-fn-src\")) -->, plus[^none].\n\n[^unused]: Kept.\n"
+        );
+    }
+
+    #[test]
+    fn test_convert_latex_refs() {
+        let md = "## Intro \\label{sec:intro}\n\nAs shown in \\ref{sec:intro}.";
+        let result = convert_latex_refs(md);
+
+        assert_eq!(
+            result,
+            "## Intro <!--raw-typst #label(\"sec:intro\") -->\n\nAs shown in <!--raw-typst #ref(label(\"sec:intro\")) -->."
+        );
+    }
+
+    #[test]
+    fn test_convert_latex_refs_skips_code_fences() {
+        let md = "```latex\n\\label{sec:intro} and \\ref{sec:intro}\n```";
+        assert_eq!(convert_latex_refs(md), md);
+    }
+
+    #[test]
+    fn test_soften_long_table_cells() {
+        let token = "a".repeat(45);
+        let md = format!(
+            "| Name | Value |\n|------------------------|---|\n| {} | `{}` |\n| https://example.com/{} | ok |\n\n{}",
+            token, token, token, token
+        );
+        let result = soften_long_table_cells(&md, 20);
+
+        let softened = format!("{}{b}{}{b}{}", &token[..20], &token[20..40], &token[40..], b = BREAK_OPPORTUNITY);
+        assert!(result.contains(&format!("| {} |", softened)));
+        // Code spans, URLs, delimiter rows and text outside tables are untouched
+        assert!(result.contains(&format!("`{}`", token)));
+        assert!(result.contains(&format!("https://example.com/{}", token)));
+        assert!(result.contains("|------------------------|---|"));
+        assert!(result.ends_with(&format!("\n\n{}", token)));
+        assert_eq!(result.matches(BREAK_OPPORTUNITY).count(), 2);
+    }
+
+    #[test]
+    fn test_normalize_mixed_list_indentation() {
+        let md = "- Fruits\n\t- Apples\n\t\t- Green\n    - Pears\n- Grains\n\t1. Rice\n\t1. Oats\n\t\t- Brown\n\nAfter.";
+        let result = normalize_list_indentation(md);
+        assert_eq!(
+            result,
+            "- Fruits\n  - Apples\n    - Green\n  - Pears\n- Grains\n  1. Rice\n  1. Oats\n     - Brown\n\nAfter."
+        );
+
+        // Space-only lists and fenced code are left alone
+        let md = "- a\n    - b\n\n```\n- x\n\t- y\n```\n";
+        assert_eq!(normalize_list_indentation(md), md);
+    }
 }
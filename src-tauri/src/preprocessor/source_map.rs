@@ -2,10 +2,14 @@
 
 use anyhow::Result;
 use std::collections::HashMap;
+use std::io::Write;
 
 use super::types::{AnchorEntry, AnchorMeta, EditorPosition, PdfPosition, SourceMapPayload};
 
 /// Attach PDF positions to anchor metadata to create a complete source map.
+///
+/// Every anchor is kept; those Typst could not locate have `pdf: None` but still
+/// carry their editor position for forward-only sync.
 pub fn attach_pdf_positions(
     anchors: &[AnchorMeta],
     positions: &HashMap<String, PdfPosition>,
@@ -14,6 +18,7 @@ pub fn attach_pdf_positions(
         .iter()
         .map(|anchor| AnchorEntry {
             id: anchor.id.clone(),
+            seq: anchor.seq,
             editor: EditorPosition {
                 offset: anchor.offset,
                 line: anchor.line,
@@ -21,9 +26,122 @@ pub fn attach_pdf_positions(
             },
             pdf: positions.get(&anchor.id).cloned(),
         })
-        .collect();
+        .collect::<Vec<AnchorEntry>>();
 
-    SourceMapPayload { anchors: entries }
+    SourceMapPayload {
+        located_count: entries.iter().filter(|entry| entry.pdf.is_some()).count(),
+        total_count: entries.len(),
+        anchors: entries,
+    }
+}
+
+/// Combine the source maps of documents previewed back to back into one PDF.
+///
+/// Each map is paired with the page count of its own document, passed explicitly
+/// because trailing pages without anchors are invisible to the map. Anchor entries
+/// are concatenated in order and their PDF pages shifted by the pages of every
+/// earlier document; `seq` continues across documents. Editor positions stay
+/// relative to each document, so anchor ids should be namespaced per document to
+/// stay unique.
+pub fn merge_source_maps(maps: Vec<(SourceMapPayload, usize)>) -> SourceMapPayload {
+    let mut merged = SourceMapPayload::default();
+    let mut page_offset = 0;
+
+    for (payload, page_count) in maps {
+        let seq_offset = merged.anchors.len();
+        merged.located_count += payload.located_count;
+        merged.total_count += payload.total_count;
+        merged.anchors.extend(payload.anchors.into_iter().map(|mut entry| {
+            entry.seq += seq_offset;
+            if let Some(pdf) = &mut entry.pdf {
+                pdf.page += page_offset;
+            }
+            entry
+        }));
+        page_offset += page_count;
+    }
+
+    merged
+}
+
+/// Write the source map as newline-delimited JSON, one `AnchorEntry` per line.
+///
+/// Lets consumers of large documents process anchors incrementally instead of
+/// parsing the whole payload at once.
+pub fn write_source_map_ndjson<W: Write>(payload: &SourceMapPayload, mut out: W) -> Result<()> {
+    for entry in &payload.anchors {
+        serde_json::to_writer(&mut out, entry)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Highest PDF page number referenced by the source map, or 0 if no anchor has a position.
+///
+/// Anchors cover the document from `tf-doc-start` onwards, so this doubles as the page count.
+pub fn max_page(payload: &SourceMapPayload) -> usize {
+    payload
+        .anchors
+        .iter()
+        .filter_map(|entry| entry.pdf.as_ref().map(|pdf| pdf.page))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Topmost located anchor on a PDF page (1-based), for "scroll to page N".
+///
+/// Returns `None` when no anchor was placed on that page.
+pub fn first_anchor_on_page(payload: &SourceMapPayload, page: usize) -> Option<&AnchorEntry> {
+    payload
+        .anchors
+        .iter()
+        .filter_map(|entry| entry.pdf.as_ref().filter(|pdf| pdf.page == page).map(|pdf| (entry, pdf.y)))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(entry, _)| entry)
+}
+
+/// Estimated PDF position of an editor offset, interpolated between the located
+/// anchors either side of it.
+///
+/// With a `tf-doc-end` anchor (see `PreprocessOptions::doc_end_anchor`), offsets past
+/// the last content anchor still have an upper bracket instead of snapping to that
+/// anchor. Brackets on different pages resolve to the nearer one.
+pub fn interpolated_pdf_position(payload: &SourceMapPayload, offset: usize) -> Option<PdfPosition> {
+    let located = payload
+        .anchors
+        .iter()
+        .filter_map(|entry| entry.pdf.as_ref().map(|pdf| (entry.editor.offset, pdf)));
+    let mut lower: Option<(usize, &PdfPosition)> = None;
+    let mut upper: Option<(usize, &PdfPosition)> = None;
+    for (anchor_offset, pdf) in located {
+        if anchor_offset <= offset {
+            if lower.map_or(true, |(best, _)| anchor_offset >= best) {
+                lower = Some((anchor_offset, pdf));
+            }
+        } else if upper.map_or(true, |(best, _)| anchor_offset < best) {
+            upper = Some((anchor_offset, pdf));
+        }
+    }
+
+    match (lower, upper) {
+        (Some((start, from)), Some((end, to))) => {
+            let t = (offset - start) as f32 / (end - start) as f32;
+            if from.page == to.page {
+                Some(PdfPosition {
+                    y: from.y + (to.y - from.y) * t,
+                    source_line: None,
+                    ..from.clone()
+                })
+            } else if t < 0.5 {
+                Some(from.clone())
+            } else {
+                Some(to.clone())
+            }
+        }
+        (Some((_, only)), None) | (None, Some((_, only))) => Some(only.clone()),
+        (None, None) => None,
+    }
 }
 
 /// Parse PDF positions from Typst query JSON output.
@@ -33,12 +151,15 @@ pub fn pdf_positions_from_query(json_bytes: &[u8]) -> Result<HashMap<String, Pdf
     
     if let Some(entries) = value.as_array() {
         for entry in entries {
-            if let Some(label) = find_label(entry) {
-                if !label.starts_with("tf-") {
-                    continue;
-                }
+            let metadata = anchor_metadata(entry);
+            let label = match &metadata {
+                Some((id, _)) => Some(id.clone()),
+                None => find_label(entry).filter(|label| label.starts_with("tf-")),
+            };
+            if let Some(label) = label {
                 if let Some((page, x, y)) = find_location(entry) {
-                    map.insert(label, PdfPosition { page, x, y });
+                    let source_line = metadata.map(|(_, line)| line);
+                    map.insert(label, PdfPosition { page, x, y, source_line });
                 }
             }
         }
@@ -46,6 +167,16 @@ pub fn pdf_positions_from_query(json_bytes: &[u8]) -> Result<HashMap<String, Pdf
     Ok(map)
 }
 
+/// Read `(id, line)` from the value of an [`AnchorSyntax::Metadata`] anchor.
+///
+/// [`AnchorSyntax::Metadata`]: super::types::AnchorSyntax::Metadata
+fn anchor_metadata(entry: &serde_json::Value) -> Option<(String, usize)> {
+    let value = entry.get("value")?;
+    let id = value.get("id")?.as_str()?;
+    let line = value.get("line")?.as_u64()?;
+    Some((id.to_owned(), line as usize))
+}
+
 /// Recursively search for a label in a JSON value.
 fn find_label(value: &serde_json::Value) -> Option<String> {
     match value {
@@ -131,3 +262,197 @@ fn parse_coordinate(value: Option<&serde_json::Value>) -> f32 {
         .and_then(|v| v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse().ok())))
         .unwrap_or(0.0) as f32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocessor::types::{AnchorSyntax, PreprocessOptions};
+    use crate::preprocessor::anchors::inject_anchors_with_options;
+
+    fn anchor(id: &str, offset: usize, line: usize) -> AnchorMeta {
+        AnchorMeta { id: id.to_string(), offset, line, column: 0, preview: None, seq: 0 }
+    }
+
+    #[test]
+    fn test_max_page() {
+        let anchors: Vec<AnchorMeta> = (1..=6)
+            .map(|i| anchor(&format!("tf-{}", i), i * 10, i))
+            .collect();
+        assert_eq!(max_page(&attach_pdf_positions(&anchors, &HashMap::new())), 0);
+
+        // Anchor tf-6 has no position; pages are listed out of order
+        let positions: HashMap<String, PdfPosition> = [3, 1, 5, 2, 4]
+            .iter()
+            .enumerate()
+            .map(|(i, &page)| (format!("tf-{}", i + 1), PdfPosition { page, x: 0.0, y: 0.0, source_line: None }))
+            .collect();
+        assert_eq!(max_page(&attach_pdf_positions(&anchors, &positions)), 5);
+    }
+
+    #[test]
+    fn test_merge_source_maps() {
+        let chapter = |prefix: &str, pages: &[usize]| {
+            let anchors: Vec<AnchorMeta> = (0..=pages.len())
+                .map(|i| anchor(&format!("{}-tf-{}", prefix, i), i * 10, i))
+                .collect();
+            // The last anchor stays unlocated
+            let positions: HashMap<String, PdfPosition> = pages
+                .iter()
+                .enumerate()
+                .map(|(i, &page)| (format!("{}-tf-{}", prefix, i), PdfPosition { page, x: 0.0, y: 10.0, source_line: None }))
+                .collect();
+            attach_pdf_positions(&anchors, &positions)
+        };
+
+        let merged = merge_source_maps(vec![(chapter("one", &[1, 2, 3]), 3), (chapter("two", &[1, 1, 2]), 2)]);
+
+        let pages: Vec<(&str, Option<usize>)> = merged
+            .anchors
+            .iter()
+            .map(|entry| (entry.id.as_str(), entry.pdf.as_ref().map(|pdf| pdf.page)))
+            .collect();
+        assert_eq!(
+            pages,
+            vec![
+                ("one-tf-0", Some(1)),
+                ("one-tf-1", Some(2)),
+                ("one-tf-2", Some(3)),
+                ("one-tf-3", None),
+                ("two-tf-0", Some(4)),
+                ("two-tf-1", Some(4)),
+                ("two-tf-2", Some(5)),
+                ("two-tf-3", None),
+            ]
+        );
+        assert_eq!((merged.located_count, merged.total_count), (6, 8));
+        assert_eq!(merged.anchors[4].editor.offset, 0);
+    }
+
+    #[test]
+    fn test_first_anchor_on_page() {
+        let anchors: Vec<AnchorMeta> = (1..=6)
+            .map(|i| anchor(&format!("tf-{}", i), i * 10, i))
+            .collect();
+        // (page, y) per anchor; tf-6 is unlocated
+        let positions: HashMap<String, PdfPosition> = [(1, 300.0), (1, 72.0), (2, 500.0), (2, 90.0), (3, 40.0)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(page, y))| (format!("tf-{}", i + 1), PdfPosition { page, x: 0.0, y, source_line: None }))
+            .collect();
+        let payload = attach_pdf_positions(&anchors, &positions);
+
+        let first_ids: Vec<Option<&str>> = (1..=4)
+            .map(|page| first_anchor_on_page(&payload, page).map(|entry| entry.id.as_str()))
+            .collect();
+        assert_eq!(first_ids, vec![Some("tf-2"), Some("tf-4"), Some("tf-5"), None]);
+    }
+
+    #[test]
+    fn test_source_map_coverage_counts() {
+        let anchors: Vec<AnchorMeta> = (0..5)
+            .map(|i| anchor(&format!("tf-{}", i), i, i))
+            .collect();
+        let positions = HashMap::from([
+            ("tf-0".to_string(), PdfPosition { page: 1, x: 0.0, y: 0.0, source_line: None }),
+            ("tf-3".to_string(), PdfPosition { page: 2, x: 0.0, y: 10.0, source_line: None }),
+            ("unrelated".to_string(), PdfPosition { page: 3, x: 0.0, y: 0.0, source_line: None }),
+        ]);
+        let payload = attach_pdf_positions(&anchors, &positions);
+
+        assert_eq!(payload.anchors.len(), 5);
+        assert_eq!((payload.located_count, payload.total_count), (2, 5));
+
+        let empty = SourceMapPayload::default();
+        assert_eq!((empty.located_count, empty.total_count), (0, 0));
+    }
+
+    #[test]
+    fn test_write_source_map_ndjson() {
+        let anchors: Vec<AnchorMeta> = ["tf-doc-start", "intro", "tf-hr-1"]
+            .iter()
+            .enumerate()
+            .map(|(i, id)| anchor(id, i * 20, i * 2))
+            .collect();
+        let positions = HashMap::from([("intro".to_string(), PdfPosition { page: 2, x: 10.0, y: 42.5, source_line: None })]);
+        let payload = attach_pdf_positions(&anchors, &positions);
+
+        let mut out = Vec::new();
+        write_source_map_ndjson(&payload, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        let entries: Vec<AnchorEntry> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("each line is one AnchorEntry"))
+            .collect();
+        assert_eq!(entries.len(), payload.anchors.len());
+        assert_eq!(entries[1].id, "intro");
+        assert_eq!(entries[1].editor.offset, 20);
+        assert_eq!(entries[1].pdf.as_ref().map(|p| p.page), Some(2));
+        assert!(entries[2].pdf.is_none());
+    }
+
+    #[test]
+    fn test_metadata_anchors_round_trip() {
+        let options = PreprocessOptions {
+            anchor_syntax: AnchorSyntax::Metadata,
+            ..Default::default()
+        };
+        let output = inject_anchors_with_options("# Intro\n\nFirst paragraph.\n\n## Next\n\nMore.", &options).unwrap();
+
+        // Simulate `typst query` over the emitted metadata, one page per anchor
+        let metadata = regex::Regex::new(r#"#metadata\(\(id: "([^"]+)", line: (\d+)\)\)"#).unwrap();
+        let query: Vec<serde_json::Value> = metadata
+            .captures_iter(&output.markdown)
+            .enumerate()
+            .map(|(i, caps)| {
+                serde_json::json!({
+                    "func": "metadata",
+                    "value": { "id": &caps[1], "line": caps[2].parse::<u64>().unwrap() },
+                    "location": { "page": i + 1, "position": { "x": 0, "y": 72 } },
+                })
+            })
+            .collect();
+        assert_eq!(query.len(), output.anchors.len());
+
+        let positions = pdf_positions_from_query(&serde_json::to_vec(&query).unwrap()).unwrap();
+        let payload = attach_pdf_positions(&output.anchors, &positions);
+
+        assert_eq!(payload.located_count, payload.total_count);
+        for entry in &payload.anchors {
+            let pdf = entry.pdf.as_ref().unwrap();
+            assert_eq!(pdf.source_line, Some(entry.editor.line), "{}", entry.id);
+        }
+        assert!(payload.anchors.iter().any(|entry| entry.id == "next" && entry.editor.line == 4));
+    }
+
+    #[test]
+    fn test_interpolation_past_last_anchor_uses_doc_end() {
+        let options = PreprocessOptions {
+            doc_end_anchor: true,
+            ..Default::default()
+        };
+        let md = "Intro.\n\nLast paragraph.\n\n[^1]: A long trailing footnote.\n";
+        let output = inject_anchors_with_options(md, &options).unwrap();
+        let doc_end = output.anchors.last().unwrap();
+        assert_eq!((doc_end.id.as_str(), doc_end.offset), ("tf-doc-end", md.len()));
+
+        let last = output.anchors.iter().find(|a| a.offset == md.find("Last").unwrap()).unwrap();
+        let position = |y: f32| PdfPosition { page: 1, x: 72.0, y, source_line: None };
+        let mut positions = HashMap::from([
+            ("tf-doc-start".to_string(), position(72.0)),
+            (last.id.clone(), position(200.0)),
+        ]);
+        let footnote = md.find("A long").unwrap();
+
+        // Without a located doc-end the last paragraph is the only bracket
+        let payload = attach_pdf_positions(&output.anchors, &positions);
+        assert_eq!(interpolated_pdf_position(&payload, footnote).unwrap().y, 200.0);
+
+        positions.insert("tf-doc-end".to_string(), position(600.0));
+        let payload = attach_pdf_positions(&output.anchors, &positions);
+        let expected = 200.0 + 400.0 * (footnote - last.offset) as f32 / (md.len() - last.offset) as f32;
+        let interpolated = interpolated_pdf_position(&payload, footnote).unwrap();
+        assert!((interpolated.y - expected).abs() < 0.01, "{} != {}", interpolated.y, expected);
+        assert_eq!(interpolated.page, 1);
+    }
+}
@@ -1,10 +1,14 @@
 //! Core types for the markdown preprocessor.
 
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+use std::ops::Range;
 
 /// Position in the editor (source markdown).
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EditorPosition {
     pub offset: usize,
     pub line: usize,
@@ -12,17 +16,23 @@ pub struct EditorPosition {
 }
 
 /// Position in the rendered PDF.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PdfPosition {
     pub page: usize,
     pub x: f32,
     pub y: f32,
+    /// 0-based source line reported by [`AnchorSyntax::Metadata`] anchors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_line: Option<usize>,
 }
 
 /// A single anchor entry combining editor and PDF positions.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnchorEntry {
     pub id: String,
+    /// Position in document order, see [`AnchorMeta::seq`]
+    #[serde(default)]
+    pub seq: usize,
     pub editor: EditorPosition,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pdf: Option<PdfPosition>,
@@ -32,22 +42,279 @@ pub struct AnchorEntry {
 #[derive(Debug, Clone, Serialize, Default)]
 pub struct SourceMapPayload {
     pub anchors: Vec<AnchorEntry>,
+    /// Anchors that Typst placed in the PDF (the rest only have editor positions)
+    pub located_count: usize,
+    /// All anchors, located or not
+    pub total_count: usize,
 }
 
 /// Metadata about an anchor during preprocessing.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AnchorMeta {
     pub id: String,
     pub offset: usize,
     pub line: usize,
     pub column: usize,
+    /// Position in document order, strictly increasing from 0, so consumers can
+    /// order anchors without relying on offsets
+    pub seq: usize,
+    /// Start of the element's text, when [`PreprocessOptions::anchor_previews`] is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+}
+
+/// Fence info string of an anchored code block, split into language and attributes.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct CodeBlockInfo {
+    /// First token of the info string (`rust` in ```` ```rust {linenos} ````)
+    pub language: String,
+    /// Everything after the language (`{linenos}`), trimmed
+    pub attributes: String,
+}
+
+/// Where the `#label` comment for a heading is placed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeadingLabelPlacement {
+    /// Appended to the heading line: `# Title <!--raw-typst #label("title") -->`
+    #[default]
+    EndOfLine,
+    /// On its own line directly after the heading
+    NextLine,
+}
+
+/// Suffix appended to duplicate heading slugs. The first occurrence keeps the bare
+/// slug; later ones get `separator` followed by `start`, `start + 1`, ...
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlugSuffixStyle {
+    pub separator: String,
+    pub start: usize,
+}
+
+impl Default for SlugSuffixStyle {
+    /// GitHub style: `intro`, `intro-1`, `intro-2`
+    fn default() -> Self {
+        Self {
+            separator: "-".to_string(),
+            start: 1,
+        }
+    }
+}
+
+/// How a multi-key citation (`[@a; @b]`) is emitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiCitationMode {
+    /// One `#cite` call per key, separated by spaces
+    #[default]
+    SeparateCalls,
+    /// Adjacent `#cite` calls in one content block, rendered as a single group
+    GroupedBracket,
+}
+
+/// How anchor labels are written into the markdown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorSyntax {
+    /// cmarker raw-typst comment: `<!--raw-typst #label("id") -->`
+    #[default]
+    RawTypstComment,
+    /// Queryable metadata carrying the source line:
+    /// `<!--raw-typst #metadata((id: "id", line: N)) #label("id") -->`
+    Metadata,
+}
+
+/// Which horizontal rules (`---`) receive a `tf-hr-N` sync anchor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAnchorMode {
+    /// Anchor every rule
+    #[default]
+    All,
+    /// Never anchor rules
+    Off,
+    /// Only anchor rules directly followed by a heading (section separators)
+    SectionSeparators,
+}
+
+/// What to do with raw HTML blocks, which Typst cannot render.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RawHtmlMode {
+    /// Leave HTML blocks as written
+    #[default]
+    PassThrough,
+    /// Remove HTML blocks
+    Strip,
+    /// Show HTML blocks as literal text
+    Escape,
+}
+
+/// What to do with headings whose text produces an empty slug (`#` alone, `# !!!`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyHeadingMode {
+    /// Leave the heading without a label or anchor
+    #[default]
+    Skip,
+    /// Label and anchor the heading as `tf-heading-N`
+    FallbackId,
+    /// Skip the heading and report it in [`PreprocessorOutput::warnings`]
+    Error,
+}
+
+/// Which export a document is prepared for, deciding the fate of `:::draft` blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportMode {
+    /// Keep draft blocks
+    #[default]
+    Draft,
+    /// Remove draft blocks
+    Final,
+}
+
+/// Default input size limit: far beyond any real document, but stops anchor
+/// injection from exhausting memory on a runaway file.
+pub const DEFAULT_MAX_INPUT_BYTES: usize = 50 * 1024 * 1024;
+
+/// Options controlling how markdown is preprocessed.
+#[derive(Debug, Clone, Default)]
+pub struct PreprocessOptions {
+    /// Convert `[@key]` citations to `#cite()` calls (requires a loaded bibliography)
+    pub has_bibliography: bool,
+    /// Fast live-preview pass: leave `[@key]` citations literal even with a bibliography.
+    /// Exports always run without it
+    pub preview_mode: bool,
+    /// Underline converted citations so their link to the bibliography entry is visible
+    pub link_citations: bool,
+    /// Label every converted citation and list, after the body, the pages citing
+    /// each source with links back to them
+    pub citation_backrefs: bool,
+    /// How `[@a; @b]` citation groups are emitted
+    pub multi_citation_mode: MultiCitationMode,
+    /// Prefixes stripped from citation keys (e.g. `zotero:`) before emitting `#cite`
+    pub citation_key_prefixes: Vec<String>,
+    /// Treat a heading's `{#id}` as a stable sync id: the scroll anchor keeps the id
+    /// while the link label follows the (editable) heading text
+    pub stable_heading_ids: bool,
+    /// Blockquotes longer than this many bytes get an anchor on every paragraph
+    /// inside them; `None` leaves blockquotes unanchored
+    pub quote_anchor_threshold: Option<usize>,
+    /// Placement of heading labels relative to the heading line
+    pub heading_label_placement: HeadingLabelPlacement,
+    /// Also emit `#metadata((level: N, slug: ".."))` per heading so the template can
+    /// build the PDF outline from reliable levels
+    pub heading_metadata: bool,
+    /// Syntax used for injected anchor labels
+    pub anchor_syntax: AnchorSyntax,
+    /// Which horizontal rules get sync anchors
+    pub rule_anchors: RuleAnchorMode,
+    /// Emit editor-only `fm-title`/`fm-author` anchors for frontmatter fields
+    pub frontmatter_anchors: bool,
+    /// Give each anchor a short whitespace-collapsed text preview for an editor minimap.
+    /// Off by default since it grows the anchor payload
+    pub anchor_previews: bool,
+    /// Add break opportunities to table-cell words longer than this many characters;
+    /// `None` leaves tables as written
+    pub table_cell_break_length: Option<usize>,
+    /// Treat `#Title` (no space after the hashes) as a heading. CommonMark renders
+    /// it as literal text, so this is off by default
+    pub lenient_atx_headings: bool,
+    /// Treatment of raw HTML blocks (comments and raw Typst are never touched)
+    pub raw_html: RawHtmlMode,
+    /// Keep author comments (`<!-- TODO -->`) in the output instead of stripping them.
+    /// They never render either way; keeping them preserves the source layout
+    pub keep_author_comments: bool,
+    /// Keep or drop `:::draft` ... `:::` blocks
+    pub export_mode: ExportMode,
+    /// Collision suffix for duplicate heading slugs
+    pub slug_suffix: SlugSuffixStyle,
+    /// Handling of headings with no sluggable text
+    pub empty_headings: EmptyHeadingMode,
+    /// Render local images at this Typst width (e.g. `100%`) so oversized images
+    /// cannot overflow the page; `None` leaves image references as written
+    pub max_image_width: Option<String>,
+    /// Citation keys defined by the bibliography, parsed once by the caller. When set,
+    /// cited keys missing from it are reported in [`PreprocessorOutput::unknown_citations`]
+    pub known_keys: Option<HashSet<String>>,
+    /// Language assumed for code fences without an info string. A frontmatter
+    /// `default_code_lang:` key overrides it for that document
+    pub default_code_lang: Option<String>,
+    /// Close unmatched opening `**`/`*`/`_` markers at the end of their block. They are
+    /// always reported in [`PreprocessorOutput::warnings`]; repairing is opt-in
+    pub repair_emphasis: bool,
+    /// Turn `[^1]` footnotes into explicit Typst `#footnote[...]` calls instead of
+    /// relying on the markdown bridge
+    pub explicit_footnotes: bool,
+    /// Convert LaTeX-style `\label{x}` / `\ref{x}` into Typst labels and references
+    pub latex_cross_refs: bool,
+    /// Emit a `tf-doc-end` anchor after the last block, bracketing scroll interpolation
+    /// for trailing content such as footnotes
+    pub doc_end_anchor: bool,
+    /// Largest input accepted, in bytes; `None` means [`DEFAULT_MAX_INPUT_BYTES`].
+    /// Larger documents fail with [`crate::error::PreprocessError::TooLarge`]
+    pub max_input_bytes: Option<usize>,
+    /// Record how long each preprocessing phase took in [`PreprocessorOutput::timings`]
+    pub record_timings: bool,
+    /// Editor byte range being edited. With a previous output supplied to
+    /// [`super::preprocess_markdown_with_previous`], only anchors in and around this
+    /// range are regenerated
+    pub focus_range: Option<Range<usize>>,
+}
+
+/// Wall-clock time spent in each preprocessing phase.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PhaseTimings {
+    pub frontmatter_split: Duration,
+    /// Citation counting and conversion
+    pub citation_conversion: Duration,
+    pub normalization: Duration,
+    pub anchor_injection: Duration,
+    /// Reattaching frontmatter and shifting positions past it
+    pub output_build: Duration,
 }
 
 /// Output from the preprocessor containing processed markdown and anchor metadata.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct PreprocessorOutput {
     pub markdown: String,
     pub anchors: Vec<AnchorMeta>,
+    /// Placeholder citation keys (e.g. `smith20??`) left unconverted for follow-up
+    pub draft_citations: Vec<String>,
+    /// Number of times each citation key is cited
+    pub citation_counts: HashMap<String, usize>,
+    /// Keys converted to `#cite` calls, deduplicated in first-appearance order, so the
+    /// bibliography can be limited to cited references. Empty without a bibliography
+    pub cited_keys: Vec<String>,
+    /// Footnote label (`[^label]`) -> editor position of its definition
+    pub footnotes: HashMap<String, EditorPosition>,
+    /// Code anchor id -> full fence info string of that code block
+    pub code_blocks: HashMap<String, CodeBlockInfo>,
+    /// The body has no renderable content (only frontmatter, whitespace or comments)
+    pub is_empty: bool,
+    /// Language code for hyphenation: frontmatter `lang:`, else guessed from the body
+    pub detected_lang: Option<String>,
+    /// Snippets inserted by anchor injection as (source offset, byte length), sorted by offset
+    pub insertions: Vec<(usize, usize)>,
+    /// Per-phase timings, when requested via [`PreprocessOptions::record_timings`]
+    pub timings: Option<PhaseTimings>,
+    /// Citation conversion or normalization rewrote the body, beyond anchor injection
+    pub was_normalized: bool,
+    /// Layout and source diagnostics, e.g. tables likely too wide for the page or
+    /// unbalanced emphasis
+    pub warnings: Vec<String>,
+    /// Cited keys not in [`PreprocessOptions::known_keys`], sorted
+    pub unknown_citations: Vec<String>,
+}
+
+/// Put anchors in document (source offset) order and number them with [`AnchorMeta::seq`].
+///
+/// The sort is stable, so anchors sharing an offset keep their emission order.
+pub(crate) fn number_anchors(anchors: &mut [AnchorMeta]) {
+    anchors.sort_by_key(|anchor| anchor.offset);
+    for (seq, anchor) in anchors.iter_mut().enumerate() {
+        anchor.seq = seq;
+    }
 }
 
 /// Convert a byte offset to (line, column) in the source.
@@ -66,7 +333,6 @@ pub fn offset_to_line_column(source: &str, offset: usize) -> (usize, usize) {
 }
 
 /// Build a lookup table from anchor ID to editor position.
-#[allow(dead_code)]
 pub fn anchors_to_lookup(anchors: &[AnchorMeta]) -> HashMap<String, EditorPosition> {
     anchors
         .iter()
@@ -82,3 +348,117 @@ pub fn anchors_to_lookup(anchors: &[AnchorMeta]) -> HashMap<String, EditorPositi
         })
         .collect()
 }
+
+/// Number of bytes the preprocessor inserted before `source_offset`.
+///
+/// Adding this to a source offset gives the matching offset in `output.markdown`.
+/// Only anchor insertions (and the frontmatter separator) are counted; citation
+/// and normalization rewrites change text length and are not tracked.
+pub fn inserted_bytes_before(output: &PreprocessorOutput, source_offset: usize) -> usize {
+    output
+        .insertions
+        .iter()
+        .take_while(|(offset, _)| *offset <= source_offset)
+        .map(|(_, len)| len)
+        .sum()
+}
+
+/// Shift source offsets at or after `edit_at` by `delta` bytes after an edit that
+/// did not change the document structure, instead of preprocessing again.
+///
+/// Anchors inside a deleted span collapse onto `edit_at`. Lines and columns are not
+/// touched, since they depend on the edited text; call [`refresh_anchor_positions`]
+/// with the edited source when they are needed. `output.markdown` is not updated.
+pub fn shift_anchor_offsets(output: &mut PreprocessorOutput, edit_at: usize, delta: isize) {
+    let shift = |offset: &mut usize| {
+        if *offset >= edit_at {
+            *offset = offset.saturating_add_signed(delta).max(edit_at);
+        }
+    };
+    output.anchors.iter_mut().for_each(|anchor| shift(&mut anchor.offset));
+    output.footnotes.values_mut().for_each(|position| shift(&mut position.offset));
+    output.insertions.iter_mut().for_each(|(offset, _)| shift(offset));
+}
+
+/// Recompute anchor and footnote lines/columns from their offsets in `source`.
+pub fn refresh_anchor_positions(output: &mut PreprocessorOutput, source: &str) {
+    let position = |offset: usize| offset_to_line_column(source, offset.min(source.len()));
+    for anchor in &mut output.anchors {
+        (anchor.line, anchor.column) = position(anchor.offset);
+    }
+    for footnote in output.footnotes.values_mut() {
+        (footnote.line, footnote.column) = position(footnote.offset);
+    }
+}
+
+/// Group anchor ids by editor line, for gutter markers.
+///
+/// Ids on the same line are ordered by column, then by id.
+pub fn anchors_by_line(output: &PreprocessorOutput) -> BTreeMap<usize, Vec<String>> {
+    let mut anchors: Vec<&AnchorMeta> = output.anchors.iter().collect();
+    anchors.sort_by(|a, b| (a.line, a.column, &a.id).cmp(&(b.line, b.column, &b.id)));
+
+    let mut by_line: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for anchor in anchors {
+        by_line.entry(anchor.line).or_default().push(anchor.id.clone());
+    }
+    by_line
+}
+
+/// Fingerprint the document structure (anchor ids and their order).
+///
+/// Offsets and prose are ignored, so rewording a paragraph keeps the fingerprint
+/// while adding, removing or reordering headings and blocks changes it. Generic
+/// paragraph anchors (`tf-<offset>-<index>`) embed source offsets and are hashed
+/// as a placeholder.
+pub fn structural_fingerprint(output: &PreprocessorOutput) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for anchor in &output.anchors {
+        if is_offset_anchor(&anchor.id) {
+            "tf-paragraph".hash(&mut hasher);
+        } else {
+            anchor.id.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// Element category of an anchor (`heading`, `paragraph`, `code`, ...), from its id prefix.
+///
+/// Generic `tf-<offset>-<index>` anchors mark any plain block and count as `paragraph`;
+/// ids without a `tf-`/`fm-` prefix are heading slugs.
+pub fn anchor_category(id: &str) -> &'static str {
+    const PREFIXES: [(&str, &str); 10] = [
+        ("tf-doc-start", "document"),
+        ("tf-doc-end", "document"),
+        ("tf-code", "code"),
+        ("tf-img-", "image"),
+        ("tf-hr-", "hr"),
+        ("tf-quote-", "quote"),
+        ("tf-admonition-", "admonition"),
+        ("tf-pagebreak-", "pagebreak"),
+        ("tf-heading-", "heading"),
+        ("fm-", "frontmatter"),
+    ];
+
+    if is_offset_anchor(id) {
+        return "paragraph";
+    }
+    match PREFIXES.iter().find(|(prefix, _)| id.starts_with(prefix)) {
+        Some((_, category)) => category,
+        None if id.starts_with("tf-") => "other",
+        None => "heading",
+    }
+}
+
+/// Check whether an anchor id is a generic `tf-<offset>-<index>` paragraph anchor.
+pub(crate) fn is_offset_anchor(id: &str) -> bool {
+    id.strip_prefix("tf-")
+        .and_then(|rest| rest.split_once('-'))
+        .is_some_and(|(offset, index)| {
+            !offset.is_empty()
+                && !index.is_empty()
+                && offset.bytes().all(|b| b.is_ascii_digit())
+                && index.bytes().all(|b| b.is_ascii_digit())
+        })
+}
@@ -187,37 +187,8 @@ fn detect_image_format(path: &Path) -> Result<Option<&'static str>> {
     let mut file = fs::File::open(path)?;
     let mut header = [0u8; 12];
     let bytes_read = file.read(&mut header)?;
-    
-    if bytes_read < 4 {
-        return Ok(None);
-    }
-    
-    // PNG: 89 50 4E 47
-    if header[0..4] == [0x89, 0x50, 0x4E, 0x47] {
-        return Ok(Some("png"));
-    }
-    
-    // JPEG: FF D8 FF
-    if header[0..3] == [0xFF, 0xD8, 0xFF] {
-        return Ok(Some("jpg"));
-    }
-    
-    // GIF: 47 49 46
-    if header[0..3] == [0x47, 0x49, 0x46] {
-        return Ok(Some("gif"));
-    }
-    
-    // WebP: RIFF....WEBP
-    if bytes_read >= 12 && header[0..4] == [0x52, 0x49, 0x46, 0x46] && header[8..12] == [0x57, 0x45, 0x42, 0x50] {
-        return Ok(Some("webp"));
-    }
-    
-    // BMP: 42 4D
-    if header[0..2] == [0x42, 0x4D] {
-        return Ok(Some("bmp"));
-    }
-    
-    Ok(None)
+
+    Ok(utils::detect_image_extension(&header[..bytes_read]))
 }
 
 /// Handle cover image path rewriting and copying to assets directory.
@@ -226,8 +197,9 @@ fn handle_cover_image(
     prefs_val: &mut JsonValue,
     app_handle: &AppHandle,
 ) -> Result<()> {
+    let assets_prefix = format!("/{}/", utils::assets_dir_name(app_handle));
     if let Some(ci) = prefs_val.get("cover_image").and_then(|v| v.as_str()) {
-        if !ci.is_empty() && !ci.starts_with("/assets/") {
+        if !ci.is_empty() && !ci.starts_with(&assets_prefix) {
             let mut img_path = PathBuf::from(ci);
             if !img_path.is_absolute() {
                 let maybe = utils::get_content_dir(app_handle)?.join(&img_path);
@@ -267,7 +239,7 @@ fn handle_cover_image(
                 }
                 
                 fs::copy(&img_path, &dest)?;
-                prefs_val["cover_image"] = JsonValue::String(format!("/assets/{}", fname));
+                prefs_val["cover_image"] = JsonValue::String(format!("{}{}", assets_prefix, fname));
             }
         }
     }
@@ -311,6 +283,20 @@ pub fn setup_prefs(config: &RenderConfig, path_type: &str) -> Result<PrefsSetupR
     })
 }
 
+/// Remove the bibliography from the build prefs so the template skips it.
+///
+/// Used by preview-mode renders, which leave citations literal anyway.
+pub fn drop_bibliography_pref(config: &RenderConfig) -> Result<()> {
+    let prefs_path = config.build_dir.join("prefs.json");
+    let mut prefs_val = serde_json::from_str::<JsonValue>(&fs::read_to_string(&prefs_path)?)?;
+    if let Some(prefs) = prefs_val.as_object_mut() {
+        if prefs.remove("bibliography_path").is_some() {
+            fs::write(&prefs_path, serde_json::to_string_pretty(&prefs_val)?)?;
+        }
+    }
+    Ok(())
+}
+
 /// Setup template for rendering: copy tideflow.typ and sync theme assets,
 /// emit template inspection events.
 pub fn setup_template(config: &RenderConfig, path_type: &str) -> Result<()> {
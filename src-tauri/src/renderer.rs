@@ -1,7 +1,8 @@
 use crate::log_debug;
+use crate::preferences;
 use crate::preprocessor::{
-    attach_pdf_positions, pdf_positions_from_query, preprocess_markdown, AnchorMeta, PdfPosition,
-    SourceMapPayload,
+    attach_pdf_positions, max_page, pdf_positions_from_query, preprocess_markdown_with_options,
+    AnchorMeta, ExportMode, PdfPosition, PreprocessOptions, SourceMapPayload,
 };
 use crate::render_pipeline::{self, RenderConfig};
 use crate::utils;
@@ -18,6 +19,10 @@ use tokio::sync::Mutex;
 pub struct RenderedDocument {
     pub pdf_path: String,
     pub source_map: SourceMapPayload,
+    /// Highest page seen in the source map (0 when positions are unavailable)
+    pub page_count: usize,
+    /// Rendered without citations or bibliography for speed; not what an export produces
+    pub preview_mode: bool,
 }
 
 // A global mutex to ensure only one render happens at a time
@@ -26,7 +31,7 @@ lazy_static::lazy_static! {
 }
 
 /// Check if bibliography is enabled by reading preferences
-fn has_bibliography_enabled(app_handle: &AppHandle) -> bool {
+pub(crate) fn has_bibliography_enabled(app_handle: &AppHandle) -> bool {
     let content_dir = match utils::get_content_dir(app_handle) {
         Ok(dir) => dir,
         Err(_) => return false,
@@ -52,6 +57,38 @@ fn has_bibliography_enabled(app_handle: &AppHandle) -> bool {
     false
 }
 
+/// Preprocessor options from preferences for a render of the given kind: live previews
+/// keep `:::draft` blocks, exports drop them.
+pub(crate) fn preprocess_options(app_handle: &AppHandle, export_mode: ExportMode) -> PreprocessOptions {
+    let prefs = preferences::load_preferences(app_handle);
+    PreprocessOptions {
+        has_bibliography: has_bibliography_enabled(app_handle),
+        link_citations: prefs.link_citations,
+        citation_backrefs: prefs.citation_backrefs,
+        multi_citation_mode: prefs.multi_citation_mode,
+        heading_label_placement: prefs.heading_label_placement,
+        anchor_syntax: prefs.anchor_syntax,
+        rule_anchors: prefs.rule_anchors,
+        raw_html: prefs.raw_html,
+        empty_headings: prefs.empty_headings,
+        explicit_footnotes: prefs.explicit_footnotes,
+        latex_cross_refs: prefs.latex_cross_refs,
+        max_image_width: prefs.max_image_width.as_deref().and_then(typst_length),
+        export_mode,
+        ..Default::default()
+    }
+}
+
+/// `width` when it is a plain Typst length or ratio (`100%`, `12cm`), the only values
+/// safe to splice into generated `#image` calls.
+fn typst_length(width: &str) -> Option<String> {
+    let width = width.trim();
+    let number_end = width.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (number, unit) = width.split_at(number_end);
+    let valid = number.parse::<f64>().is_ok() && matches!(unit, "%" | "pt" | "mm" | "cm" | "in" | "em");
+    valid.then(|| width.to_string())
+}
+
 fn build_source_map(
     app_handle: &AppHandle,
     typst_path: &Path,
@@ -248,11 +285,11 @@ pub async fn render_markdown(app_handle: &AppHandle, file_path: &str) -> Result<
     let assets_root = utils::get_assets_dir(app_handle).ok();
     let assets_root_ref = assets_root.as_deref();
 
-    // Check if bibliography is enabled to determine whether to convert citations
-    let has_bib = has_bibliography_enabled(app_handle);
-
     // Clean (export) version: do NOT inject visible tokens
-    let preprocess_clean = preprocess_markdown(&md_content_raw, has_bib)?;
+    let preprocess_clean = preprocess_markdown_with_options(
+        &md_content_raw,
+        &preprocess_options(app_handle, ExportMode::Final),
+    )?;
     let md_content_clean = utils::rewrite_image_paths_in_markdown(
         &preprocess_clean.markdown,
         base_dir,
@@ -261,7 +298,10 @@ pub async fn render_markdown(app_handle: &AppHandle, file_path: &str) -> Result<
     fs::write(build_dir.join("content.md"), &md_content_clean)?;
 
     // Preview version: inject preview-only tokens (these will NOT be used for exports)
-    let preprocess_preview = preprocess_markdown(&md_content_raw, has_bib)?;
+    let preprocess_preview = preprocess_markdown_with_options(
+        &md_content_raw,
+        &preprocess_options(app_handle, ExportMode::Draft),
+    )?;
     let md_content_preview = utils::rewrite_image_paths_in_markdown(
         &preprocess_preview.markdown,
         base_dir,
@@ -311,7 +351,9 @@ pub async fn render_markdown(app_handle: &AppHandle, file_path: &str) -> Result<
     let source_map = build_source_map(app_handle, &typst_path, &build_dir, &content_dir, &preprocess_clean.anchors);
     let document = RenderedDocument {
         pdf_path: preview_pdf.to_string_lossy().to_string(),
+        page_count: max_page(&source_map),
         source_map,
+        preview_mode: false,
     };
 
     Ok(document)
@@ -356,11 +398,11 @@ pub async fn export_markdown(app_handle: &AppHandle, file_path: &str) -> Result<
     let assets_root = utils::get_assets_dir(app_handle).ok();
     let assets_root_ref = assets_root.as_deref();
 
-    // Check if bibliography is enabled to determine whether to convert citations
-    let has_bib = has_bibliography_enabled(app_handle);
-
     // For export, do NOT inject visible tokens — output must be clean for users
-    let preprocess = preprocess_markdown(&md_content_raw, has_bib)?;
+    let preprocess = preprocess_markdown_with_options(
+        &md_content_raw,
+        &preprocess_options(app_handle, ExportMode::Final),
+    )?;
     let md_content =
         utils::rewrite_image_paths_in_markdown(&preprocess.markdown, base_dir, assets_root_ref);
     fs::write(build_dir.join("content.md"), md_content)?;
@@ -396,6 +438,7 @@ pub async fn render_typst(
     content: &str,
     _format: &str,
     current_file: Option<&str>,
+    preview_mode: bool,
 ) -> Result<RenderedDocument> {
     // Acquire render lock to prevent multiple simultaneous renders
     let _lock = RENDER_MUTEX.lock().await;
@@ -414,12 +457,13 @@ pub async fn render_typst(
     let temp_content_name = format!("temp_{}.md", uuid);
     let temp_content_path = build_dir.join(&temp_content_name);
 
-    // Check if bibliography is enabled to determine whether to convert citations
-    let has_bib = has_bibliography_enabled(app_handle);
-
     // Preprocess content to rewrite image paths so Typst/cmarker can resolve them properly
     // For ad-hoc typst renders, include visible tokens to aid preview extraction
-    let preprocess = preprocess_markdown(content, has_bib)?;
+    let options = PreprocessOptions {
+        preview_mode,
+        ..preprocess_options(app_handle, ExportMode::Draft)
+    };
+    let preprocess = preprocess_markdown_with_options(content, &options)?;
     
     // Determine base directory for image path resolution
     // Use the current file's parent directory if available, otherwise fall back to content_dir
@@ -467,6 +511,9 @@ pub async fn render_typst(
 
     // Setup preferences
     render_pipeline::setup_prefs(&config, "typst-temp")?;
+    if preview_mode {
+        render_pipeline::drop_bibliography_pref(&config)?;
+    }
 
     // Ensure the content is available as content.md (required by template)
     fs::copy(&temp_content_path, build_dir.join("content.md"))?;
@@ -494,7 +541,9 @@ pub async fn render_typst(
     let source_map = build_source_map(app_handle, &typst_path, &build_dir, &content_dir, &preprocess.anchors);
     Ok(RenderedDocument {
         pdf_path: output_path.to_string_lossy().to_string(),
+        page_count: max_page(&source_map),
         source_map,
+        preview_mode,
     })
 }
 
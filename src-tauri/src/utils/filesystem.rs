@@ -91,3 +91,38 @@ pub fn sanitize_filename(filename: &str) -> String {
     
     sanitized
 }
+
+/// Detect an image format from its leading bytes (magic numbers).
+/// Returns the canonical file extension, or `None` if the format is not recognized.
+pub fn detect_image_extension(header: &[u8]) -> Option<&'static str> {
+    if header.len() < 4 {
+        return None;
+    }
+
+    // PNG: 89 50 4E 47
+    if header[0..4] == [0x89, 0x50, 0x4E, 0x47] {
+        return Some("png");
+    }
+
+    // JPEG: FF D8 FF
+    if header[0..3] == [0xFF, 0xD8, 0xFF] {
+        return Some("jpg");
+    }
+
+    // GIF: 47 49 46
+    if header[0..3] == [0x47, 0x49, 0x46] {
+        return Some("gif");
+    }
+
+    // WebP: RIFF....WEBP
+    if header.len() >= 12 && header[0..4] == [0x52, 0x49, 0x46, 0x46] && header[8..12] == [0x57, 0x45, 0x42, 0x50] {
+        return Some("webp");
+    }
+
+    // BMP: 42 4D
+    if header[0..2] == [0x42, 0x4D] {
+        return Some("bmp");
+    }
+
+    None
+}
@@ -2,7 +2,7 @@
 //!
 //! This module provides essential utilities organized by domain:
 //! - `paths`: Directory path resolution (app, content, assets, templates, styles, typst binary)
-//! - `filesystem`: File operations (directory copying, filename sanitization)
+//! - `filesystem`: File operations (directory copying, filename sanitization, image format detection)
 //! - `initialization`: Application setup (directory creation, resource copying, default configs)
 //! - `typst`: Typst-specific utilities (image path rewriting for Markdown/HTML/Typst)
 //! - `logger`: Production-safe logging utilities
@@ -14,11 +14,11 @@ pub mod paths;
 pub mod typst;
 
 // Re-export commonly used functions for backward compatibility
-pub use filesystem::sanitize_filename;
+pub use filesystem::{detect_image_extension, sanitize_filename};
 pub use initialization::{ensure_tideflow_template_exists, initialize_app_directories};
 pub use paths::{
-    get_app_dir, get_assets_dir, get_content_dir,
-    get_templates_dir, get_typst_path,
+    assets_dir_name, get_app_dir, get_assets_dir, get_content_dir,
+    get_templates_dir, get_typst_path, AssetsDirName, DEFAULT_ASSETS_DIR_NAME,
 };
 pub use typst::rewrite_image_paths_in_markdown;
 
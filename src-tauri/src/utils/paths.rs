@@ -3,8 +3,65 @@
 use anyhow::{anyhow, Result};
 use std::fs;
 use std::path::PathBuf;
+use std::sync::RwLock;
 use tauri::{AppHandle, Manager};
 
+/// Name of the assets subdirectory inside the content directory unless overridden
+pub const DEFAULT_ASSETS_DIR_NAME: &str = "assets";
+
+/// Name of the assets subdirectory, held in Tauri managed state and persisted as the
+/// `assets_dir_name` preference.
+pub struct AssetsDirName(RwLock<String>);
+
+impl Default for AssetsDirName {
+    fn default() -> Self {
+        Self(RwLock::new(DEFAULT_ASSETS_DIR_NAME.to_string()))
+    }
+}
+
+impl AssetsDirName {
+    /// State holding `name`, or the default when `name` is not a valid directory name
+    pub fn new(name: &str) -> Self {
+        let state = Self::default();
+        let _ = state.set(name);
+        state
+    }
+
+    /// Current name (e.g. "assets", "images", "media")
+    pub fn get(&self) -> String {
+        self.0
+            .read()
+            .map(|name| name.clone())
+            .unwrap_or_else(|_| DEFAULT_ASSETS_DIR_NAME.to_string())
+    }
+
+    /// Change the name. Must be a single plain directory name.
+    pub fn set(&self, name: &str) -> Result<()> {
+        let name = name.trim();
+        let is_plain = !name.is_empty()
+            && !name.starts_with('.')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_plain {
+            return Err(anyhow!("Invalid assets directory name: {:?}", name));
+        }
+
+        let mut current = self
+            .0
+            .write()
+            .map_err(|_| anyhow!("Assets directory name lock poisoned"))?;
+        *current = name.to_string();
+        Ok(())
+    }
+}
+
+/// Current name of the assets subdirectory for this app (the default before state is managed)
+pub fn assets_dir_name(app_handle: &AppHandle) -> String {
+    app_handle
+        .try_state::<AssetsDirName>()
+        .map(|name| name.get())
+        .unwrap_or_else(|| DEFAULT_ASSETS_DIR_NAME.to_string())
+}
+
 /// Get the app's base directory
 pub fn get_app_dir(app_handle: &AppHandle) -> Result<PathBuf> {
     let app_dir = app_handle
@@ -30,7 +87,7 @@ pub fn get_content_dir(app_handle: &AppHandle) -> Result<PathBuf> {
 /// Get the assets directory
 pub fn get_assets_dir(app_handle: &AppHandle) -> Result<PathBuf> {
     let content_dir = get_content_dir(app_handle)?;
-    let assets_dir = content_dir.join("assets");
+    let assets_dir = content_dir.join(assets_dir_name(app_handle));
     
     if !assets_dir.exists() {
         fs::create_dir_all(&assets_dir)?;
@@ -170,3 +227,26 @@ pub fn get_typst_path(app_handle: &AppHandle) -> Result<PathBuf> {
         attempted_list
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_assets_dir_name() {
+        let state = AssetsDirName::default();
+        assert_eq!(state.get(), DEFAULT_ASSETS_DIR_NAME);
+
+        state.set("media").unwrap();
+        assert_eq!(state.get(), "media");
+
+        for invalid in ["", "../media", "a/b", ".build", "my media"] {
+            assert!(state.set(invalid).is_err(), "{:?} should be rejected", invalid);
+        }
+        assert_eq!(state.get(), "media");
+
+        // A bad saved preference falls back to the default
+        assert_eq!(AssetsDirName::new("../up").get(), DEFAULT_ASSETS_DIR_NAME);
+        assert_eq!(AssetsDirName::new(" images ").get(), "images");
+    }
+}
@@ -49,8 +49,14 @@ pub fn rewrite_image_paths_in_markdown(
         // Normalize input to forward slashes
         let normalized_unwrapped = unwrapped.replace('\\', "/");
 
+        // The assets directory may be renamed (e.g. media/); its name is the link prefix
+        let assets_name = assets_root
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "assets".to_string());
+
         // If path starts with assets/, emit root-relative /assets/... so Typst resolves from --root
-        if normalized_unwrapped.starts_with("assets/") || normalized_unwrapped == "assets" {
+        if normalized_unwrapped.starts_with(&format!("{}/", assets_name)) || normalized_unwrapped == assets_name {
             let mut root_rel = format!("/{}", normalized_unwrapped.trim_start_matches('/'));
             
             // Re-wrap for markdown if needed
@@ -162,7 +168,7 @@ pub fn rewrite_image_paths_in_markdown(
                 
                 if std::fs::copy(&abs, &dest).is_ok() {
                     // Construct root-relative path for Typst (--root points at content_dir)
-                    let mut rel = format!("/assets/{}", fname);
+                    let mut rel = format!("/{}/{}", assets_name, fname);
                     
                     if wrap_for_markdown && (had_angle || rel.contains(' ') || rel.contains('(') || rel.contains(')')) {
                         rel = format!("<{}>", rel);
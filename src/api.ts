@@ -9,6 +9,7 @@ import type {
   SourceMap,
 } from './types';
 import { logger } from './utils/logger';
+import { useUIStore } from './stores/uiStore';
 
 // File operations
 export async function readMarkdownFile(path: string): Promise<string> {
@@ -69,9 +70,19 @@ export async function importImage(
 }
 
 // Import an image from a filesystem path by copying it into the app's assets directory.
+export interface ImportedImage {
+  path: string;
+  warnings: string[];
+}
+
 export async function importImageFromPath(sourcePath: string): Promise<string> {
   // Send both camelCase and snake_case to be safe across bindings
-  return invoke('import_image_from_path', { sourcePath, source_path: sourcePath });
+  const imported = await invoke<ImportedImage>('import_image_from_path', { sourcePath, source_path: sourcePath });
+  // e.g. SVG image references that will render blank
+  for (const warning of imported.warnings) {
+    useUIStore.getState().addToast({ type: 'warning', message: warning });
+  }
+  return imported.path;
 }
 
 // Import a bibliography file from a filesystem path by copying it into the app's content directory.
@@ -80,6 +91,18 @@ export async function importBibliographyFromPath(sourcePath: string): Promise<st
   return invoke('import_bibliography_from_path', { sourcePath, source_path: sourcePath });
 }
 
+export interface BibInfo {
+  entry_count: number;
+  keys: string[];
+  format: 'BibTex' | 'CslYaml' | 'CslJson' | 'Hayagriva' | null;
+  warnings: string[];
+}
+
+// Check a bibliography file's entries and format without importing it.
+export async function inspectBibliography(sourcePath: string): Promise<BibInfo> {
+  return invoke('inspect_bibliography', { sourcePath, source_path: sourcePath });
+}
+
 // Rendering operations
 export async function renderMarkdown(filePath: string): Promise<RenderedDocument> {
   const raw = await invoke<BackendRenderedDocument>('render_markdown', { filePath });
@@ -92,6 +115,7 @@ interface RenderArgs {
   content: string;
   format: string;
   currentFile?: string;
+  previewMode?: boolean;
 }
 
 interface RenderQueueState {
@@ -116,8 +140,9 @@ function normalizeSourceMap(map: SourceMap | undefined): SourceMap {
     return { anchors: [] };
   }
   return {
-    anchors: map.anchors.map((anchor) => ({
+    anchors: map.anchors.map((anchor, index) => ({
       id: anchor.id,
+      seq: anchor.seq ?? index,
       editor: anchor.editor,
       pdf: anchor.pdf,
     })),
@@ -128,6 +153,7 @@ function normalizeRenderedDocument(doc: BackendRenderedDocument): RenderedDocume
   return {
     pdfPath: doc.pdf_path,
     sourceMap: normalizeSourceMap(doc.source_map),
+    previewMode: doc.preview_mode ?? false,
   };
 }
 
@@ -135,7 +161,8 @@ async function invokeRenderTypst(args: RenderArgs): Promise<RenderedDocument> {
   const result: BackendRenderedDocument = await invoke('render_typst', {
     content: args.content,
     format: args.format,
-    currentFile: args.currentFile
+    currentFile: args.currentFile,
+    previewMode: args.previewMode ?? false,
   });
   return normalizeRenderedDocument(result);
 }
@@ -181,8 +208,14 @@ async function processRenderQueue(): Promise<void> {
   }
 }
 
-export function renderTypst(content: string, format: string, currentFile?: string | null): Promise<RenderedDocument> {
-  const args: RenderArgs = { content, format, currentFile: currentFile || undefined };
+// previewMode skips citations and the bibliography for faster live renders
+export function renderTypst(
+  content: string,
+  format: string,
+  currentFile?: string | null,
+  previewMode?: boolean
+): Promise<RenderedDocument> {
+  const args: RenderArgs = { content, format, currentFile: currentFile || undefined, previewMode };
 
   // Update pending with latest content (last one wins)
   renderQueue.pending = args;
@@ -244,6 +277,18 @@ interface BackendPreferences {
   bibliography_style?: string;
   bibliography_title?: string;
   bibliography_show_all?: boolean;
+  link_citations?: boolean;
+  citation_backrefs?: boolean;
+  multi_citation_mode?: string;
+  heading_label_placement?: string;
+  anchor_syntax?: string;
+  rule_anchors?: string;
+  raw_html?: string;
+  empty_headings?: string;
+  explicit_footnotes?: boolean;
+  latex_cross_refs?: boolean;
+  max_image_width?: string | null;
+  assets_dir_name?: string;
 }
 
 export async function getPreferences(): Promise<Preferences> {
@@ -285,6 +330,18 @@ export async function getPreferences(): Promise<Preferences> {
     bibliography_style: raw.bibliography_style ?? 'ieee',
     bibliography_title: raw.bibliography_title ?? '',
     bibliography_show_all: raw.bibliography_show_all ?? false,
+    link_citations: raw.link_citations ?? false,
+    citation_backrefs: raw.citation_backrefs ?? false,
+    multi_citation_mode: (raw.multi_citation_mode as Preferences['multi_citation_mode']) ?? 'separate_calls',
+    heading_label_placement: (raw.heading_label_placement as Preferences['heading_label_placement']) ?? 'end_of_line',
+    anchor_syntax: (raw.anchor_syntax as Preferences['anchor_syntax']) ?? 'raw_typst_comment',
+    rule_anchors: (raw.rule_anchors as Preferences['rule_anchors']) ?? 'all',
+    raw_html: (raw.raw_html as Preferences['raw_html']) ?? 'pass_through',
+    empty_headings: (raw.empty_headings as Preferences['empty_headings']) ?? 'skip',
+    explicit_footnotes: raw.explicit_footnotes ?? false,
+    latex_cross_refs: raw.latex_cross_refs ?? false,
+    max_image_width: raw.max_image_width ?? undefined,
+    assets_dir_name: raw.assets_dir_name ?? 'assets',
   };
 }
 
@@ -328,6 +385,18 @@ export async function setPreferences(preferences: Preferences): Promise<void> {
     bibliography_style: preferences.bibliography_style,
     bibliography_title: preferences.bibliography_title,
     bibliography_show_all: preferences.bibliography_show_all,
+    link_citations: preferences.link_citations,
+    citation_backrefs: preferences.citation_backrefs,
+    multi_citation_mode: preferences.multi_citation_mode,
+    heading_label_placement: preferences.heading_label_placement,
+    anchor_syntax: preferences.anchor_syntax,
+    rule_anchors: preferences.rule_anchors,
+    raw_html: preferences.raw_html,
+    empty_headings: preferences.empty_headings,
+    explicit_footnotes: preferences.explicit_footnotes,
+    latex_cross_refs: preferences.latex_cross_refs,
+    max_image_width: preferences.max_image_width || null,
+    assets_dir_name: preferences.assets_dir_name,
   };
   await invoke('set_preferences', { preferences: outbound });
 }
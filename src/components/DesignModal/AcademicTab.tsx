@@ -87,6 +87,30 @@ const AcademicTab: React.FC<AcademicTabProps> = ({
             <div className="helper-text" style={{ marginTop: '-8px', marginLeft: '28px' }}>
               Include all bibliography entries, not just cited works
             </div>
+
+            <label className="checkbox-label">
+              <input
+                type="checkbox"
+                checked={local.link_citations || false}
+                onChange={e => mutate({ link_citations: e.target.checked })}
+              />
+              <span>Underline Citation Links</span>
+            </label>
+            <div className="helper-text" style={{ marginTop: '-8px', marginLeft: '28px' }}>
+              Make the link from each citation to its bibliography entry visible
+            </div>
+
+            <label className="checkbox-label">
+              <input
+                type="checkbox"
+                checked={local.citation_backrefs || false}
+                onChange={e => mutate({ citation_backrefs: e.target.checked })}
+              />
+              <span>Citation Back-References</span>
+            </label>
+            <div className="helper-text" style={{ marginTop: '-8px', marginLeft: '28px' }}>
+              List the pages citing each source after the document, linked back to the citations
+            </div>
           </>
         )}
 
@@ -72,6 +72,17 @@ export const defaultPreferences: Preferences = {
   bibliography_style: 'ieee',
   bibliography_title: '',
   bibliography_show_all: false,
+  link_citations: false,
+  citation_backrefs: false,
+  multi_citation_mode: 'separate_calls',
+  heading_label_placement: 'end_of_line',
+  anchor_syntax: 'raw_typst_comment',
+  rule_anchors: 'all',
+  raw_html: 'pass_through',
+  empty_headings: 'skip',
+  explicit_footnotes: false,
+  latex_cross_refs: false,
+  assets_dir_name: 'assets',
 };
 
 // Preferences-specific store state
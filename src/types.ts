@@ -55,6 +55,19 @@ export interface Preferences {
   bibliography_style?: string;       // Citation style: "apa", "ieee", "chicago", etc.
   bibliography_title?: string;       // Custom "References" heading (optional)
   bibliography_show_all?: boolean;   // Show all entries or only cited (default: false)
+  link_citations?: boolean;          // Underline citations linking to their entry
+  citation_backrefs?: boolean;       // List citing pages after the document
+  multi_citation_mode?: 'separate_calls' | 'grouped_bracket'; // How [@a; @b] groups are emitted
+  // Preprocessing settings
+  heading_label_placement?: 'end_of_line' | 'next_line';
+  anchor_syntax?: 'raw_typst_comment' | 'metadata';
+  rule_anchors?: 'all' | 'off' | 'section_separators';
+  raw_html?: 'pass_through' | 'strip' | 'escape';
+  empty_headings?: 'skip' | 'fallback_id' | 'error';
+  explicit_footnotes?: boolean;      // Convert [^1] footnotes to Typst footnote calls
+  latex_cross_refs?: boolean;        // Convert \label{x} / \ref{x} to Typst
+  max_image_width?: string;          // Cap local images at this Typst width (e.g. "100%")
+  assets_dir_name?: string;          // Assets subdirectory name (default: "assets")
 }
 
 export interface CompileStatus {
@@ -109,10 +122,13 @@ export interface PdfAnchorPosition {
   page: number;
   x: number;
   y: number;
+  source_line?: number;
 }
 
 export interface SourceAnchor {
   id: string;
+  // Position in document order, strictly increasing
+  seq: number;
   editor: EditorLocation;
   pdf?: PdfAnchorPosition;
 }
@@ -124,11 +140,14 @@ export interface SourceMap {
 export interface BackendRenderedDocument {
   pdf_path: string;
   source_map: SourceMap;
+  preview_mode?: boolean;
 }
 
 export interface RenderedDocument {
   pdfPath: string;
   sourceMap: SourceMap;
+  // Rendered without citations/bibliography for speed; show a draft indicator
+  previewMode: boolean;
 }
 
 export type ToastType = 'success' | 'error' | 'warning' | 'info';